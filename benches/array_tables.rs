@@ -0,0 +1,44 @@
+//! Serializes a large array of tables with identical keys, run with `cargo bench --bench array_tables`.
+
+use std::{hint::black_box, time::Instant};
+
+use toml_pretty::{Map, Options, Value};
+
+const ITERATIONS: u32 = 20;
+
+fn document() -> Map {
+  let entries = (0..50_000)
+    .map(|i| {
+      let mut meta = Map::new();
+      meta.insert(String::from("created"), Value::from(i));
+      meta.insert(String::from("created by"), Value::from("exporter"));
+      let mut entry = Map::new();
+      entry.insert(String::from("id"), Value::from(i));
+      entry.insert(String::from("name"), Value::from(format!("entry {i}")));
+      entry.insert(String::from("display name"), Value::from("Entry"));
+      entry.insert(String::from("meta"), Value::Object(meta));
+      Value::Object(entry)
+    })
+    .collect();
+  let mut map = Map::new();
+  map.insert(String::from("entries"), Value::Array(entries));
+  map
+}
+
+fn bench(name: &str, map: &Map, options: Options<'_>) {
+  let start = Instant::now();
+  for _ in 0..ITERATIONS {
+    black_box(toml_pretty::to_string(black_box(map), options).unwrap());
+  }
+  println!("{name}: {:?} / iter", start.elapsed() / ITERATIONS);
+}
+
+fn main() {
+  let map = document();
+  bench("inline tables", &map, Options::default());
+  bench(
+    "[[array]] tables",
+    &map,
+    Options::default().table_headers(true).array_tables(true),
+  );
+}
@@ -0,0 +1,59 @@
+//! Serializes a string heavy document, run with `cargo bench --bench strings`.
+//! Strings which need no escaping are written straight from the value tree,
+//! escaped ones go through an owned copy first. Both documents have strings of the same length,
+//! so the difference between them is the cost of that copy.
+//! The baseline is cloning the document, which serializing into the (owned) value tree costs too.
+
+use std::{
+  hint::black_box,
+  time::{Duration, Instant},
+};
+
+use toml_pretty::{Map, Options, Value};
+
+const ITERATIONS: u32 = 200;
+
+fn document(escapes: bool) -> Map {
+  let suffix = if escapes {
+    "\"quoted\" \\ path"
+  } else {
+    "'quoted' / path"
+  };
+  let mut map = Map::new();
+  for i in 0..2_000 {
+    let val = format!("value number {i:>5} with a {suffix}");
+    map.insert(format!("key_{i}"), Value::String(val));
+  }
+  let list = (0..2_000)
+    .map(|i| Value::String(format!("element {i:>5} {suffix}")))
+    .collect();
+  map.insert(String::from("list"), Value::Array(list));
+  map
+}
+
+fn bench(name: &str, mut f: impl FnMut()) -> Duration {
+  let start = Instant::now();
+  for _ in 0..ITERATIONS {
+    f();
+  }
+  let elapsed = start.elapsed() / ITERATIONS;
+  println!("{name}: {elapsed:?} / iter");
+  elapsed
+}
+
+fn main() {
+  let (plain, escaped) = (document(false), document(true));
+  bench("baseline (clone)", || {
+    black_box(black_box(&plain).clone());
+  });
+  let borrowed = bench("no escapes", || {
+    black_box(toml_pretty::to_string(black_box(&plain), Options::default()).unwrap());
+  });
+  let owned = bench("escapes", || {
+    black_box(toml_pretty::to_string(black_box(&escaped), Options::default()).unwrap());
+  });
+  println!(
+    "borrowed strings take {:.0}% of the time of escaped ones",
+    100.0 * borrowed.as_secs_f64() / owned.as_secs_f64()
+  );
+}
@@ -0,0 +1,75 @@
+use std::io::{Read, Write};
+
+use anyhow::Context;
+use toml_pretty::{EmptyTable, Options};
+
+const USAGE: &str = "Usage: toml_pretty [--to-json | --yaml] [--headers] [--tab <tab>] < input
+
+Converts json (or json5 / jsonc, keeping comments) on stdin to pretty toml.
+  --to-json   Convert toml to json instead, keeping the key order of the toml.
+  --yaml      Convert yaml to pretty toml, keeping comments.
+  --headers   Put nested tables under [table] headers.
+  --tab       The indentation for multiline arrays. Default is a tab.";
+
+fn main() -> anyhow::Result<()> {
+  let mut to_json = false;
+  let mut yaml = false;
+  let mut headers = false;
+  let mut tab = String::from("\t");
+  let mut args = std::env::args().skip(1);
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--to-json" => to_json = true,
+      "--yaml" => yaml = true,
+      "--headers" => headers = true,
+      "--tab" => tab = args.next().context("--tab requires a value")?,
+      "-h" | "--help" => {
+        println!("{USAGE}");
+        return Ok(());
+      }
+      _ => anyhow::bail!("unknown argument {arg}\n\n{USAGE}"),
+    }
+  }
+
+  let mut input = String::new();
+  std::io::stdin()
+    .read_to_string(&mut input)
+    .context("failed to read stdin")?;
+
+  if to_json && yaml {
+    anyhow::bail!("--to-json and --yaml can't be used together\n\n{USAGE}");
+  }
+  let options = Options::default().tab(&tab).table_headers(headers);
+  let output = if to_json {
+    let map = toml_pretty::from_str(&input)
+      .map_err(with_help)
+      .context("failed to parse toml")?;
+    serde_json::to_string_pretty(&map).context("failed to serialize json")?
+  } else if yaml {
+    // Keep empty mappings, eg. `volumes: { data: {} }`, which often mean "defaults" in yaml configs.
+    let empty_table = if headers {
+      EmptyTable::Header
+    } else {
+      EmptyTable::Inline
+    };
+    toml_pretty::yaml_to_toml(&input, options.empty_table(empty_table))
+      .map_err(with_help)
+      .context("failed to convert to toml")?
+  } else {
+    toml_pretty::json5_to_toml(&input, options)
+      .map_err(with_help)
+      .context("failed to convert to toml")?
+  };
+
+  let mut stdout = std::io::stdout().lock();
+  writeln!(stdout, "{output}").context("failed to write stdout")?;
+  Ok(())
+}
+
+/// Adds the suggestion of the error, if any, to its message.
+fn with_help(e: toml_pretty::Error) -> anyhow::Error {
+  match e.help() {
+    Some(help) => anyhow::anyhow!("{e}\n  help: {help}"),
+    None => e.into(),
+  }
+}
@@ -0,0 +1,315 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitStr};
+
+/// Derives `toml_pretty::Deprecated` from `#[toml_pretty(deprecated = "use new_name instead")]`
+/// field attributes. `#[toml_pretty(nested)]` includes the deprecated fields of the field's type
+/// (which must implement `Deprecated`) beneath it. Field names follow `#[serde(rename)]`,
+/// `#[serde(rename_all)]`, `#[serde(skip)]` and `#[serde(flatten)]`.
+#[proc_macro_derive(Deprecated, attributes(toml_pretty))]
+pub fn derive_deprecated(input: TokenStream) -> TokenStream {
+  let input = parse_macro_input!(input as DeriveInput);
+  derive(input, Derive::Deprecated)
+    .unwrap_or_else(syn::Error::into_compile_error)
+    .into()
+}
+
+/// Derives `toml_pretty::Secrets` from `#[toml_pretty(secret)]` field attributes.
+/// `#[toml_pretty(nested)]` includes the secrets of the field's type (which must implement `Secrets`)
+/// beneath it. Field names follow serde's attributes like for `Deprecated`.
+#[proc_macro_derive(Secrets, attributes(toml_pretty))]
+pub fn derive_secrets(input: TokenStream) -> TokenStream {
+  let input = parse_macro_input!(input as DeriveInput);
+  derive(input, Derive::Secrets)
+    .unwrap_or_else(syn::Error::into_compile_error)
+    .into()
+}
+
+/// Derives `toml_pretty::Examples` from `#[toml_pretty(example = "\"localhost\"")]` field attributes,
+/// with the example as toml value text. `#[toml_pretty(nested)]` includes the examples of the field's type
+/// (which must implement `Examples`) beneath it. Field names follow serde's attributes like for `Deprecated`.
+#[proc_macro_derive(Examples, attributes(toml_pretty))]
+pub fn derive_examples(input: TokenStream) -> TokenStream {
+  let input = parse_macro_input!(input as DeriveInput);
+  derive(input, Derive::Examples)
+    .unwrap_or_else(syn::Error::into_compile_error)
+    .into()
+}
+
+#[derive(Clone, Copy)]
+enum Derive {
+  Deprecated,
+  Secrets,
+  Examples,
+}
+
+impl Derive {
+  fn name(self) -> &'static str {
+    match self {
+      Derive::Deprecated => "Deprecated",
+      Derive::Secrets => "Secrets",
+      Derive::Examples => "Examples",
+    }
+  }
+}
+
+/// A serialized field, with its `toml_pretty` attributes.
+struct Field<'a> {
+  /// The key, quoted if needed.
+  key: String,
+  ty: &'a syn::Type,
+  flatten: bool,
+  deprecated: Option<String>,
+  example: Option<String>,
+  secret: bool,
+  nested: bool,
+}
+
+fn fields(input: &DeriveInput, derive: Derive) -> syn::Result<Vec<Field<'_>>> {
+  let Data::Struct(data) = &input.data else {
+    return Err(syn::Error::new_spanned(
+      &input.ident,
+      format!("{} can only be derived for structs", derive.name()),
+    ));
+  };
+  let Fields::Named(fields) = &data.fields else {
+    return Err(syn::Error::new_spanned(
+      &input.ident,
+      format!(
+        "{} can only be derived for structs with named fields",
+        derive.name()
+      ),
+    ));
+  };
+  let mut rename_all = None;
+  for attr in input
+    .attrs
+    .iter()
+    .filter(|attr| attr.path().is_ident("serde"))
+  {
+    attr.parse_nested_meta(|meta| {
+      if meta.path.is_ident("rename_all") {
+        rename_all = Some(meta.value()?.parse::<LitStr>()?.value());
+      } else if meta.input.peek(syn::Token![=]) {
+        meta.value()?.parse::<syn::Expr>()?;
+      } else if meta.input.peek(syn::token::Paren) {
+        meta.input.parse::<proc_macro2::Group>()?;
+      }
+      Ok(())
+    })?;
+  }
+
+  let mut res = Vec::new();
+  for field in &fields.named {
+    let Some(ident) = &field.ident else {
+      continue;
+    };
+    let mut name = None;
+    let mut skip = false;
+    let mut flatten = false;
+    for attr in field
+      .attrs
+      .iter()
+      .filter(|attr| attr.path().is_ident("serde"))
+    {
+      attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("rename") {
+          name = Some(meta.value()?.parse::<LitStr>()?.value());
+        } else if meta.path.is_ident("skip") || meta.path.is_ident("skip_serializing") {
+          skip = true;
+        } else if meta.path.is_ident("flatten") {
+          flatten = true;
+        } else if meta.input.peek(syn::Token![=]) {
+          meta.value()?.parse::<syn::Expr>()?;
+        } else if meta.input.peek(syn::token::Paren) {
+          meta.input.parse::<proc_macro2::Group>()?;
+        }
+        Ok(())
+      })?;
+    }
+    let mut deprecated = None;
+    let mut example = None;
+    let mut secret = false;
+    let mut nested = false;
+    for attr in field
+      .attrs
+      .iter()
+      .filter(|attr| attr.path().is_ident("toml_pretty"))
+    {
+      attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("deprecated") {
+          deprecated = Some(meta.value()?.parse::<LitStr>()?.value());
+          Ok(())
+        } else if meta.path.is_ident("example") {
+          example = Some(meta.value()?.parse::<LitStr>()?.value());
+          Ok(())
+        } else if meta.path.is_ident("secret") {
+          secret = true;
+          Ok(())
+        } else if meta.path.is_ident("nested") {
+          nested = true;
+          Ok(())
+        } else {
+          Err(
+            meta
+              .error("expected `deprecated = \"...\"`, `example = \"...\"`, `secret` or `nested`"),
+          )
+        }
+      })?;
+    }
+    if skip {
+      continue;
+    }
+    let name = name.unwrap_or_else(|| {
+      let name = ident.to_string();
+      let name = name.strip_prefix("r#").unwrap_or(&name);
+      match &rename_all {
+        Some(case) => rename(name, case),
+        None => name.to_string(),
+      }
+    });
+    res.push(Field {
+      key: quote_key(&name),
+      ty: &field.ty,
+      flatten,
+      deprecated,
+      example,
+      secret,
+      nested,
+    });
+  }
+  Ok(res)
+}
+
+fn derive(input: DeriveInput, derive: Derive) -> syn::Result<TokenStream2> {
+  let mut entries = Vec::new();
+  for field in fields(&input, derive)? {
+    let Field { key, ty, .. } = &field;
+    let prefix = if field.flatten {
+      quote! { path }
+    } else {
+      quote! { ::std::format!("{}.{}", #key, path) }
+    };
+    match derive {
+      Derive::Deprecated => {
+        if let Some(message) = &field.deprecated {
+          entries.push(quote! {
+            comments.insert(::std::string::String::from(#key), ::std::string::String::from(#message));
+          });
+        }
+        if field.nested {
+          entries.push(quote! {
+            for (path, message) in <#ty as ::toml_pretty::Deprecated>::deprecated() {
+              comments.insert(#prefix, message);
+            }
+          });
+        }
+      }
+      Derive::Secrets => {
+        if field.secret {
+          entries.push(quote! {
+            secrets.push(::std::string::String::from(#key));
+          });
+        }
+        if field.nested {
+          entries.push(quote! {
+            for path in <#ty as ::toml_pretty::Secrets>::secrets() {
+              secrets.push(#prefix);
+            }
+          });
+        }
+      }
+      Derive::Examples => {
+        if let Some(example) = &field.example {
+          entries.push(quote! {
+            examples.push((::std::string::String::from(#key), ::std::string::String::from(#example)));
+          });
+        }
+        if field.nested {
+          entries.push(quote! {
+            for (path, example) in <#ty as ::toml_pretty::Examples>::examples() {
+              examples.push((#prefix, example));
+            }
+          });
+        }
+      }
+    }
+  }
+
+  let ident = &input.ident;
+  let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+  Ok(match derive {
+    Derive::Deprecated => quote! {
+      impl #impl_generics ::toml_pretty::Deprecated for #ident #ty_generics #where_clause {
+        fn deprecated() -> ::toml_pretty::CommentMap {
+          let mut comments = ::toml_pretty::CommentMap::new();
+          #(#entries)*
+          comments
+        }
+      }
+    },
+    Derive::Secrets => quote! {
+      impl #impl_generics ::toml_pretty::Secrets for #ident #ty_generics #where_clause {
+        fn secrets() -> ::std::vec::Vec<::std::string::String> {
+          let mut secrets = ::std::vec::Vec::new();
+          #(#entries)*
+          secrets
+        }
+      }
+    },
+    Derive::Examples => quote! {
+      impl #impl_generics ::toml_pretty::Examples for #ident #ty_generics #where_clause {
+        fn examples() -> ::std::vec::Vec<(::std::string::String, ::std::string::String)> {
+          let mut examples = ::std::vec::Vec::new();
+          #(#entries)*
+          examples
+        }
+      }
+    },
+  })
+}
+
+/// Applies a `#[serde(rename_all)]` case to a snake_case field name.
+fn rename(name: &str, case: &str) -> String {
+  let capitalize = |word: &str| {
+    let mut chars = word.chars();
+    chars
+      .next()
+      .map(|c| c.to_ascii_uppercase().to_string() + chars.as_str())
+      .unwrap_or_default()
+  };
+  let words = name.split('_');
+  match case {
+    "lowercase" => name.to_ascii_lowercase(),
+    "UPPERCASE" => name.to_ascii_uppercase(),
+    "PascalCase" => words.map(capitalize).collect(),
+    "camelCase" => words
+      .enumerate()
+      .map(|(i, word)| {
+        if i == 0 {
+          word.to_string()
+        } else {
+          capitalize(word)
+        }
+      })
+      .collect(),
+    "SCREAMING_SNAKE_CASE" => name.to_ascii_uppercase(),
+    "kebab-case" => name.replace('_', "-"),
+    "SCREAMING-KEBAB-CASE" => name.replace('_', "-").to_ascii_uppercase(),
+    _ => name.to_string(),
+  }
+}
+
+/// Quotes the key like toml_pretty does, unless it is a valid bare key.
+fn quote_key(key: &str) -> String {
+  if !key.is_empty()
+    && key
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+  {
+    key.to_string()
+  } else {
+    format!("\"{}\"", key.replace('\\', "\\\\").replace('"', "\\\""))
+  }
+}
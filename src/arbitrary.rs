@@ -0,0 +1,164 @@
+use crate::{Map, Number, Value};
+
+/// Generates random values for property tests / fuzzing of toml generating pipelines,
+/// constrained to what the crate can write as toml: tables at the root, no nulls,
+/// no empty tables (which [EmptyTable](crate::EmptyTable) may skip), finite floats
+/// and arrays nested at most two deep.
+/// The same seed always gives the same values. Pair with [check_roundtrip](crate::check_roundtrip).
+#[derive(Debug, Clone)]
+pub struct Generator {
+  state: u64,
+  /// How deeply tables / arrays may nest. Default: `4`.
+  pub max_depth: usize,
+  /// The most entries of a table / elements of an array, and chars of a string. Default: `6`.
+  pub max_len: usize,
+}
+
+impl Generator {
+  pub fn new(seed: u64) -> Generator {
+    Generator {
+      // Zero would stay zero.
+      state: seed ^ 0x9e37_79b9_7f4a_7c15,
+      max_depth: 4,
+      max_len: 6,
+    }
+  }
+
+  /// Seeds the generator from fuzzer input.
+  pub fn from_bytes(data: &[u8]) -> Generator {
+    let seed = data.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+      (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    Generator::new(seed)
+  }
+
+  /// A random root table.
+  pub fn table(&mut self) -> Map {
+    self.map(self.max_depth, 0)
+  }
+
+  /// A random value, which may be a table or array nested up to `max_depth`.
+  pub fn value(&mut self) -> Value {
+    self.value_at(self.max_depth, 0)
+  }
+
+  /// With the number of `arrays` the value is nested in.
+  fn value_at(&mut self, depth: usize, arrays: usize) -> Value {
+    let kinds = match (depth, arrays) {
+      (0, _) => 5,
+      (_, 2) => 6,
+      _ => 8,
+    };
+    match self.below(kinds) {
+      0 => Value::Bool(self.below(2) == 0),
+      1 => Value::Number(Number::from(self.next() as i64)),
+      2 => Value::Number(Number::from(self.below(100) as i64 - 50)),
+      3 => {
+        let float = (self.next() as i64 as f64) / (1u64 << self.below(60)) as f64;
+        Value::Number(Number::from_f64(float).unwrap_or(Number::from(0)))
+      }
+      4 => Value::String(self.string()),
+      5 => Value::Object(self.map(depth - 1, arrays)),
+      _ => {
+        let len = self.below(self.max_len + 1);
+        // Arrays of only tables are written as arrays of tables, check them more often.
+        let tables = self.below(2) == 0;
+        Value::Array(
+          (0..len)
+            .map(|_| {
+              if tables {
+                Value::Object(self.map(depth - 1, arrays + 1))
+              } else {
+                self.value_at(depth - 1, arrays + 1)
+              }
+            })
+            .collect(),
+        )
+      }
+    }
+  }
+
+  fn map(&mut self, depth: usize, arrays: usize) -> Map {
+    let len = 1 + self.below(self.max_len);
+    let mut map = Map::new();
+    while map.len() < len {
+      let key = self.key();
+      let val = self.value_at(depth, arrays);
+      map.insert(key, val);
+    }
+    map
+  }
+
+  fn key(&mut self) -> String {
+    // Mostly bare keys, sometimes ones that need quoting.
+    if self.below(4) == 0 {
+      self.string()
+    } else {
+      let len = 1 + self.below(self.max_len);
+      (0..len).map(|_| self.pick("abcxyz019_-")).collect()
+    }
+  }
+
+  fn string(&mut self) -> String {
+    let len = self.below(self.max_len + 1);
+    (0..len)
+      .map(|_| self.pick("ab z.'\"\\\t\n\r\u{0}\u{7f}#=[]{}é€😀"))
+      .collect()
+  }
+
+  fn pick(&mut self, chars: &str) -> char {
+    let count = chars.chars().count();
+    chars.chars().nth(self.below(count)).unwrap_or('a')
+  }
+
+  /// A random number in `0..n`.
+  fn below(&mut self, n: usize) -> usize {
+    (self.next() % n.max(1) as u64) as usize
+  }
+
+  /// xorshift64*
+  fn next(&mut self) -> u64 {
+    self.state ^= self.state >> 12;
+    self.state ^= self.state << 25;
+    self.state ^= self.state >> 27;
+    self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{check_roundtrip, Options};
+
+  #[test]
+  fn deterministic() {
+    assert_eq!(Generator::new(7).table(), Generator::new(7).table());
+    assert_ne!(Generator::new(7).table(), Generator::new(8).table());
+  }
+
+  #[test]
+  fn roundtrips() {
+    for seed in 0..500 {
+      let table = Generator::new(seed).table();
+      if let Err(e) = check_roundtrip(&table, Options::default()) {
+        panic!("seed {seed}: {e}");
+      }
+    }
+  }
+
+  #[test]
+  fn roundtrips_with_headers() {
+    let options = Options::default()
+      .table_headers(true)
+      .header_dotted_depth(2)
+      .dotted_depth(1)
+      .array_tables(true)
+      .super_tables(true);
+    for seed in 0..500 {
+      let table = Generator::new(seed).table();
+      if let Err(e) = check_roundtrip(&table, options) {
+        panic!("seed {seed}: {e}");
+      }
+    }
+  }
+}
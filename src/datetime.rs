@@ -0,0 +1,154 @@
+use crate::DatetimeOffset;
+
+/// Rewrites an RFC 3339 offset datetime string (eg. `1979-05-27T07:32:00-08:00`) to the
+/// offset chosen by [Options::datetime_offset](crate::Options::datetime_offset).
+/// None if the string isn't an offset datetime, or is already written with that offset.
+pub(crate) fn normalize_offset(val: &str, target: DatetimeOffset) -> Option<String> {
+  let target = match target {
+    DatetimeOffset::Preserve => return None,
+    DatetimeOffset::Utc => None,
+    DatetimeOffset::Fixed(minutes) => Some(i64::from(minutes)),
+  };
+  let datetime = Datetime::parse(val)?;
+  let minutes = days_from_civil(datetime.year, datetime.month, datetime.day) * 1440
+    + datetime.hour * 60
+    + datetime.minute
+    - datetime.offset
+    + target.unwrap_or_default();
+  let (year, month, day) = civil_from_days(minutes.div_euclid(1440));
+  if !(0..=9999).contains(&year) {
+    return None;
+  }
+  let minutes = minutes.rem_euclid(1440);
+  let offset = match target {
+    None => String::from("Z"),
+    Some(target) => {
+      let sign = if target < 0 { '-' } else { '+' };
+      format!("{sign}{:02}:{:02}", target.abs() / 60, target.abs() % 60)
+    }
+  };
+  let res = format!(
+    "{year:04}-{month:02}-{day:02}{}{:02}:{:02}{}{offset}",
+    datetime.separator,
+    minutes / 60,
+    minutes % 60,
+    datetime.seconds,
+  );
+  (res != val).then_some(res)
+}
+
+struct Datetime<'a> {
+  year: i64,
+  month: i64,
+  day: i64,
+  /// `T`, `t` or a space, kept as written.
+  separator: char,
+  hour: i64,
+  minute: i64,
+  /// The seconds with any fraction, eg. `:00.999`, kept as written.
+  seconds: &'a str,
+  /// In minutes east of UTC.
+  offset: i64,
+}
+
+impl<'a> Datetime<'a> {
+  fn parse(val: &'a str) -> Option<Datetime<'a>> {
+    let bytes = val.as_bytes();
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+      let digits = val.get(range)?;
+      if !digits.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+      }
+      digits.parse().ok()
+    };
+    let at = |i: usize, expected: &[u8]| bytes.get(i).is_some_and(|c| expected.contains(c));
+    if !(at(4, b"-") && at(7, b"-") && at(10, b"Tt ") && at(13, b":") && at(16, b":")) {
+      return None;
+    }
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(1..=12).contains(&month)
+      || !(1..=days_in_month(year, month)).contains(&day)
+      || hour > 23
+      || minute > 59
+      // 60 for leap seconds.
+      || second > 60
+    {
+      return None;
+    }
+    let mut end = 19;
+    if at(end, b".") {
+      end += 1;
+      let digits = bytes[end..]
+        .iter()
+        .take_while(|c| c.is_ascii_digit())
+        .count();
+      if digits == 0 {
+        return None;
+      }
+      end += digits;
+    }
+    let seconds = &val[16..end];
+    let offset = match &bytes[end..] {
+      [b'Z' | b'z'] => 0,
+      [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+        let (hours, minutes) = (number(end + 1..end + 3)?, number(end + 4..end + 6)?);
+        if hours > 23 || minutes > 59 {
+          return None;
+        }
+        let offset = hours * 60 + minutes;
+        if *sign == b'-' {
+          -offset
+        } else {
+          offset
+        }
+      }
+      _ => return None,
+    };
+    Some(Datetime {
+      year,
+      month,
+      day,
+      separator: char::from(bytes[10]),
+      hour,
+      minute,
+      seconds,
+      offset,
+    })
+  }
+}
+
+pub(crate) fn days_in_month(year: i64, month: i64) -> i64 {
+  match month {
+    2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+    2 => 28,
+    4 | 6 | 9 | 11 => 30,
+    _ => 31,
+  }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+/// See http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+  let year = if month <= 2 { year - 1 } else { year };
+  let era = year.div_euclid(400);
+  let year_of_era = year - era * 400;
+  let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+  let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+  era * 146097 + day_of_era - 719468
+}
+
+/// The inverse of [days_from_civil].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+  let days = days + 719468;
+  let era = days.div_euclid(146097);
+  let day_of_era = days - era * 146097;
+  let year_of_era =
+    (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let mp = (5 * day_of_year + 2) / 153;
+  let day = day_of_year - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = year_of_era + era * 400 + i64::from(month <= 2);
+  (year, month, day)
+}
@@ -0,0 +1,359 @@
+use serde::Serialize;
+
+use crate::{
+  append_comment, flatten_map, format_value, parse::parse, render, to_map, CommentMap, Map,
+  Options, Result, Value,
+};
+
+/// A flattened document, keyed by dotted path.
+/// Can be post-processed (eg. dropping experimental keys) before rendering.
+#[derive(Debug, Clone, Default)]
+pub struct Document {
+  map: Map,
+}
+
+impl Document {
+  pub fn new<T: Serialize + ?Sized>(value: &T) -> Result<Document> {
+    Ok(Document {
+      map: flatten_map(to_map(value, Options::default())?),
+    })
+  }
+
+  /// Parse toml text into a document.
+  pub fn parse(input: &str) -> Result<Document> {
+    Ok(Document {
+      map: flatten_map(parse(input)?),
+    })
+  }
+
+  /// Remove the value at `path`, along with everything nested beneath it.
+  /// Returns whether anything was removed.
+  pub fn remove(&mut self, path: &str) -> bool {
+    let len = self.map.len();
+    self.map = std::mem::take(&mut self.map)
+      .into_iter()
+      .filter(|(key, _)| !is_under(key, path))
+      .collect();
+    self.map.len() != len
+  }
+
+  /// Move the value at `old` (along with everything nested beneath it) to `new`,
+  /// keeping its position in the document. Returns whether anything was renamed.
+  /// Nothing is renamed when `new` (or a value containing it, eg. `b = 1` for `b.c`)
+  /// already exists outside of `old`, as that would replace it or produce invalid toml.
+  pub fn rename(&mut self, old: &str, new: &str) -> bool {
+    let conflict = self
+      .map
+      .keys()
+      .filter(|key| !is_under(key, old))
+      .any(|key| is_under(key, new) || is_under(new, key));
+    if conflict {
+      return false;
+    }
+    let mut renamed = false;
+    self.map = std::mem::take(&mut self.map)
+      .into_iter()
+      .map(|(key, val)| {
+        if is_under(&key, old) {
+          renamed = true;
+          (format!("{new}{}", &key[old.len()..]), val)
+        } else {
+          (key, val)
+        }
+      })
+      .collect();
+    renamed
+  }
+
+  /// Visit every flattened value in document order, with its dotted path.
+  /// The value may be modified in place, eg. for unit conversion.
+  pub fn walk(&mut self, mut visit: impl FnMut(&str, &mut Value)) {
+    for (path, val) in self.map.iter_mut() {
+      visit(path, val);
+    }
+  }
+
+  /// Sort keys by path, including the keys of inline tables inside arrays.
+  pub fn sort_keys(&mut self) {
+    let mut entries = std::mem::take(&mut self.map)
+      .into_iter()
+      .collect::<Vec<_>>();
+    entries.sort_by(|(a, _), (b, _)| a.split('.').cmp(b.split('.')));
+    for (_, val) in &mut entries {
+      sort_value(val);
+    }
+    self.map = entries.into_iter().collect();
+  }
+
+  /// Compare against another document, ignoring key order.
+  /// Returns one line per differing key path, empty if the documents are equal.
+  pub fn diff(&self, other: &Document) -> Vec<String> {
+    let mut diff = Vec::new();
+    for (path, val) in &self.map {
+      match other.map.get(path) {
+        Some(other) if other == val => {}
+        Some(other) => diff.push(format!("  {path}: {} != {}", preview(val), preview(other))),
+        None => diff.push(format!("  {path}: {} != (missing)", preview(val))),
+      }
+    }
+    for (path, val) in &other.map {
+      if !self.map.contains_key(path) {
+        diff.push(format!("  {path}: (missing) != {}", preview(val)));
+      }
+    }
+    diff
+  }
+
+  /// Three way merge of `ours` and `theirs`, which were both changed from `base`.
+  /// Keys changed on only one side take that side's value. Keys changed differently
+  /// on both sides are conflicts: they keep our value (or theirs, if we removed the key),
+  /// with a comment block showing both sides, similar to git conflict markers.
+  /// A path made a value on one side while the other side adds keys beneath it
+  /// (eg. `a = 1` against `a.y = 2`) is a conflict as well, which keeps our side
+  /// of everything under the path (or theirs, if we have nothing there).
+  pub fn merge(base: &Document, ours: &Document, theirs: &Document) -> Merge {
+    let mut merge = Merge::default();
+    let keys = ours
+      .map
+      .keys()
+      .chain(theirs.map.keys().filter(|key| !ours.map.contains_key(*key)));
+    for key in keys {
+      let base_val = base.map.get(key);
+      let our_val = ours.map.get(key);
+      let their_val = theirs.map.get(key);
+      let val = if our_val == their_val || their_val == base_val {
+        our_val
+      } else if our_val == base_val {
+        their_val
+      } else {
+        let side = |val: Option<&Value>| match val {
+          Some(val) => format!("{key} = {}", preview(val)),
+          None => String::from("(removed)"),
+        };
+        let comment = format!(
+          "<<<<<<< ours\n{}\n=======\n{}\n>>>>>>> theirs",
+          side(our_val),
+          side(their_val)
+        );
+        merge.conflicts.insert(key.clone(), comment);
+        our_val.or(their_val)
+      };
+      if let Some(val) = val {
+        merge.document.map.insert(key.clone(), val.clone());
+      }
+    }
+    resolve_structure(&mut merge, ours, theirs);
+    merge
+  }
+
+  /// The flattened (dotted) keys, in document order.
+  pub fn keys(&self) -> impl Iterator<Item = &str> {
+    self.map.keys().map(String::as_str)
+  }
+
+  pub fn to_string(&self, options: Options<'_>) -> Result<String> {
+    render(&self.map, options)
+  }
+}
+
+/// The result of [Document::merge].
+#[derive(Debug, Clone, Default)]
+pub struct Merge {
+  pub document: Document,
+  /// The conflict comment blocks, keyed by path. Empty if the merge was clean.
+  pub conflicts: CommentMap,
+}
+
+impl Merge {
+  pub fn is_clean(&self) -> bool {
+    self.conflicts.is_empty()
+  }
+
+  /// Render the merged document with the conflict blocks as comments,
+  /// after any comments for the same path in `options.comments`.
+  pub fn to_string(&self, options: Options<'_>) -> Result<String> {
+    let mut comments = options.comments.cloned().unwrap_or_default();
+    for (path, conflict) in &self.conflicts {
+      append_comment(&mut comments, path.clone(), conflict);
+    }
+    self.document.to_string(Options {
+      comments: Some(&comments),
+      ..options
+    })
+  }
+}
+
+/// Turns merged paths which are both a value and a table (eg. `a` and `a.y`), which can't be
+/// written as toml, into a single conflict at the value's path.
+fn resolve_structure(merge: &mut Merge, ours: &Document, theirs: &Document) {
+  let map = &merge.document.map;
+  let mut roots = Vec::<String>::new();
+  for key in map.keys() {
+    let clashes = map.keys().any(|other| other != key && is_under(other, key));
+    if clashes && !roots.iter().any(|root| is_under(key, root)) {
+      roots.retain(|root| !is_under(root, key));
+      roots.push(key.clone());
+    }
+  }
+  for root in roots {
+    let subtree = |document: &Document| {
+      document
+        .map
+        .iter()
+        .filter(|(key, _)| is_under(key, &root))
+        .map(|(key, val)| (key.clone(), val.clone()))
+        .collect::<Vec<_>>()
+    };
+    let side = |entries: &[(String, Value)]| {
+      if entries.is_empty() {
+        return String::from("(removed)");
+      }
+      entries
+        .iter()
+        .map(|(key, val)| format!("{key} = {}", preview(val)))
+        .collect::<Vec<_>>()
+        .join("\n")
+    };
+    let (our_entries, their_entries) = (subtree(ours), subtree(theirs));
+    let kept = if our_entries.is_empty() {
+      &their_entries
+    } else {
+      &our_entries
+    };
+    // The kept side's entries go where the first entry under the path was.
+    let mut entries = Vec::with_capacity(merge.document.map.len());
+    let mut inserted = false;
+    for (key, val) in std::mem::take(&mut merge.document.map) {
+      if !is_under(&key, &root) {
+        entries.push((key, val));
+      } else if !inserted {
+        inserted = true;
+        entries.extend(kept.iter().cloned());
+      }
+    }
+    merge.document.map = entries.into_iter().collect();
+    merge.conflicts = std::mem::take(&mut merge.conflicts)
+      .into_iter()
+      .filter(|(key, _)| !is_under(key, &root))
+      .collect();
+    let comment = format!(
+      "<<<<<<< ours\n{}\n=======\n{}\n>>>>>>> theirs",
+      side(&our_entries),
+      side(&their_entries)
+    );
+    merge.conflicts.insert(root, comment);
+  }
+}
+
+fn preview(val: &Value) -> String {
+  format_value(val, Options::default().inline_array(true)).unwrap_or_else(|_| format!("{val:?}"))
+}
+
+fn sort_value(val: &mut Value) {
+  match val {
+    Value::Array(vals) => vals.iter_mut().for_each(sort_value),
+    Value::Object(map) => {
+      let mut entries = std::mem::take(map).into_iter().collect::<Vec<_>>();
+      entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+      for (_, val) in &mut entries {
+        sort_value(val);
+      }
+      *map = entries.into_iter().collect();
+    }
+    _ => {}
+  }
+}
+
+/// Whether `key` is `path` itself or nested beneath it.
+pub(crate) fn is_under(key: &str, path: &str) -> bool {
+  match key.strip_prefix(path) {
+    Some(rest) => rest.is_empty() || rest.starts_with('.'),
+    None => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn keys(document: &Document) -> Vec<&str> {
+    document.keys().collect()
+  }
+
+  #[test]
+  fn merge_values() {
+    let base = Document::parse("a = 1\nb = 2\nc = 3").unwrap();
+    let ours = Document::parse("a = 10\nb = 2\nc = 30").unwrap();
+    let theirs = Document::parse("a = 1\nb = 20\nc = 31\nd = 4").unwrap();
+    let merge = Document::merge(&base, &ours, &theirs);
+    assert_eq!(keys(&merge.document), ["a", "b", "c", "d"]);
+    assert_eq!(merge.document.map["b"], Value::from(20));
+    assert_eq!(merge.document.map["c"], Value::from(30));
+    assert_eq!(merge.conflicts.keys().collect::<Vec<_>>(), ["c"]);
+  }
+
+  #[test]
+  fn merge_structure_conflicts() {
+    let base = Document::parse("a.x = 1").unwrap();
+    let ours = Document::parse("a = 1").unwrap();
+    let theirs = Document::parse("a.x = 1\na.y = 2").unwrap();
+    let merge = Document::merge(&base, &ours, &theirs);
+    assert!(!merge.is_clean());
+    assert_eq!(
+      merge.conflicts.get("a").map(String::as_str),
+      Some("<<<<<<< ours\na = 1\n=======\na.x = 1\na.y = 2\n>>>>>>> theirs")
+    );
+    let res = merge.to_string(Options::default()).unwrap();
+    assert_eq!(keys(&Document::parse(&res).unwrap()), ["a"]);
+    // The other way around keeps our table.
+    let merge = Document::merge(&base, &theirs, &ours);
+    assert_eq!(keys(&merge.document), ["a.x", "a.y"]);
+    let res = merge.to_string(Options::default()).unwrap();
+    assert_eq!(keys(&Document::parse(&res).unwrap()), ["a.x", "a.y"]);
+  }
+
+  #[test]
+  fn rename_keeps_position() {
+    let mut document = Document::parse("a = 1\nb.x = 2\nb.y = 3\nc = 4").unwrap();
+    assert!(document.rename("b", "d.e"));
+    assert_eq!(keys(&document), ["a", "d.e.x", "d.e.y", "c"]);
+  }
+
+  #[test]
+  fn rename_missing() {
+    let mut document = Document::parse("a = 1").unwrap();
+    assert!(!document.rename("b", "c"));
+    assert_eq!(keys(&document), ["a"]);
+  }
+
+  #[test]
+  fn rename_onto_existing_key() {
+    let mut document = Document::parse("a = 1\nb = 2").unwrap();
+    assert!(!document.rename("a", "b"));
+    assert_eq!(keys(&document), ["a", "b"]);
+    assert_eq!(document.map.get("b"), Some(&Value::Number(2i64.into())));
+  }
+
+  #[test]
+  fn rename_under_scalar() {
+    let mut document = Document::parse("a = 1\nb.c = 2").unwrap();
+    assert!(!document.rename("a", "b.c.d"));
+    assert_eq!(keys(&document), ["a", "b.c"]);
+  }
+
+  #[test]
+  fn rename_over_table() {
+    let mut document = Document::parse("a = 1\nb.c = 2").unwrap();
+    assert!(!document.rename("a", "b"));
+    // Into an existing table is fine, as long as the key itself is new.
+    assert!(document.rename("a", "b.d"));
+    assert_eq!(keys(&document), ["b.d", "b.c"]);
+  }
+
+  #[test]
+  fn rename_beneath_itself() {
+    let mut document = Document::parse("a.x = 1").unwrap();
+    assert!(document.rename("a", "a.b"));
+    assert_eq!(keys(&document), ["a.b.x"]);
+  }
+}
@@ -0,0 +1,60 @@
+use std::panic::RefUnwindSafe;
+
+/// Writes the pieces of the output (keys, values, brackets and headers), for custom rendering backends,
+/// eg. colorized or instrumented output, or alternative quoting. See [Options::formatter](crate::Options::formatter).
+/// The flattening, ordering, skipping and layout decisions are made as usual,
+/// and each method is given the text the crate would write, which the defaults write unchanged.
+/// Line widths (eg. [Options::max_line_width](crate::Options::max_line_width)) count everything written,
+/// including any text a formatter adds. Comments and whitespace are written as usual.
+/// Formatters are shared through [Options](crate::Options), which stay `Send`, `Sync` and unwind safe,
+/// so state a formatter keeps (eg. counters) goes in atomics or a `Mutex`.
+pub trait Formatter: Sync + RefUnwindSafe {
+  /// Writes a key, dotted and quoted as needed, eg. `server."host name"`.
+  fn write_key(&self, out: &mut String, key: &str) {
+    out.push_str(key);
+  }
+
+  /// Writes a string value, given as is and as the quoted toml string, eg. `"a\tb"` or a `"""` string.
+  fn write_string(&self, out: &mut String, value: &str, quoted: &str) {
+    let _ = value;
+    out.push_str(quoted);
+  }
+
+  /// Writes an integer or float, eg. `1` or `1.5`.
+  fn write_number(&self, out: &mut String, number: &str) {
+    out.push_str(number);
+  }
+
+  fn write_bool(&self, out: &mut String, bool: bool) {
+    out.push_str(if bool { "true" } else { "false" });
+  }
+
+  fn begin_array(&self, out: &mut String) {
+    out.push('[');
+  }
+
+  fn end_array(&self, out: &mut String) {
+    out.push(']');
+  }
+
+  fn begin_inline_table(&self, out: &mut String) {
+    out.push('{');
+  }
+
+  fn end_inline_table(&self, out: &mut String) {
+    out.push('}');
+  }
+
+  /// Writes a `[table]` header, or an `[[array]]` section header.
+  fn write_header(&self, out: &mut String, table: &str, array: bool) {
+    out.push_str(if array { "[[" } else { "[" });
+    out.push_str(table);
+    out.push_str(if array { "]]" } else { "]" });
+  }
+}
+
+/// The crate's own output, the default [Formatter].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultFormatter;
+
+impl Formatter for DefaultFormatter {}
@@ -0,0 +1,438 @@
+use crate::{quote_key, CommentMap, Error, Map, Number, Result, Value};
+
+/// Parse JSON5 (or JSONC, which is a subset) text, along with its comments.
+/// Comments above a key / array element, or after it on the same line,
+/// are keyed by its path like the [CommentMap] passed to [Options::comments](crate::Options::comments).
+/// Comments with nothing after them in their object / array are dropped.
+pub(crate) fn parse(input: &str) -> Result<(Value, CommentMap)> {
+  let mut parser = Parser {
+    input,
+    pos: 0,
+    comments: CommentMap::new(),
+    pending: Vec::new(),
+  };
+  parser.skip_blank()?;
+  let val = parser.parse_value("")?;
+  parser.skip_blank()?;
+  if parser.peek().is_some() {
+    return Err(parser.error("expected end of input"));
+  }
+  Ok((val, parser.comments))
+}
+
+struct Parser<'a> {
+  input: &'a str,
+  pos: usize,
+  comments: CommentMap,
+  /// Comment lines waiting for the next key / element.
+  pending: Vec<String>,
+}
+
+impl Parser<'_> {
+  fn parse_value(&mut self, path: &str) -> Result<Value> {
+    match self.peek() {
+      Some('{') => self.parse_object(path),
+      Some('[') => self.parse_array(path),
+      Some('"' | '\'') => self.parse_string().map(Value::String),
+      Some(c) if c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'I' | 'N') => {
+        self.parse_number()
+      }
+      Some(_) => {
+        let ident = self.parse_identifier();
+        match ident.as_str() {
+          "true" => Ok(Value::Bool(true)),
+          "false" => Ok(Value::Bool(false)),
+          "null" => Ok(Value::Null),
+          "" => Err(self.error("expected value")),
+          _ => Err(self.error(format!("unexpected {ident}"))),
+        }
+      }
+      None => Err(self.error("expected value")),
+    }
+  }
+
+  fn parse_object(&mut self, path: &str) -> Result<Value> {
+    self.pos += 1;
+    let mut map = Map::new();
+    loop {
+      self.skip_blank()?;
+      if self.eat('}') {
+        self.pending.clear();
+        return Ok(Value::Object(map));
+      }
+      let key = match self.peek() {
+        Some('"' | '\'') => self.parse_string()?,
+        _ => self.parse_identifier(),
+      };
+      if key.is_empty() {
+        return Err(self.error("expected key"));
+      }
+      let key_path = if path.is_empty() {
+        quote_key(&key).into_owned()
+      } else {
+        format!("{path}.{}", quote_key(&key))
+      };
+      self.take_pending(&key_path);
+      self.skip_blank()?;
+      if !self.eat(':') {
+        return Err(self.error("expected ':'"));
+      }
+      self.skip_blank()?;
+      let val = self.parse_value(&key_path)?;
+      // Duplicate keys keep their first position, like the other inputs.
+      match map.get_mut(&key) {
+        Some(existing) => *existing = val,
+        None => {
+          map.insert(key, val);
+        }
+      }
+      if self.end_of_item(&key_path, '}')? {
+        self.pending.clear();
+        return Ok(Value::Object(map));
+      }
+    }
+  }
+
+  fn parse_array(&mut self, path: &str) -> Result<Value> {
+    self.pos += 1;
+    let mut vals = Vec::new();
+    loop {
+      self.skip_blank()?;
+      if self.eat(']') {
+        self.pending.clear();
+        return Ok(Value::Array(vals));
+      }
+      let elem_path = format!("{path}[{}]", vals.len());
+      self.take_pending(&elem_path);
+      vals.push(self.parse_value(&elem_path)?);
+      if self.end_of_item(&elem_path, ']')? {
+        self.pending.clear();
+        return Ok(Value::Array(vals));
+      }
+    }
+  }
+
+  /// After a key's value / array element. Picks up a comment on the same line,
+  /// then returns whether the object / array was closed by `close`.
+  fn end_of_item(&mut self, path: &str, close: char) -> Result<bool> {
+    self.skip_inline_whitespace();
+    let comma = self.eat(',');
+    self.skip_inline_whitespace();
+    if self.rest().starts_with("//") || self.rest().starts_with("/*") {
+      let before = self.pending.len();
+      self.skip_comment()?;
+      let trailing = self.pending.split_off(before);
+      if !trailing.is_empty() {
+        let comment = trailing.join("\n");
+        match self.comments.get_mut(path) {
+          Some(existing) => {
+            existing.push('\n');
+            existing.push_str(&comment);
+          }
+          None => {
+            self.comments.insert(path.to_string(), comment);
+          }
+        }
+      }
+    }
+    self.skip_blank()?;
+    if self.eat(close) {
+      return Ok(true);
+    }
+    if comma {
+      Ok(false)
+    } else {
+      Err(self.error(format!("expected ',' or '{close}'")))
+    }
+  }
+
+  fn take_pending(&mut self, path: &str) {
+    if !self.pending.is_empty() {
+      let comment = std::mem::take(&mut self.pending).join("\n");
+      self.comments.insert(path.to_string(), comment);
+    }
+  }
+
+  fn parse_string(&mut self) -> Result<String> {
+    let Some(quote) = self.next_char() else {
+      return Err(self.error("expected string"));
+    };
+    let mut res = String::new();
+    loop {
+      match self.next_char() {
+        None | Some('\n') => return Err(self.error("unterminated string")),
+        Some(c) if c == quote => return Ok(res),
+        Some('\\') => match self.next_char() {
+          Some('n') => res.push('\n'),
+          Some('r') => res.push('\r'),
+          Some('t') => res.push('\t'),
+          Some('b') => res.push('\u{8}'),
+          Some('f') => res.push('\u{c}'),
+          Some('v') => res.push('\u{b}'),
+          Some('0') => res.push('\0'),
+          Some('x') => {
+            let code = self.parse_hex(2)?;
+            res.push(char::from_u32(code).ok_or_else(|| self.error("invalid \\x escape"))?);
+          }
+          Some('u') => res.push(self.parse_unicode_escape()?),
+          // Line continuation
+          Some('\n') => {}
+          Some('\r') => {
+            self.eat('\n');
+          }
+          Some(c) => res.push(c),
+          None => return Err(self.error("unterminated string")),
+        },
+        Some(c) => res.push(c),
+      }
+    }
+  }
+
+  fn parse_unicode_escape(&mut self) -> Result<char> {
+    let high = self.parse_hex(4)?;
+    if (0xD800..0xDC00).contains(&high) && self.rest().starts_with("\\u") {
+      self.pos += 2;
+      let low = self.parse_hex(4)?;
+      let code = 0x10000 + ((high - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+      return char::from_u32(code).ok_or_else(|| self.error("invalid \\u escape"));
+    }
+    char::from_u32(high).ok_or_else(|| self.error("invalid \\u escape"))
+  }
+
+  fn parse_hex(&mut self, len: usize) -> Result<u32> {
+    let hex = self.rest().get(..len).unwrap_or_default();
+    let code = u32::from_str_radix(hex, 16).map_err(|_| self.error("invalid hex escape"))?;
+    self.pos += len;
+    Ok(code)
+  }
+
+  fn parse_number(&mut self) -> Result<Value> {
+    let start = self.pos;
+    while let Some(c) = self.peek() {
+      if c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.') {
+        self.pos += c.len_utf8();
+      } else {
+        break;
+      }
+    }
+    let text = &self.input[start..self.pos];
+    let (negative, unsigned) = match text.strip_prefix('-') {
+      Some(rest) => (true, rest),
+      None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    if unsigned == "Infinity" || unsigned == "NaN" {
+      return Err(self.error(format!("{text} can't be represented")));
+    }
+    if let Some(hex) = unsigned
+      .strip_prefix("0x")
+      .or_else(|| unsigned.strip_prefix("0X"))
+    {
+      if hex.is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(self.error(format!("invalid number {text}")));
+      }
+      let int = i64::from_str_radix(hex, 16).map_err(|_| self.error("invalid hex number"))?;
+      return Ok(Value::from(if negative { -int } else { int }));
+    }
+    if !is_decimal(unsigned) {
+      return Err(self.error(format!("invalid number {text}")));
+    }
+    let number = if !unsigned.contains(['.', 'e', 'E']) {
+      if negative {
+        text.parse::<i64>().ok().map(Number::from)
+      } else {
+        unsigned.parse::<u64>().ok().map(Number::from)
+      }
+    } else {
+      None
+    };
+    match number {
+      Some(number) => Ok(Value::Number(number)),
+      None => {
+        // Rust floats don't accept a trailing '.', eg. `1.`
+        let float = unsigned.replace(".e", ".0e").replace(".E", ".0E");
+        let float = float.strip_suffix('.').unwrap_or(&float);
+        let float = float
+          .parse::<f64>()
+          .map_err(|_| self.error(format!("invalid number {text}")))?;
+        Ok(Value::from(if negative { -float } else { float }))
+      }
+    }
+  }
+
+  fn parse_identifier(&mut self) -> String {
+    let start = self.pos;
+    while let Some(c) = self.peek() {
+      if c.is_alphanumeric() || matches!(c, '_' | '$') {
+        self.pos += c.len_utf8();
+      } else {
+        break;
+      }
+    }
+    self.input[start..self.pos].to_string()
+  }
+
+  /// Skips whitespace and comments, collecting the comments into `pending`.
+  fn skip_blank(&mut self) -> Result<()> {
+    loop {
+      match self.peek() {
+        Some(c) if c.is_whitespace() || c == '\u{feff}' => self.pos += c.len_utf8(),
+        Some('/') => self.skip_comment()?,
+        _ => return Ok(()),
+      }
+    }
+  }
+
+  fn skip_inline_whitespace(&mut self) {
+    while let Some(c @ (' ' | '\t')) = self.peek() {
+      self.pos += c.len_utf8();
+    }
+  }
+
+  fn skip_comment(&mut self) -> Result<()> {
+    if let Some(rest) = self.rest().strip_prefix("//") {
+      let line = comment_line(rest.split('\n').next().unwrap_or_default());
+      self.pos += 2 + rest.find('\n').unwrap_or(rest.len());
+      self.pending.push(line);
+    } else if let Some(rest) = self.rest().strip_prefix("/*") {
+      let Some(end) = rest.find("*/") else {
+        return Err(self.error("unterminated comment"));
+      };
+      let lines = rest[..end]
+        .lines()
+        .map(|line| {
+          let line = line.trim_start();
+          comment_line(line.strip_prefix('*').unwrap_or(line))
+        })
+        .collect::<Vec<_>>();
+      // Drop the blank lines of `/*\n ... \n*/` style blocks.
+      let first = lines
+        .iter()
+        .position(|line| !line.is_empty())
+        .unwrap_or(lines.len());
+      let last = lines
+        .iter()
+        .rposition(|line| !line.is_empty())
+        .map_or(first, |i| i + 1);
+      self
+        .pending
+        .extend_from_slice(&lines[first..last.max(first)]);
+      self.pos += 2 + end + 2;
+    } else {
+      return Err(self.error("unexpected '/'"));
+    }
+    Ok(())
+  }
+
+  fn eat(&mut self, c: char) -> bool {
+    if self.peek() == Some(c) {
+      self.pos += c.len_utf8();
+      true
+    } else {
+      false
+    }
+  }
+
+  fn rest(&self) -> &str {
+    &self.input[self.pos..]
+  }
+
+  fn peek(&self) -> Option<char> {
+    self.rest().chars().next()
+  }
+
+  fn next_char(&mut self) -> Option<char> {
+    let c = self.peek()?;
+    self.pos += c.len_utf8();
+    Some(c)
+  }
+
+  fn error(&self, message: impl Into<String>) -> Error {
+    Error::ParseJson5 {
+      line: self.input[..self.pos].matches('\n').count() + 1,
+      message: message.into(),
+    }
+  }
+}
+
+fn comment_line(line: &str) -> String {
+  let line = line.trim_end_matches('\r');
+  line
+    .strip_prefix(' ')
+    .unwrap_or(line)
+    .trim_end()
+    .to_string()
+}
+
+/// Whether the (unsigned) text is a JSON5 decimal number: no leading zeros (`01`),
+/// though either side of the `.` may be empty (`.5`, `5.`), with an optional exponent.
+fn is_decimal(text: &str) -> bool {
+  let (mantissa, exponent) = match text.split_once(['e', 'E']) {
+    Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+    None => (text, None),
+  };
+  let (int, fraction) = match mantissa.split_once('.') {
+    Some((int, fraction)) => (int, fraction),
+    None => (mantissa, ""),
+  };
+  let digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+  digits(int)
+    && digits(fraction)
+    && !(int.is_empty() && fraction.is_empty())
+    && (int.len() < 2 || !int.starts_with('0'))
+    && exponent.is_none_or(|exponent| {
+      let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+      !exponent.is_empty() && digits(exponent)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn number(text: &str) -> Result<Value> {
+    parse(&format!("{{ a: {text} }}")).map(|(val, _)| val["a"].clone())
+  }
+
+  #[test]
+  fn numbers() {
+    for (text, expected) in [
+      ("0", Value::from(0)),
+      ("-0", Value::from(0)),
+      ("10", Value::from(10)),
+      ("+1", Value::from(1)),
+      ("18446744073709551615", Value::from(u64::MAX)),
+      ("-9223372036854775808", Value::from(i64::MIN)),
+      ("0x1F", Value::from(31)),
+      ("-0x10", Value::from(-16)),
+      ("1.5", Value::from(1.5)),
+      (".5", Value::from(0.5)),
+      ("5.", Value::from(5.0)),
+      ("0.5", Value::from(0.5)),
+      ("1e3", Value::from(1000.0)),
+      ("1.e3", Value::from(1000.0)),
+      ("2E-2", Value::from(0.02)),
+    ] {
+      assert_eq!(number(text).unwrap(), expected, "{text}");
+    }
+    for text in [
+      "01", "-01", "00", "01.5", "00.5", ".", "1e", "1e+", "0x", "0x+1", "inf", "1f", "Infinity",
+      "NaN",
+    ] {
+      assert!(number(text).is_err(), "{text}");
+    }
+  }
+
+  #[test]
+  fn json5_syntax() {
+    let (val, comments) = parse(
+      "// leading\n{\n  unquoted: 'single',\n  \"quoted\": [1, 2,], // trailing\n  nested: { a: null },\n}",
+    )
+    .unwrap();
+    assert_eq!(val["unquoted"].as_str(), Some("single"));
+    assert_eq!(val["quoted"][1].as_u64(), Some(2));
+    assert!(val["nested"]["a"].is_null());
+    assert!(comments.contains_key("quoted"));
+    assert!(parse("{ a: 1 } x").is_err());
+    assert!(parse("{ a: 1").is_err());
+  }
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+use crate::{join_path, segment_dots, Value};
+
+/// A canonical key order for [Ordering::canonical](crate::Ordering::canonical),
+/// eg. a mandated config layout, or the property order of a JSON Schema.
+#[derive(Debug, Clone, Default)]
+pub struct KeyOrder {
+  /// The position of the first path beneath each table / key.
+  positions: HashMap<String, usize>,
+}
+
+impl KeyOrder {
+  /// From (dotted) key paths, in order. Tables are ordered by the first path beneath them,
+  /// so listing the leaf keys is enough. Like [Ordering::priority](crate::Ordering::priority),
+  /// paths inside arrays of tables don't include the index, eg. `servers.name`.
+  pub fn new<S: AsRef<str>>(paths: impl IntoIterator<Item = S>) -> KeyOrder {
+    let mut positions = HashMap::new();
+    for (i, path) in paths.into_iter().enumerate() {
+      let path = path.as_ref();
+      for end in segment_dots(path).into_iter().chain([path.len()]) {
+        positions.entry(path[..end].to_string()).or_insert(i);
+      }
+    }
+    KeyOrder { positions }
+  }
+
+  /// From the order of the `properties` of a JSON Schema, including nested objects,
+  /// array `items`, `allOf` / `anyOf` / `oneOf` and local `$ref`s (eg. `#/$defs/Server`).
+  /// [Value] keeps the order of object keys when deserialized, eg. with `serde_json::from_str`.
+  pub fn from_json_schema(schema: &Value) -> KeyOrder {
+    let mut paths = Vec::new();
+    schema_paths(schema, schema, "", &mut Vec::new(), &mut paths);
+    KeyOrder::new(paths)
+  }
+
+  pub(crate) fn position(&self, path: &str) -> Option<usize> {
+    self.positions.get(path).copied()
+  }
+}
+
+/// `refs` are the `$ref`s being followed, so recursive schemas end.
+fn schema_paths<'s>(
+  schema: &'s Value,
+  root: &'s Value,
+  path: &str,
+  refs: &mut Vec<&'s str>,
+  paths: &mut Vec<String>,
+) {
+  let Value::Object(schema) = schema else {
+    return;
+  };
+  if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+    if !refs.contains(&reference) {
+      if let Some(target) = resolve_ref(root, reference) {
+        refs.push(reference);
+        schema_paths(target, root, path, refs, paths);
+        refs.pop();
+      }
+    }
+  }
+  if let Some(Value::Object(properties)) = schema.get("properties") {
+    for (name, property) in properties {
+      let path = join_path(path, name);
+      paths.push(path.clone());
+      schema_paths(property, root, &path, refs, paths);
+    }
+  }
+  match schema.get("items") {
+    Some(Value::Array(items)) => {
+      for item in items {
+        schema_paths(item, root, path, refs, paths);
+      }
+    }
+    Some(item) => schema_paths(item, root, path, refs, paths),
+    None => {}
+  }
+  for combinator in ["allOf", "anyOf", "oneOf"] {
+    if let Some(Value::Array(schemas)) = schema.get(combinator) {
+      for schema in schemas {
+        schema_paths(schema, root, path, refs, paths);
+      }
+    }
+  }
+}
+
+/// Resolves a local `$ref` (a JSON pointer into the same schema, eg. `#/definitions/Server`).
+fn resolve_ref<'s>(root: &'s Value, reference: &str) -> Option<&'s Value> {
+  let pointer = reference.strip_prefix('#')?;
+  let mut val = root;
+  for segment in pointer.split('/').skip(1) {
+    let segment = segment.replace("~1", "/").replace("~0", "~");
+    val = match val {
+      Value::Object(map) => map.get(&segment)?,
+      Value::Array(vals) => vals.get(segment.parse::<usize>().ok()?)?,
+      _ => return None,
+    };
+  }
+  Some(val)
+}
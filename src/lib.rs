@@ -1,225 +1,4391 @@
-use std::fmt::Write;
+use std::{
+  borrow::{Borrow, Cow},
+  cell::{Cell, RefCell},
+  collections::{HashMap, HashSet},
+  fs::File,
+  path::{Path, PathBuf},
+};
 
 use ordered_hash_map::OrderedHashMap;
 use serde::Serialize;
-use serde_json::Value;
 use thiserror::Error;
 
+#[cfg(feature = "arbitrary")]
+mod arbitrary;
+mod datetime;
+mod document;
+mod formatter;
+#[cfg(feature = "json5")]
+mod json5;
+mod key_order;
+mod parse;
+mod ser;
+mod sha256;
+#[cfg(test)]
+mod tests;
+mod value;
+#[cfg(feature = "yaml")]
+mod yaml;
+
+#[cfg(feature = "arbitrary")]
+pub use arbitrary::Generator;
+use document::is_under;
+pub use document::{Document, Merge};
+pub use formatter::{DefaultFormatter, Formatter};
+pub use key_order::KeyOrder;
+use parse::{parse_value, split_key};
+use ser::{Diagnostics, ValueSerializer};
+#[cfg(feature = "derive")]
+pub use toml_pretty_derive::{Deprecated, Examples, Secrets};
+pub use value::{Map, Number, Value};
+
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Comments keyed by dotted path. A path can be a leaf key, a table,
+/// in which case the comment goes above the whole table,
+/// or an array element (eg. `servers[2]`), which puts the array on multiple lines.
+pub type CommentMap = OrderedHashMap<String, String>;
+
+/// Deprecation messages of fields keyed by path, for [Options::deprecated].
+/// With the `derive` feature, `#[derive(Deprecated)]` implements it from
+/// `#[toml_pretty(deprecated = "use new_name instead")]` field attributes.
+pub trait Deprecated {
+  fn deprecated() -> CommentMap;
+}
+
+impl<T: Deprecated> Deprecated for Option<T> {
+  fn deprecated() -> CommentMap {
+    T::deprecated()
+  }
+}
+
+/// Paths of the fields holding secrets, for [Options::secrets].
+/// With the `derive` feature, `#[derive(Secrets)]` implements it from `#[toml_pretty(secret)]` field attributes.
+pub trait Secrets {
+  fn secrets() -> Vec<String>;
+}
+
+impl<T: Secrets> Secrets for Option<T> {
+  fn secrets() -> Vec<String> {
+    T::secrets()
+  }
+}
+
+/// Secret paths reach into arrays, so the elements' paths are the array's.
+impl<T: Secrets> Secrets for Vec<T> {
+  fn secrets() -> Vec<String> {
+    T::secrets()
+  }
+}
+
+/// Example values of fields keyed by path, as toml value text, for [Options::examples].
+/// With the `derive` feature, `#[derive(Examples)]` implements it from
+/// `#[toml_pretty(example = "\"localhost\"")]` field attributes.
+pub trait Examples {
+  fn examples() -> Vec<(String, String)>;
+}
+
+impl<T: Examples> Examples for Option<T> {
+  fn examples() -> Vec<(String, String)> {
+    T::examples()
+  }
+}
+
+/// Examples don't reach into arrays, so a `nested` array has none.
+impl<T> Examples for Vec<T> {
+  fn examples() -> Vec<(String, String)> {
+    Vec::new()
+  }
+}
+
+/// Inspects the value at a key path before formatting.
+/// Return `Err` with a description to reject the document.
+pub type Validator = fn(&Value) -> std::result::Result<(), String>;
+
 #[derive(Debug, Error)]
 pub enum Error {
+  #[cfg(feature = "json")]
   #[error("Failed to de/serialize value to json")]
   JsonSerialization(#[from] serde_json::Error),
   #[error("Failed to format args")]
   Format(#[from] std::fmt::Error),
+  #[error("Failed to write output")]
+  Io(#[from] std::io::Error),
   #[error("Came across triple nested array. Not supported.")]
   TripleNestedArray,
   #[error("Came across Value::Object after flatten_map. This shouldn't happen")]
   ObjectReached,
+  #[error("Failed to parse toml on line {line}: {message}")]
+  Parse { line: usize, message: String },
+  #[error("Failed to parse json5 on line {line}: {message}")]
+  ParseJson5 { line: usize, message: String },
+  #[error("Failed to parse yaml on line {line}: {message}")]
+  ParseYaml { line: usize, message: String },
+  #[error("Output does not round trip:\n{0}")]
+  Roundtrip(String),
+  #[error("Invalid options: {0}")]
+  InvalidOptions(String),
+  #[error("Invalid value at {path}: {message}")]
+  Validation { path: String, message: String },
+  #[error("Failed to serialize value: {0}")]
+  Custom(String),
+  #[error("Invalid UTF-8 at {path}")]
+  InvalidUtf8 { path: String },
+  #[error("Integer at {path} doesn't fit in 64 bits")]
+  IntegerOutOfRange { path: String },
+  #[error("Keys {first:?} and {second:?} both become {path:?} after key_transform")]
+  KeyCollision {
+    path: String,
+    first: String,
+    second: String,
+  },
+  #[error("No table at {0}")]
+  PathNotFound(String),
+  #[error("Output exceeds the limit of {limit} bytes at {path}")]
+  OutputTooLarge { limit: usize, path: String },
+  #[error("Value at {path} is nested deeper than the limit of {limit}")]
+  TooDeep { limit: usize, path: String },
+  #[error("Value has more than the limit of {limit} keys, at {path}")]
+  TooManyKeys { limit: usize, path: String },
+  #[error("Duplicate key {key}")]
+  DuplicateKey { key: String },
+  #[error("{key} must come before the table {table} in header mode")]
+  TableOrder { key: String, table: String },
+  #[error("Can't write {path}, it conflicts with the value at {existing}")]
+  PathConflict { path: String, existing: String },
+}
+
+impl Error {
+  /// The key path the error occurred at, if known.
+  pub fn path(&self) -> Option<&str> {
+    match self {
+      Error::Validation { path, .. }
+      | Error::InvalidUtf8 { path }
+      | Error::IntegerOutOfRange { path }
+      | Error::KeyCollision { path, .. }
+      | Error::OutputTooLarge { path, .. }
+      | Error::TooDeep { path, .. }
+      | Error::TooManyKeys { path, .. }
+      | Error::PathNotFound(path)
+      | Error::DuplicateKey { key: path }
+      | Error::TableOrder { key: path, .. }
+      | Error::PathConflict { path, .. } => Some(path),
+      _ => None,
+    }
+  }
+
+  /// The line of the input a parse error occurred on.
+  pub fn line(&self) -> Option<usize> {
+    match self {
+      Error::Parse { line, .. }
+      | Error::ParseJson5 { line, .. }
+      | Error::ParseYaml { line, .. } => Some(*line),
+      _ => None,
+    }
+  }
+
+  /// A suggestion for fixing the error, for display by CLI tools.
+  pub fn help(&self) -> Option<&'static str> {
+    match self {
+      Error::TripleNestedArray => Some("wrap the innermost arrays in a struct / map"),
+      Error::InvalidUtf8 { .. } => Some("use Options::non_utf8 to replace or percent encode it"),
+      Error::IntegerOutOfRange { .. } => Some("use Options::big_integers to write it as a string"),
+      Error::KeyCollision { .. } => Some("rename one of the keys, or adjust the key_transform"),
+      Error::OutputTooLarge { .. } => {
+        Some("raise max_output_bytes, or use max_array_items / truncate_depth")
+      }
+      Error::TooDeep { .. } => Some("raise max_depth, if the value is trusted"),
+      Error::TooManyKeys { .. } => Some("raise max_keys, if the value is trusted"),
+      Error::DuplicateKey { .. } => Some("produce each key once, merging their values first"),
+      Error::TableOrder { .. } => {
+        Some("produce the entries which aren't tables first, or disable table_headers")
+      }
+      Error::PathConflict { .. } => {
+        Some("move the value to a path which isn't beneath another value")
+      }
+      Error::Custom(_) => {
+        Some("use to_string_lenient to skip the values which can't be serialized")
+      }
+      Error::Roundtrip(_) => Some("check the toml for values which can't be represented"),
+      _ => None,
+    }
+  }
+}
+
+/// The location of a key's value in the output, see [to_string_with_source_map].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+  /// Starting from 1.
+  pub line: usize,
+  /// In characters, starting from 1.
+  pub column: usize,
+  /// In characters. Includes the line breaks of multiline values.
+  pub length: usize,
+}
+
+/// The [Span] of each key's value, keyed by path like [CommentMap].
+/// Keys beneath `[[array]]` headers include the index, eg. `servers[0].name`.
+pub type SourceMap = OrderedHashMap<String, Span>;
+
+/// A lossy decision made while serializing, see [to_string_with_warnings].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+  /// An integer outside of toml's 64 bit signed range was written as a string, per [BigIntegers::String].
+  IntegerAsString { path: String },
+  /// A NaN / infinite float was dropped, like `None`.
+  NonFiniteDropped { path: String },
+  /// Non UTF-8 data was replaced, per [Options::non_utf8].
+  NonUtf8Replaced { path: String },
+  /// A key which isn't a valid bare key was quoted.
+  KeyQuoted { path: String },
+}
+
+impl Warning {
+  pub fn path(&self) -> &str {
+    match self {
+      Warning::IntegerAsString { path }
+      | Warning::NonFiniteDropped { path }
+      | Warning::NonUtf8Replaced { path }
+      | Warning::KeyQuoted { path } => path,
+    }
+  }
+}
+
+impl std::fmt::Display for Warning {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Warning::IntegerAsString { path } => {
+        write!(f, "{path}: integer out of range, written as a string")
+      }
+      Warning::NonFiniteDropped { path } => write!(f, "{path}: non finite float dropped"),
+      Warning::NonUtf8Replaced { path } => write!(f, "{path}: non UTF-8 data replaced"),
+      Warning::KeyQuoted { path } => write!(f, "{path}: key quoted"),
+    }
+  }
+}
+
+/// The dependency tables of a Cargo.toml, for [Options::dependency_tables].
+pub const CARGO_DEPENDENCY_TABLES: &[&str] = &[
+  "dependencies",
+  "dev-dependencies",
+  "build-dependencies",
+  "workspace.dependencies",
+  "target.*.dependencies",
+  "target.*.dev-dependencies",
+  "target.*.build-dependencies",
+];
+
+/// How to render tables with no keys, at any depth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmptyTable {
+  /// Leave them out of the output.
+  #[default]
+  Skip,
+  /// Render as an empty inline table, `key = {}`.
+  Inline,
+  /// Render as an empty `[key]` table header. Requires `table_headers`.
+  /// Inside inline tables, falls back to `key = {}`.
+  Header,
+}
+
+/// Where empty tables nested inside other tables are written, eg. for `{ "a": { "b": { "c": {} } } }`.
+/// Applies to the top level document, `[[array]]` sections and inline tables alike.
+/// How the empty tables which remain are written is up to [EmptyTable].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NestedEmptyTable {
+  /// At the path of the empty table itself, `a.b.c = {}`.
+  #[default]
+  Innermost,
+  /// At the outermost ancestor containing nothing but empty tables, `a = {}`.
+  Outermost,
+  /// Leave out empty tables nested inside other tables. Top level empty tables are kept.
+  Skip,
+}
+
+/// How to serialize `OsString` values which aren't valid UTF-8.
+/// `OsString`s which are valid UTF-8 are always written as plain strings.
+/// Note that serde's impl for `Path` / `PathBuf` fails on non UTF-8 paths before toml_pretty sees them,
+/// so those are always [Error::InvalidUtf8], regardless of the policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NonUtf8 {
+  /// Fail with [Error::InvalidUtf8], including the key path of the value.
+  #[default]
+  Error,
+  /// Replace invalid sequences with U+FFFD.
+  Lossy,
+  /// Percent encode invalid bytes (and '%'), eg. `%FF`, so the original can be recovered.
+  PercentEncode,
+}
+
+/// How to serialize integers which don't fit in toml's 64 bit signed integers,
+/// eg. `u64`s above `i64::MAX`, `u128`s, or big integer types which serialize through `serialize_i128` / `serialize_u128`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BigIntegers {
+  /// Fail with [Error::IntegerOutOfRange], including the key path of the value.
+  #[default]
+  Error,
+  /// Write them as strings of their decimal digits, eg. `"340282366920938463463374607431768211455"`.
+  String,
+}
+
+/// Which keys to sort alphabetically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Alphabetical {
+  /// Keep serialization order.
+  #[default]
+  Off,
+  /// Sort the keys of maps (eg. `HashMap`), while struct fields keep their declaration order.
+  /// Note that structs containing a `#[serde(flatten)]` field serialize as maps, so are sorted too.
+  Maps,
+  /// Sort the keys of every table.
+  All,
+}
+
+/// Key ordering rules, applied within each table in order of precedence:
+/// 1. In tables inside arrays (inline tables / `[[array]]` entries), the `tag_keys`, in list order.
+/// 2. Keys in the `priority` list, in list order.
+/// 3. Keys in the `canonical` order. Keys it doesn't cover go after them.
+/// 4. Keys in the `reference` document, in its order. New keys go after them.
+/// 5. Keys grouped by kind, if `group_by_kind`: booleans and numbers, then strings,
+///    then arrays, then tables (and arrays of tables). Otherwise scalars (and arrays of scalars)
+///    before tables, if `scalars_first`.
+/// 6. `alphabetical` order.
+/// 7. Serialization (insertion) order.
+///
+/// Each rule only breaks ties left by the rules before it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ordering<'a> {
+  pub tag_keys: &'a [&'a str],
+  pub priority: &'a [&'a str],
+  pub canonical: Option<&'a KeyOrder>,
+  pub reference: Option<&'a Document>,
+  pub scalars_first: bool,
+  pub group_by_kind: bool,
+  pub alphabetical: Alphabetical,
+}
+
+impl<'a> Ordering<'a> {
+  /// The default ordering, usable in `const` items.
+  pub const fn new() -> Self {
+    Self {
+      tag_keys: &[],
+      priority: &[],
+      canonical: None,
+      reference: None,
+      scalars_first: false,
+      group_by_kind: false,
+      alphabetical: Alphabetical::Off,
+    }
+  }
+
+  /// Specify the tag keys of tagged enums (eg. `type`, from `#[serde(tag = "type")]`)
+  /// to put first in the tables inside arrays, so each entry's variant is seen at a glance.
+  /// Serde writes the tag first already; this keeps it first under the other rules, eg. `alphabetical`.
+  pub const fn tag_keys(mut self, tag_keys: &'a [&'a str]) -> Self {
+    self.tag_keys = tag_keys;
+    self
+  }
+
+  /// Specify (dotted) key paths to put first in their table, in this order.
+  /// Paths inside arrays of tables don't include the index, eg. `servers.name`.
+  pub const fn priority(mut self, priority: &'a [&'a str]) -> Self {
+    self.priority = priority;
+    self
+  }
+
+  /// Specify a canonical key order for the whole document, eg. [KeyOrder::from_json_schema].
+  pub const fn canonical(mut self, canonical: &'a KeyOrder) -> Self {
+    self.canonical = Some(canonical);
+    self
+  }
+
+  /// Specify a document (eg. the previous version of the file) whose key order to follow,
+  /// so regenerated files stay diff minimal. See [Document::parse].
+  pub const fn reference(mut self, reference: &'a Document) -> Self {
+    self.reference = Some(reference);
+    self
+  }
+
+  /// Specify whether to put scalars / arrays of scalars before tables.
+  pub const fn scalars_first(mut self, scalars_first: bool) -> Self {
+    self.scalars_first = scalars_first;
+    self
+  }
+
+  /// Specify whether to group keys by kind: booleans and numbers first, then strings,
+  /// then arrays, then tables. Takes the place of `scalars_first`.
+  pub const fn group_by_kind(mut self, group_by_kind: bool) -> Self {
+    self.group_by_kind = group_by_kind;
+    self
+  }
+
+  /// Specify which keys to sort alphabetically. Default is [Alphabetical::Off]
+  pub const fn alphabetical(mut self, alphabetical: Alphabetical) -> Self {
+    self.alphabetical = alphabetical;
+    self
+  }
+
+  fn is_default(&self) -> bool {
+    self.tag_keys.is_empty()
+      && self.priority.is_empty()
+      && self.canonical.is_none()
+      && self.reference.is_none()
+      && !self.scalars_first
+      && !self.group_by_kind
+      && self.alphabetical != Alphabetical::All
+  }
+}
+
+/// Where the content of a multiline string starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MultilineStart {
+  /// On the line after the opening `"""`, as toml drops a line break right after it.
+  #[default]
+  Newline,
+  /// Right after the opening `"""`. Strings starting with a line break still start on the next line.
+  Inline,
+  /// On the line after an opening `"""\`, whose line continuation drops the line break.
+  Backslash,
+}
+
+/// How to write carriage returns in string values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CarriageReturn {
+  /// Write every `\r` as the `\r` escape, so the value round trips exactly.
+  #[default]
+  Escape,
+  /// Convert `\r\n` line endings to `\n`. Lone `\r`s are still escaped.
+  Normalize,
+}
+
+/// How to write tab characters in string values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StringTabs {
+  /// Write tabs as is, which is valid toml.
+  #[default]
+  Keep,
+  /// Write tabs as the `\t` escape.
+  Escape,
+  /// Replace tabs with spaces, up to the next tab stop every `n` columns.
+  /// The value will no longer match the original.
+  Expand(usize),
+}
+
+/// How to write the offset of datetime strings, eg. from chrono's `DateTime<FixedOffset>`,
+/// which serialize as RFC 3339 strings like `1979-05-27T07:32:00-08:00`.
+/// Strings which aren't offset datetimes are left as is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DatetimeOffset {
+  /// Keep the offset they were written with.
+  #[default]
+  Preserve,
+  /// Convert to UTC, written as `Z`, eg. `1979-05-27T15:32:00Z`.
+  Utc,
+  /// Convert to a fixed offset, in minutes east of UTC, eg. `60` for `+01:00`.
+  Fixed(i16),
+}
+
+/// How [to_file] writes the file.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileOptions<'a> {
+  pub lock: bool,
+  pub backup: Option<&'a str>,
+}
+
+impl<'a> FileOptions<'a> {
+  /// The default file options, usable in `const` items.
+  pub const fn new() -> Self {
+    Self {
+      lock: false,
+      backup: None,
+    }
+  }
+
+  /// Specify whether to hold an exclusive advisory lock on `<file>.lock` while writing,
+  /// so processes regenerating the same file take turns rather than clobbering each other.
+  /// The lock file is left in place, as removing it would race with the next writer.
+  pub const fn lock(mut self, lock: bool) -> Self {
+    self.lock = lock;
+    self
+  }
+
+  /// Specify a suffix (eg. `.bak`) to copy the existing file to before replacing it.
+  pub const fn backup(mut self, suffix: &'a str) -> Self {
+    self.backup = Some(suffix);
+    self
+  }
+}
+
+/// The case of the variable names for [Options::env_comments].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EnvCase {
+  /// `APP_SERVER_PORT`
+  #[default]
+  Upper,
+  /// `app_server_port`
+  Lower,
+  /// Keep the case of the keys, `APP_server_port`.
+  Preserve,
 }
 
 #[derive(Clone, Copy)]
 pub struct Options<'a> {
   pub tab: &'a str,
+  pub array_indent: Option<&'a str>,
   pub skip_empty_string: bool,
   pub inline_array: bool,
+  pub inline_tables: bool,
   pub max_inline_array_length: usize,
+  pub post_process: Option<fn(String) -> String>,
+  pub validators: &'a [(&'a str, Validator)],
+  pub comments: Option<&'a CommentMap>,
+  pub footer: Option<&'a str>,
+  pub secrets: &'a [&'a str],
+  pub redact_secrets: bool,
+  pub secret_mask: &'a str,
+  pub scaffold: bool,
+  pub examples: &'a [(&'a str, &'a str)],
+  pub type_comments: bool,
+  pub env_comments: Option<&'a str>,
+  pub env_case: EnvCase,
+  pub table_headers: bool,
+  pub empty_table: EmptyTable,
+  pub nested_empty_table: NestedEmptyTable,
+  pub super_tables: bool,
+  pub collapse_tables: bool,
+  pub ordering: Ordering<'a>,
+  pub dotted_depth: usize,
+  pub header_dotted_depth: usize,
+  pub max_output_bytes: Option<usize>,
+  pub max_depth: Option<usize>,
+  pub max_keys: Option<usize>,
+  pub non_utf8: NonUtf8,
+  pub big_integers: BigIntegers,
+  pub carriage_return: CarriageReturn,
+  pub string_tabs: StringTabs,
+  pub datetime_offset: DatetimeOffset,
+  pub key_transform: Option<fn(&str) -> String>,
+  pub quote_all_keys: bool,
+  pub dependency_tables: &'a [&'a str],
+  pub trailing_comma: bool,
+  pub trailing_newline: bool,
+  pub max_line_width: Option<usize>,
+  pub max_inline_array_elements: Option<usize>,
+  pub max_array_items: Option<usize>,
+  pub truncate_depth: Option<usize>,
+  pub migrations: &'a [(&'a str, &'a str)],
+  pub migration_comments: bool,
+  pub aliases: &'a [(&'a str, &'a str)],
+  pub deprecated: Option<&'a CommentMap>,
+  pub omit_deprecated: bool,
+  pub array_tables: bool,
+  pub array_table_blank_lines: bool,
+  pub array_table_comment: Option<&'a str>,
+  pub align_array_tables: bool,
+  pub align_numbers: bool,
+  pub comment_column: Option<usize>,
+  pub content_hash: bool,
+  pub multiline_indent: bool,
+  pub multiline_start: MultilineStart,
+  pub formatter: &'a dyn Formatter,
 }
 
 impl<'a> Default for Options<'a> {
   fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<'a> Options<'a> {
+  /// The default options, usable in `const` / `static` items along with the builder methods,
+  /// so a house style can be defined once without lazy initialization:
+  /// ```
+  /// static OPTIONS: toml_pretty::Options = toml_pretty::Options::new()
+  ///   .tab("  ")
+  ///   .trailing_newline(true);
+  /// ```
+  pub const fn new() -> Self {
     Self {
       tab: "\t",
+      array_indent: None,
       skip_empty_string: false,
       inline_array: false,
+      inline_tables: false,
       max_inline_array_length: 50,
+      post_process: None,
+      validators: &[],
+      comments: None,
+      footer: None,
+      secrets: &[],
+      redact_secrets: false,
+      secret_mask: "********",
+      scaffold: false,
+      examples: &[],
+      type_comments: false,
+      env_comments: None,
+      env_case: EnvCase::Upper,
+      table_headers: false,
+      empty_table: EmptyTable::Skip,
+      nested_empty_table: NestedEmptyTable::Innermost,
+      super_tables: false,
+      collapse_tables: false,
+      ordering: Ordering::new(),
+      dotted_depth: 0,
+      header_dotted_depth: 0,
+      max_output_bytes: None,
+      max_depth: None,
+      max_keys: None,
+      non_utf8: NonUtf8::Error,
+      big_integers: BigIntegers::Error,
+      carriage_return: CarriageReturn::Escape,
+      string_tabs: StringTabs::Keep,
+      datetime_offset: DatetimeOffset::Preserve,
+      key_transform: None,
+      quote_all_keys: false,
+      dependency_tables: &[],
+      trailing_comma: false,
+      trailing_newline: false,
+      max_line_width: None,
+      max_inline_array_elements: None,
+      max_array_items: None,
+      truncate_depth: None,
+      migrations: &[],
+      aliases: &[],
+      migration_comments: false,
+      deprecated: None,
+      omit_deprecated: false,
+      array_tables: false,
+      array_table_blank_lines: false,
+      array_table_comment: None,
+      align_array_tables: false,
+      align_numbers: false,
+      comment_column: None,
+      content_hash: false,
+      multiline_indent: false,
+      multiline_start: MultilineStart::Newline,
+      formatter: &DefaultFormatter,
     }
   }
-}
 
-impl<'a> Options<'a> {
+  /// A best effort match of the `toml` crate's `to_string_pretty` output:
+  /// `[table]` headers (including empty tables), `[[array]]` sections separated by blank lines,
+  /// values before tables, and arrays of several elements on multiple lines, indented by 4 spaces
+  /// with a trailing comma.
+  /// Only checked against the documented output, so diff generated files when switching.
+  pub const fn toml_crate_compat() -> Self {
+    Options::new()
+      .tab("    ")
+      .table_headers(true)
+      .empty_table(EmptyTable::Header)
+      .array_tables(true)
+      .array_table_blank_lines(true)
+      .max_inline_array_elements(1)
+      .trailing_comma(true)
+      .trailing_newline(true)
+      .ordering(Ordering::new().scalars_first(true))
+  }
+
+  /// Matches the defaults of the taplo formatter, so output passes a taplo pre-commit hook unchanged:
+  /// 2 space indent, arrays inline until the line is over 80 wide, trailing commas and a trailing newline.
+  pub const fn taplo() -> Self {
+    Options::new()
+      .tab("  ")
+      .max_line_width(80)
+      .trailing_comma(true)
+      .trailing_newline(true)
+  }
+
   /// Specify the symbol to use for tab. Default is '\t'
-  pub fn tab(mut self, tab: &'a str) -> Self {
+  pub const fn tab(mut self, tab: &'a str) -> Self {
     self.tab = tab;
     self
   }
 
+  /// Specify the indentation of the elements of multiline arrays, when it should differ from `tab`
+  /// (which still indents multiline strings), eg. 4 spaces with a `tab` of 2. Default is `tab`.
+  pub const fn array_indent(mut self, array_indent: &'a str) -> Self {
+    self.array_indent = Some(array_indent);
+    self
+  }
+
   /// Specify whether to skip serializing string fields containing empty strings
-  pub fn skip_empty_string(mut self, skip_empty_string: bool) -> Self {
+  pub const fn skip_empty_string(mut self, skip_empty_string: bool) -> Self {
     self.skip_empty_string = skip_empty_string;
     self
   }
 
   /// Specify whether to serialize arrays inline, rather than on multiple lines.
-  pub fn inline_array(mut self, inline_array: bool) -> Self {
+  pub const fn inline_array(mut self, inline_array: bool) -> Self {
     self.inline_array = inline_array;
     self
   }
 
-  pub fn max_inline_array_length(mut self, max_inline_array_length: usize) -> Self {
+  /// Specify whether to write every table as an inline table on its root key,
+  /// eg. `server = { host = "x", tls = { cert = "..." } }`, rather than flattening it to dotted keys.
+  /// Comments are only written on root keys, and arrays of tables still follow [Options::array_tables].
+  pub const fn inline_tables(mut self, inline_tables: bool) -> Self {
+    self.inline_tables = inline_tables;
+    self
+  }
+
+  pub const fn max_inline_array_length(mut self, max_inline_array_length: usize) -> Self {
     self.max_inline_array_length = max_inline_array_length;
     self
   }
-}
 
-pub fn to_string<T: Serialize>(value: &T, options: Options<'_>) -> Result<String> {
-  let Options {
-    tab,
-    skip_empty_string,
-    inline_array,
-    max_inline_array_length,
-  } = options;
-  let map = serde_json::from_str(&serde_json::to_string(value).map_err(Error::JsonSerialization)?)
-    .map_err(Error::JsonSerialization)?;
-  let mut res = String::new();
-  for (i, (key, val)) in flatten_map(map).into_iter().enumerate() {
-    match &val {
-      Value::Null => {}
+  /// Specify a function to run on the finished document before it is returned,
+  /// eg. to inject a license header.
+  pub const fn post_process(mut self, post_process: fn(String) -> String) -> Self {
+    self.post_process = Some(post_process);
+    self
+  }
 
-      Value::Bool(_) | Value::Number(_) => {
-        if i != 0 {
-          res.push('\n');
-        }
-        res
-          .write_fmt(format_args!("{key} = {val}"))
-          .map_err(Error::Format)?;
-      }
+  /// Specify validators to run on the values at the given (dotted) key paths
+  /// before formatting. Paths missing from the document are not validated.
+  /// A path may name a table (given the whole table), and reaches into arrays:
+  /// `servers[0].port` is the port of the first server, and `servers[*].port` or just `servers.port` that of each.
+  /// Errors name the exact path, eg. `servers[1].port`.
+  pub const fn validators(mut self, validators: &'a [(&'a str, Validator)]) -> Self {
+    self.validators = validators;
+    self
+  }
 
-      Value::String(val) => {
-        if skip_empty_string && val.is_empty() {
-          continue;
-        }
-        if i != 0 {
-          res.push('\n');
-        }
-        if val.contains('\n') {
-          res
-            .write_fmt(format_args!("{key} = \"\"\"\n{val}\"\"\""))
-            .map_err(Error::Format)?;
-        } else {
-          res
-            .write_fmt(format_args!("{key} = \"{}\"", val.replace('"', "\\\"")))
-            .map_err(Error::Format)?;
-        }
-      }
+  /// Specify comments to write above keys / tables.
+  pub const fn comments(mut self, comments: &'a CommentMap) -> Self {
+    self.comments = Some(comments);
+    self
+  }
 
-      Value::Array(vals) => {
-        if vals.is_empty() {
-          if i != 0 {
-            res.push('\n');
-          }
-          res
-            .write_fmt(format_args!("{key} = []"))
-            .map_err(Error::Format)?;
-          continue;
-        }
-        let mut strs = Vec::<String>::with_capacity(vals.capacity());
-        for val in vals {
-          match val {
-            Value::Null => {}
-            Value::Bool(_) | Value::Number(_) => strs.push(val.to_string()),
-            Value::String(string) => {
-              if skip_empty_string && string.is_empty() {
-                continue;
-              }
-              strs.push(format!("\"{}\"", string.replace('"', "\\\"")))
-            }
-            Value::Object(map) => strs.push(format!(
-              "{{ {} }}",
-              to_string(&map, options.inline_array(true))?
-                .split('\n')
-                .collect::<Vec<_>>()
-                .join(", ")
-            )),
-            Value::Array(vals) => {
-              let mut out = Vec::new();
-              for val in vals {
-                match val {
-                  Value::Null => {}
-                  Value::Bool(_) | Value::Number(_) => out.push(val.to_string()),
-                  Value::String(string) => out.push(format!("\"{}\"", string.replace('"', "\\\""))),
-                  Value::Object(map) => out.push(format!(
-                    "{{ {} }}",
-                    to_string(&map, options.inline_array(true))?
-                      .split('\n')
-                      .collect::<Vec<_>>()
-                      .join(", ")
-                  )),
-                  Value::Array(_) => return Err(Error::TripleNestedArray),
-                }
-              }
-              strs.push(format!("[{}]", out.join(", ")));
-            }
-          }
-        }
-        let total_length = strs.iter().fold(0, |total, curr| total + curr.len());
-        let inline_array = inline_array || total_length <= max_inline_array_length;
-        let join = if inline_array {
-          String::from(", ")
-        } else {
-          format!(",\n{tab}")
-        };
-        let val = strs.join(&join);
-        if i != 0 {
-          res.push('\n');
-        }
-        if inline_array {
-          res
-            .write_fmt(format_args!("{key} = [{val}]"))
-            .map_err(Error::Format)?;
-        } else {
-          res
-            .write_fmt(format_args!("{key} = [\n{tab}{val}\n]"))
-            .map_err(Error::Format)?;
-        }
-      }
+  /// Specify a comment block to write at the end of the document,
+  /// separated from the last key by a blank line. eg. regeneration instructions.
+  pub const fn footer(mut self, footer: &'a str) -> Self {
+    self.footer = Some(footer);
+    self
+  }
 
-      // All objects should be removed by flatten_map
-      Value::Object(_) => return Err(Error::ObjectReached),
-    }
+  /// Specify the (dotted) key paths holding secrets. Tables redact everything beneath them.
+  /// Paths reach into arrays like those of [validators](Options::validators), eg. `db.password` or `db[*].password`
+  /// for the passwords of each `[[db]]`. With the `derive` feature, see [Secrets].
+  /// Only redacted when `redact_secrets` is enabled, so logging and on-disk output can share one type.
+  pub const fn secrets(mut self, secrets: &'a [&'a str]) -> Self {
+    self.secrets = secrets;
+    self
   }
-  Ok(res)
-}
 
-fn flatten_map(map: OrderedHashMap<String, Value>) -> OrderedHashMap<String, Value> {
-  let mut target = OrderedHashMap::new();
-  flatten_map_rec(&mut target, None, map);
-  target
-}
+  /// Specify whether to replace the values at the `secrets` paths with `secret_mask`.
+  pub const fn redact_secrets(mut self, redact_secrets: bool) -> Self {
+    self.redact_secrets = redact_secrets;
+    self
+  }
 
-fn flatten_map_rec(
-  target: &mut OrderedHashMap<String, Value>,
-  parent_field: Option<String>,
-  source: OrderedHashMap<String, Value>,
-) {
-  let parent_field = match parent_field {
-    Some(mut parent_field) => {
-      parent_field.push('.');
-      parent_field
-    }
-    None => String::new(),
-  };
-  for (field, val) in source {
-    let parent_field = if parent_field.is_empty() {
-      field
-    } else {
-      let mut parent_field = parent_field.clone();
-      parent_field.push_str(&field);
-      parent_field
-    };
-    if let Value::Object(source) = val {
-      flatten_map_rec(target, Some(parent_field), source.into_iter().collect())
-    } else {
-      target.insert(parent_field, val);
-    }
+  /// Specify the string which replaces redacted secrets. Default is '********'
+  pub const fn secret_mask(mut self, secret_mask: &'a str) -> Self {
+    self.secret_mask = secret_mask;
+    self
   }
-}
 
-// Flattens a nested bson document using the mongo '.' syntax. Useful for partial updates.
-// doc! { "f1": "yes", "f2": { "f3": "no" } } -> doc! { "f1": "yes", "f2.f3": "no" }
-// pub fn flatten_document(doc: Document) -> Document {
-//   let mut target = Document::new();
-//   flatten_document_rec(&mut target, None, doc);
-//   target
-// }
+  /// Specify whether to render a scaffold / example config,
+  /// where the values at the `examples` paths are replaced by their example.
+  pub const fn scaffold(mut self, scaffold: bool) -> Self {
+    self.scaffold = scaffold;
+    self
+  }
+
+  /// Specify example values by (dotted) key path, as toml value text (eg. `"\"localhost\""`).
+  /// Used in place of the actual (often Default) value in scaffold mode.
+  /// Examples for keys missing from the document are added at the end.
+  /// An example replaces everything at its path, eg. a table example replaces a scalar.
+  /// With the `derive` feature, see [Examples].
+  pub const fn examples(mut self, examples: &'a [(&'a str, &'a str)]) -> Self {
+    self.examples = examples;
+    self
+  }
+
+  /// Specify whether to add a comment with the type of each value, eg. `# type: array of strings`,
+  /// for users editing a scaffold / example config.
+  pub const fn type_comments(mut self, type_comments: bool) -> Self {
+    self.type_comments = type_comments;
+    self
+  }
+
+  /// Add a comment with the environment variable overriding each key, eg. `# env: APP_SERVER_PORT`
+  /// for `server.port` with prefix `APP_`. The variable is the prefix followed by the path segments
+  /// joined with `_`, with any characters other than ascii letters / digits also replaced by `_`.
+  pub const fn env_comments(mut self, prefix: &'a str) -> Self {
+    self.env_comments = Some(prefix);
+    self
+  }
+
+  /// Specify the case of the variable names for [Options::env_comments]. Default is [EnvCase::Upper]
+  pub const fn env_case(mut self, env_case: EnvCase) -> Self {
+    self.env_case = env_case;
+    self
+  }
+
+  /// Specify whether to put nested tables under `[table]` headers,
+  /// rather than using dotted keys.
+  pub const fn table_headers(mut self, table_headers: bool) -> Self {
+    self.table_headers = table_headers;
+    self
+  }
+
+  /// Specify how to render tables with no keys. Default is [EmptyTable::Skip]
+  pub const fn empty_table(mut self, empty_table: EmptyTable) -> Self {
+    self.empty_table = empty_table;
+    self
+  }
+
+  /// Specify where empty tables nested inside other tables are written.
+  /// Default is [NestedEmptyTable::Innermost]
+  pub const fn nested_empty_table(mut self, nested_empty_table: NestedEmptyTable) -> Self {
+    self.nested_empty_table = nested_empty_table;
+    self
+  }
+
+  /// Specify whether intermediate tables with no keys of their own get a `[table]` header
+  /// in header mode, rather than only their sub tables (`[a]` before `[a.b]`).
+  pub const fn super_tables(mut self, super_tables: bool) -> Self {
+    self.super_tables = super_tables;
+    self
+  }
+
+  /// Specify whether super tables containing exactly one nested table (and no keys)
+  /// are left without a header, so `[a]`, `[a.b]`, `[a.b.c]` collapse into `[a.b.c]`.
+  pub const fn collapse_tables(mut self, collapse_tables: bool) -> Self {
+    self.collapse_tables = collapse_tables;
+    self
+  }
+
+  /// Specify how deeply nested tables can be while still being written with dotted keys
+  /// in header mode. Deeper tables get a `[table]` header. Default is 0 (every table gets a header).
+  /// eg. with 1, `a.b = 1` stays dotted, while `a.b.c = 1` goes under `[a.b]`.
+  pub const fn dotted_depth(mut self, dotted_depth: usize) -> Self {
+    self.dotted_depth = dotted_depth;
+    self
+  }
+
+  /// Specify how many levels of nesting to keep as dotted keys under a `[table]` header in header mode,
+  /// rather than giving every nested table its own header. Default is 0.
+  /// eg. with 1, `[server]` holds `tls.cert = "..."`, while `server.tls.ca.path` gets `[server.tls]`,
+  /// as tables with deeper nesting get their own header.
+  pub const fn header_dotted_depth(mut self, header_dotted_depth: usize) -> Self {
+    self.header_dotted_depth = header_dotted_depth;
+    self
+  }
+
+  /// Specify how to order keys. Default is serialization order.
+  /// Applies when serializing values, [Document]s keep their own order (see [Document::sort_keys]).
+  pub const fn ordering(mut self, ordering: Ordering<'a>) -> Self {
+    self.ordering = ordering;
+    self
+  }
+
+  /// Specify a limit on the size of the document, eg. when rendering untrusted payloads for display.
+  /// Rendering stops with [Error::OutputTooLarge] at the first key which goes over the limit.
+  pub const fn max_output_bytes(mut self, max_output_bytes: usize) -> Self {
+    self.max_output_bytes = Some(max_output_bytes);
+    self
+  }
+
+  /// Specify a limit on how deeply tables / arrays can be nested, eg. when formatting untrusted payloads.
+  /// Top level keys are at depth 1. Serializing stops with [Error::TooDeep] at the first value beyond it.
+  pub const fn max_depth(mut self, max_depth: usize) -> Self {
+    self.max_depth = Some(max_depth);
+    self
+  }
+
+  /// Specify a limit on the number of keys in the value, counting the keys of all nested tables.
+  /// Serializing stops with [Error::TooManyKeys] at the first key over the limit.
+  pub const fn max_keys(mut self, max_keys: usize) -> Self {
+    self.max_keys = Some(max_keys);
+    self
+  }
+
+  /// Specify how to serialize `OsString`s which aren't valid UTF-8. Default is [NonUtf8::Error]
+  pub const fn non_utf8(mut self, non_utf8: NonUtf8) -> Self {
+    self.non_utf8 = non_utf8;
+    self
+  }
+
+  /// Specify how to serialize integers which don't fit in 64 bits. Default is [BigIntegers::Error]
+  pub const fn big_integers(mut self, big_integers: BigIntegers) -> Self {
+    self.big_integers = big_integers;
+    self
+  }
+
+  /// Specify how to write carriage returns in strings. Default is [CarriageReturn::Escape]
+  pub const fn carriage_return(mut self, carriage_return: CarriageReturn) -> Self {
+    self.carriage_return = carriage_return;
+    self
+  }
+
+  /// Specify how to write tabs in strings. Default is [StringTabs::Keep]
+  pub const fn string_tabs(mut self, string_tabs: StringTabs) -> Self {
+    self.string_tabs = string_tabs;
+    self
+  }
+
+  /// Specify how to write the offset of datetime strings. Default is [DatetimeOffset::Preserve]
+  pub const fn datetime_offset(mut self, datetime_offset: DatetimeOffset) -> Self {
+    self.datetime_offset = datetime_offset;
+    self
+  }
+
+  /// Specify a function to rename every key when serializing, eg. to kebab-case.
+  /// Keys which end up the same within a table are an [Error::KeyCollision],
+  /// rather than one silently replacing the other.
+  /// [Ordering] priority paths refer to the transformed keys.
+  pub const fn key_transform(mut self, key_transform: fn(&str) -> String) -> Self {
+    self.key_transform = Some(key_transform);
+    self
+  }
+
+  /// Specify whether to quote every key segment, eg. `"a"."b" = 1` rather than `a.b = 1`,
+  /// not just the ones which aren't valid bare keys. Applies to table headers and inline tables too.
+  pub const fn quote_all_keys(mut self, quote_all_keys: bool) -> Self {
+    self.quote_all_keys = quote_all_keys;
+    self
+  }
+
+  /// Specify (dotted) paths of tables to format like Cargo.toml dependencies, eg. [CARGO_DEPENDENCY_TABLES].
+  /// A `*` segment matches any key. Entries are sorted by name, and written as
+  /// `name = "1.0"` when they only have a version, otherwise as an inline table with `version` first.
+  pub const fn dependency_tables(mut self, dependency_tables: &'a [&'a str]) -> Self {
+    self.dependency_tables = dependency_tables;
+    self
+  }
+
+  /// Specify whether multiline arrays get a comma after the last element.
+  pub const fn trailing_comma(mut self, trailing_comma: bool) -> Self {
+    self.trailing_comma = trailing_comma;
+    self
+  }
+
+  /// Specify whether to end the document with a newline.
+  pub const fn trailing_newline(mut self, trailing_newline: bool) -> Self {
+    self.trailing_newline = trailing_newline;
+    self
+  }
+
+  /// Specify the line width arrays have to fit in to stay inline,
+  /// used instead of `max_inline_array_length` when set.
+  pub const fn max_line_width(mut self, max_line_width: usize) -> Self {
+    self.max_line_width = Some(max_line_width);
+    self
+  }
+
+  /// Specify the most elements an array may have to stay inline, however long they are.
+  /// Used instead of `max_inline_array_length` and `max_line_width` when set.
+  pub const fn max_inline_array_elements(mut self, max_inline_array_elements: usize) -> Self {
+    self.max_inline_array_elements = Some(max_inline_array_elements);
+    self
+  }
+
+  /// Specify a maximum number of elements to render per array, for diagnostic output.
+  /// Longer arrays are cut off, followed by a `# … 9,987 more items` comment.
+  /// Arrays inside inline tables are written in full, as they can't hold comments.
+  pub const fn max_array_items(mut self, max_array_items: usize) -> Self {
+    self.max_array_items = Some(max_array_items);
+    self
+  }
+
+  /// Specify a maximum key depth (number of dotted segments) to render, for diagnostic output.
+  /// Deeper tables are replaced by an empty table at that depth,
+  /// with a `# (truncated: 4 more levels)` comment.
+  pub const fn truncate_depth(mut self, truncate_depth: usize) -> Self {
+    self.truncate_depth = Some(truncate_depth);
+    self
+  }
+
+  /// Specify `(old, new)` (dotted) key paths to migrate. Values at an old path,
+  /// along with everything nested beneath it, are written under the new path instead,
+  /// replacing any values already under the new path. A new path beneath an existing value
+  /// (eg. `b.c` with `b = 2`) is an [Error::PathConflict].
+  /// Useful for evolving config schemas. Comments and validators use the new paths.
+  pub const fn migrations(mut self, migrations: &'a [(&'a str, &'a str)]) -> Self {
+    self.migrations = migrations;
+    self
+  }
+
+  /// Specify whether to add a `# moved from old.key` comment to migrated keys.
+  pub const fn migration_comments(mut self, migration_comments: bool) -> Self {
+    self.migration_comments = migration_comments;
+    self
+  }
+
+  /// Specify `(old, new)` (dotted) key paths to write under both: the values at the new path
+  /// (along with everything nested beneath it) are also written under the old path,
+  /// with a `# deprecated: renamed to new.key` comment, for readers not yet updated to the new path.
+  /// Values already under the old path, or a value above it (eg. `a = 1` for `a.b`),
+  /// are an [Error::PathConflict].
+  /// Applied after [Options::migrations], so a migration and an alias can share paths.
+  pub const fn aliases(mut self, aliases: &'a [(&'a str, &'a str)]) -> Self {
+    self.aliases = aliases;
+    self
+  }
+
+  /// Specify deprecation messages keyed by path, usually from [Deprecated::deprecated].
+  /// Each is written as a `# deprecated: use new_name instead` comment above the key.
+  pub const fn deprecated(mut self, deprecated: &'a CommentMap) -> Self {
+    self.deprecated = Some(deprecated);
+    self
+  }
+
+  /// Specify whether to leave out deprecated keys entirely (strict mode), rather than commenting them.
+  pub const fn omit_deprecated(mut self, omit_deprecated: bool) -> Self {
+    self.omit_deprecated = omit_deprecated;
+    self
+  }
+
+  /// Specify whether to write arrays of tables as `[[array]]` sections in header mode,
+  /// rather than arrays of inline tables. Comments for an entry's keys use paths like `servers[0].name`.
+  pub const fn array_tables(mut self, array_tables: bool) -> Self {
+    self.array_tables = array_tables;
+    self
+  }
+
+  /// Specify whether to put a blank line between the entries of an array of tables.
+  pub const fn array_table_blank_lines(mut self, array_table_blank_lines: bool) -> Self {
+    self.array_table_blank_lines = array_table_blank_lines;
+    self
+  }
+
+  /// Specify a comment to repeat above every `[[array]]` entry. `{index}` is replaced by the entry's index.
+  pub const fn array_table_comment(mut self, array_table_comment: &'a str) -> Self {
+    self.array_table_comment = Some(array_table_comment);
+    self
+  }
+
+  /// Specify whether to align the `=` of the keys of `[[array]]` entries, across all the entries of the array.
+  pub const fn align_array_tables(mut self, align_array_tables: bool) -> Self {
+    self.align_array_tables = align_array_tables;
+    self
+  }
+
+  /// Specify whether to right align the numbers of multiline numeric arrays,
+  /// and the columns of matrices (arrays of numeric arrays), for visual comparison.
+  pub const fn align_numbers(mut self, align_numbers: bool) -> Self {
+    self.align_numbers = align_numbers;
+    self
+  }
+
+  /// Specify a column (in characters from the start of the line) to write single line key comments
+  /// at the end of the key's line instead of above it, so they line up vertically.
+  /// Lines already reaching the column get a single space before the `#`.
+  /// Multiline comments, and the comments of multiline values, are still written above the key.
+  pub const fn comment_column(mut self, comment_column: usize) -> Self {
+    self.comment_column = Some(comment_column);
+    self
+  }
+
+  /// Specify whether to end the document with a `# content-hash: sha256:...` comment,
+  /// the hash of everything before it (after `post_process`), so tooling can detect
+  /// hand edits of generated files with [verify_content_hash].
+  pub const fn content_hash(mut self, content_hash: bool) -> Self {
+    self.content_hash = content_hash;
+    self
+  }
+
+  /// Specify whether to indent the lines of multiline strings by `tab`, rather than writing them flush left.
+  /// Each line ends with a `\` line continuation, which drops the indentation from the value.
+  /// Apart from with [MultilineStart::Inline], the opening delimiter is written as `"""\`.
+  pub const fn multiline_indent(mut self, multiline_indent: bool) -> Self {
+    self.multiline_indent = multiline_indent;
+    self
+  }
+
+  /// Specify where the content of multiline strings starts. Default is [MultilineStart::Newline]
+  pub const fn multiline_start(mut self, multiline_start: MultilineStart) -> Self {
+    self.multiline_start = multiline_start;
+    self
+  }
+
+  /// Specify the [Formatter] to write keys, values and headers with,
+  /// eg. to colorize the output. Default is [DefaultFormatter].
+  pub const fn formatter(mut self, formatter: &'a dyn Formatter) -> Self {
+    self.formatter = formatter;
+    self
+  }
+
+  /// Check the options for settings which can't produce valid / coherent output.
+  /// This is run automatically before serializing.
+  pub fn validate(&self) -> Result<()> {
+    if !self.tab.chars().all(|c| c == ' ' || c == '\t') {
+      return Err(Error::InvalidOptions(format!(
+        "tab must only contain spaces and tabs, got {:?}",
+        self.tab
+      )));
+    }
+    if let Some(array_indent) = self
+      .array_indent
+      .filter(|indent| !indent.chars().all(|c| c == ' ' || c == '\t'))
+    {
+      return Err(Error::InvalidOptions(format!(
+        "array_indent must only contain spaces and tabs, got {array_indent:?}"
+      )));
+    }
+    if self.empty_table == EmptyTable::Header && !self.table_headers {
+      return Err(Error::InvalidOptions(String::from(
+        "EmptyTable::Header requires table_headers",
+      )));
+    }
+    if self.super_tables && !self.table_headers {
+      return Err(Error::InvalidOptions(String::from(
+        "super_tables requires table_headers",
+      )));
+    }
+    if self.collapse_tables && !self.super_tables {
+      return Err(Error::InvalidOptions(String::from(
+        "collapse_tables requires super_tables",
+      )));
+    }
+    if self.dotted_depth > 0 && !self.table_headers {
+      return Err(Error::InvalidOptions(String::from(
+        "dotted_depth requires table_headers",
+      )));
+    }
+    if self
+      .migrations
+      .iter()
+      .any(|(old, new)| old.is_empty() || new.is_empty())
+    {
+      return Err(Error::InvalidOptions(String::from(
+        "migration paths must not be empty",
+      )));
+    }
+    if let Some((old, new)) = self.aliases.iter().find(|(old, new)| {
+      old.is_empty() || new.is_empty() || is_under(old, new) || is_under(new, old)
+    }) {
+      return Err(Error::InvalidOptions(format!(
+        "alias paths must not be empty or nested in each other, got {old:?} and {new:?}"
+      )));
+    }
+    if self.max_depth == Some(0) {
+      return Err(Error::InvalidOptions(String::from(
+        "max_depth must be at least 1",
+      )));
+    }
+    if self.truncate_depth == Some(0) {
+      return Err(Error::InvalidOptions(String::from(
+        "truncate_depth must be at least 1",
+      )));
+    }
+    if self.string_tabs == StringTabs::Expand(0) {
+      return Err(Error::InvalidOptions(String::from(
+        "StringTabs::Expand width must be at least 1",
+      )));
+    }
+    if let DatetimeOffset::Fixed(minutes) = self.datetime_offset {
+      if minutes.unsigned_abs() >= 24 * 60 {
+        return Err(Error::InvalidOptions(format!(
+          "DatetimeOffset::Fixed must be less than a day, got {minutes} minutes"
+        )));
+      }
+    }
+    if self.validators.iter().any(|(path, _)| path.is_empty()) {
+      return Err(Error::InvalidOptions(String::from(
+        "validator paths must not be empty",
+      )));
+    }
+    for (path, _) in self.validators {
+      key_path(path)
+        .ok_or_else(|| Error::InvalidOptions(format!("invalid validator path {path:?}")))?;
+    }
+    for path in self.secrets {
+      key_path(path)
+        .ok_or_else(|| Error::InvalidOptions(format!("invalid secret path {path:?}")))?;
+    }
+    Ok(())
+  }
+}
+
+/// Serialize the value to a pretty toml string.
+/// Keys are written in serialization order (see [Options::ordering]),
+/// independent of serde_json's `preserve_order` feature.
+/// `T` may be unsized, so trait objects which implement [Serialize]
+/// (eg. `&dyn erased_serde::Serialize`) can be passed directly.
+pub fn to_string<T: Serialize + ?Sized>(value: &T, options: Options<'_>) -> Result<String> {
+  render(&flatten_map(to_map(value, options)?), options)
+}
+
+/// An object safe counterpart of [Serialize], for values whose types aren't known at compile time,
+/// eg. the boxed config objects of plugins (`Vec<Box<dyn SerializeDyn>>`). Implemented for every [Serialize] type.
+/// Write one with [to_string_dyn]. `dyn SerializeDyn` also implements [Serialize], so it can be nested
+/// in other values, in which case it's serialized with the default serialization options.
+/// (`&dyn erased_serde::Serialize` implements [Serialize] itself, so it can be passed to [to_string].)
+pub trait SerializeDyn {
+  /// Serialize into a [Value], with the serialization options (eg. [Options::big_integers]) of `options`.
+  fn to_value(&self, options: Options<'_>) -> Result<Value>;
+}
+
+impl<T: Serialize> SerializeDyn for T {
+  fn to_value(&self, options: Options<'_>) -> Result<Value> {
+    to_value(self, options)
+  }
+}
+
+impl Serialize for dyn SerializeDyn + '_ {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    self
+      .to_value(Options::default())
+      .map_err(serde::ser::Error::custom)?
+      .serialize(serializer)
+  }
+}
+
+/// Like [to_string], for a value behind a `dyn SerializeDyn`.
+pub fn to_string_dyn(value: &dyn SerializeDyn, options: Options<'_>) -> Result<String> {
+  render(
+    &flatten_map(top_level_map(value.to_value(options)?)?),
+    options,
+  )
+}
+
+/// Like [to_string], also returning the lossy decisions which were made (eg. a NaN dropped,
+/// a key quoted), so they can be surfaced to users rather than silently altering data.
+pub fn to_string_with_warnings<T: Serialize + ?Sized>(
+  value: &T,
+  options: Options<'_>,
+) -> Result<(String, Vec<Warning>)> {
+  let diagnostics = RefCell::new(Diagnostics::default());
+  let val = to_value_with(value, options, Some(&diagnostics))?;
+  let mut warnings = diagnostics.into_inner().warnings;
+  collect_warnings(&val, "", &mut warnings);
+  let res = render(&flatten_map(top_level_map(val)?), options)?;
+  Ok((res, warnings))
+}
+
+/// Like [to_string], also returning where the value of each key is in the output,
+/// so errors (eg. from a downstream validator) can point at the exact location in the file.
+/// Keys inside inline tables / arrays aren't included, only the key holding the whole value.
+pub fn to_string_with_source_map<T: Serialize + ?Sized>(
+  value: &T,
+  options: Options<'_>,
+) -> Result<(String, SourceMap)> {
+  let res = to_string(value, options)?;
+  let layout = parse::parse_layout(&res)?;
+  let mut source_map = SourceMap::with_capacity(layout.entries.len());
+  // The entries are in document order, so the line / column is counted on from the last one.
+  let (mut pos, mut line, mut column) = (0, 1, 1);
+  for entry in layout.entries {
+    for c in res[pos..entry.value.start].chars() {
+      if c == '\n' {
+        line += 1;
+        column = 1;
+      } else {
+        column += 1;
+      }
+    }
+    pos = entry.value.start;
+    let span = Span {
+      line,
+      column,
+      length: res[entry.value].chars().count(),
+    };
+    source_map.insert(entry.path, span);
+  }
+  Ok((res, source_map))
+}
+
+/// Explain the formatting decisions [to_string] makes for the value: why each table is written
+/// under a header or with dotted keys, and why each array is inline or expanded
+/// (which threshold fired). Returns `(path, decision)` pairs in document order.
+pub fn explain<T: Serialize + ?Sized>(
+  value: &T,
+  options: Options<'_>,
+) -> Result<Vec<(String, String)>> {
+  options.validate()?;
+  let mut map = flatten_map(to_map(value, options)?);
+  if options.nested_empty_table != NestedEmptyTable::Innermost {
+    map = place_empty_tables(map.into_iter().collect(), options.nested_empty_table)
+      .into_iter()
+      .collect();
+  }
+  let mut res = Vec::new();
+  if !options.dependency_tables.is_empty() {
+    let mut tables = HashSet::new();
+    for key in map.keys() {
+      if let Some((table, _, _)) = split_dependency(key, options.dependency_tables) {
+        if tables.insert(table) {
+          res.push((
+            table.to_string(),
+            String::from("dependency table: matched dependency_tables"),
+          ));
+        }
+      }
+    }
+    map = format_dependencies(&map, options.dependency_tables);
+  }
+  let deepest = deepest_tables(&map, options);
+  let mut tables = HashSet::new();
+  for (key, val) in &map {
+    let (table, _) = split_last_segment(key);
+    if !table.is_empty() && tables.insert(table) {
+      res.push((table.to_string(), table_decision(table, &deepest, options)));
+    }
+    match val {
+      Value::Array(_) if array_of_tables(val, options).is_some() => {
+        res.push((
+          key.clone(),
+          String::from("[[array]] sections: array_tables is set"),
+        ));
+      }
+      Value::Array(vals) if !vals.is_empty() => {
+        let (strs, comments, truncated) = array_elements(key, vals, options)?;
+        // The key as written on the array's line, below its table header if any.
+        let line_key = match split_last_segment(key) {
+          (table, _) if options.table_headers && table_depth(table) > options.dotted_depth => {
+            &key[header_table(table, &deepest, options).len() + 1..]
+          }
+          _ => key.as_str(),
+        };
+        let width = written_key(line_key, options).chars().count() + " = ".len();
+        let layout = array_layout(width, &strs, &comments, truncated, options);
+        res.push((key.clone(), layout.to_string()));
+      }
+      Value::Object(map) if map.is_empty() => {
+        let decision = match options.empty_table {
+          EmptyTable::Skip => "empty table: skipped by EmptyTable::Skip",
+          EmptyTable::Inline => "empty table: written as {} by EmptyTable::Inline",
+          EmptyTable::Header if options.table_headers => {
+            "empty table: written as a header by EmptyTable::Header"
+          }
+          EmptyTable::Header => "empty table: written as {}, as table_headers is off",
+        };
+        res.push((key.clone(), decision.to_string()));
+      }
+      _ => {}
+    }
+  }
+  Ok(res)
+}
+
+fn table_decision(table: &str, deepest: &HashMap<&str, usize>, options: Options<'_>) -> String {
+  if !options.table_headers {
+    return String::from("dotted keys: table_headers is off");
+  }
+  let depth = table_depth(table);
+  let header = header_table(table, deepest, options);
+  if depth > options.dotted_depth && header != table {
+    format!(
+      "dotted keys under [{header}]: within header_dotted_depth {}",
+      options.header_dotted_depth
+    )
+  } else if depth > options.dotted_depth {
+    format!(
+      "header: depth {depth} > dotted_depth {}",
+      options.dotted_depth
+    )
+  } else {
+    format!(
+      "dotted keys: depth {depth} <= dotted_depth {}",
+      options.dotted_depth
+    )
+  }
+}
+
+/// The output of [to_string_lenient].
+#[derive(Debug)]
+pub struct Partial {
+  /// The toml, without the values which couldn't be serialized / rendered.
+  pub output: String,
+  /// The skipped problems, with the path they occurred at (empty for the whole document).
+  pub errors: Vec<(String, Error)>,
+}
+
+/// Like [to_string], but keeps going past recoverable problems (eg. an unsupported value,
+/// a non string map key, a failed validator), skipping the values involved.
+/// Intended for diagnostic dumps, which should never fail entirely.
+/// Invalid options are reported, and the default options used instead.
+pub fn to_string_lenient<T: Serialize + ?Sized>(value: &T, options: Options<'_>) -> Partial {
+  let mut errors = Vec::new();
+  let options = match options.validate() {
+    Ok(()) => options,
+    Err(e) => {
+      errors.push((String::new(), e));
+      Options::default()
+    }
+  };
+  let diagnostics = RefCell::new(Diagnostics::lenient());
+  let map = to_value_with(value, options, Some(&diagnostics)).and_then(top_level_map);
+  errors.append(&mut diagnostics.into_inner().errors);
+  let mut map = match map {
+    Ok(map) => flatten_map(map),
+    Err(e) => {
+      errors.push((String::new(), e));
+      return Partial {
+        output: String::new(),
+        errors,
+      };
+    }
+  };
+  // Drop the values which fail validation, along with the whole array they're in.
+  for e in validation_errors(&map, options) {
+    let path = e.path().unwrap_or_default().to_string();
+    let dropped = &path[..path.find('[').unwrap_or(path.len())];
+    map = map
+      .into_iter()
+      .filter(|(key, _)| !is_under(key, dropped))
+      .collect();
+    errors.push((path, e));
+  }
+  // Drop the entries which fail to render on their own.
+  let mut failed = Vec::new();
+  for (key, val) in &map {
+    let entry = [(key.clone(), val.clone())].into_iter().collect::<Map>();
+    if let Err(e) = render_entries(&entry, options) {
+      failed.push((key.clone(), e));
+    }
+  }
+  for (key, e) in failed {
+    map.remove(&key);
+    errors.push((key, e));
+  }
+  let output = render(&map, options).unwrap_or_else(|e| {
+    errors.push((String::new(), e));
+    String::new()
+  });
+  Partial { output, errors }
+}
+
+/// Serialize using a fixed, fully deterministic style intended for snapshot / golden file tests.
+/// Keys are sorted, and the style does not depend on any [Options],
+/// so tweaking options in application code doesn't churn every snapshot.
+pub fn to_string_normalized<T: Serialize + ?Sized>(value: &T) -> Result<String> {
+  let mut document = Document::new(value)?;
+  document.sort_keys();
+  document.to_string(
+    Options::default()
+      .tab("  ")
+      .skip_empty_string(false)
+      .inline_array(false)
+      .max_inline_array_length(50),
+  )
+}
+
+/// Serialize the value to minified toml, for sending configs over constrained channels:
+/// a `key=value` line per root key, with every table and array written inline, without spaces.
+/// Options which decide the values / escaping (eg. `ordering`, `key_transform`, `secrets`,
+/// `validators`, `migrations`, `truncate_depth`) apply as in [to_string],
+/// while layout options (eg. `table_headers`, `comments`) are ignored.
+pub fn to_string_compact<T: Serialize + ?Sized>(value: &T, options: Options<'_>) -> Result<String> {
+  let flattened = flatten_map(to_map(value, options)?);
+  let prepared = prepare(&flattened, options)?;
+  let options = prepared.options(options);
+  let mut map = Map::with_capacity(prepared.map.len());
+  for (key, val) in prepared.map.iter() {
+    insert_path(&mut map, key, val.clone());
+  }
+  let mut res = String::new();
+  for (key, val) in &map {
+    if !is_written(val, options) {
+      continue;
+    }
+    if !res.is_empty() {
+      res.push('\n');
+    }
+    let key = quote_key(key);
+    options
+      .formatter
+      .write_key(&mut res, &written_key(&key, options));
+    res.push('=');
+    write_compact(&mut res, val, options);
+    check_output_size(&res, &key, options)?;
+  }
+  Ok(res)
+}
+
+fn write_compact(res: &mut String, val: &Value, options: Options<'_>) {
+  // An explicit stack rather than recursion, so deeply nested values can't overflow the stack.
+  let mut stack = Vec::<Compact>::new();
+  let mut next = Some(val);
+  loop {
+    if let Some(val) = next.take() {
+      match val {
+        Value::Array(vals) => {
+          options.formatter.begin_array(res);
+          stack.push(Compact::Array(vals.iter(), false));
+        }
+        Value::Object(map) => {
+          options.formatter.begin_inline_table(res);
+          stack.push(Compact::Table(map.iter(), false));
+        }
+        val => write_scalar(res, val, single_line_string_to_string, options),
+      }
+    }
+    let Some(frame) = stack.last_mut() else {
+      return;
+    };
+    match frame {
+      Compact::Array(vals, written) => match vals.find(|val| is_written(val, options)) {
+        Some(val) => {
+          if std::mem::replace(written, true) {
+            res.push(',');
+          }
+          next = Some(val);
+        }
+        None => {
+          options.formatter.end_array(res);
+          stack.pop();
+        }
+      },
+      Compact::Table(entries, written) => match entries.find(|(_, val)| is_written(val, options)) {
+        Some((key, val)) => {
+          if std::mem::replace(written, true) {
+            res.push(',');
+          }
+          let key = quote_key(key);
+          options
+            .formatter
+            .write_key(res, &written_key(&key, options));
+          res.push('=');
+          next = Some(val);
+        }
+        None => {
+          options.formatter.end_inline_table(res);
+          stack.pop();
+        }
+      },
+    }
+  }
+}
+
+/// An array / table being written by [write_compact], with whether anything was written yet.
+enum Compact<'v> {
+  Array(std::slice::Iter<'v, Value>, bool),
+  Table(ordered_hash_map::ordered_map::Iter<'v, String, Value>, bool),
+}
+
+/// Serialize the value, then parse the output back and check it matches the value.
+/// The parser follows the toml spec, so invalid output (eg. a table defined twice) is an error too.
+/// Useful as the assertion in property tests / fuzzing of toml generating pipelines,
+/// eg. with the values of a `Generator` (`arbitrary` feature).
+/// Note that options which drop values (eg. `skip_empty_string`) will show up as differences.
+pub fn check_roundtrip<T: Serialize + ?Sized>(value: &T, options: Options<'_>) -> Result<()> {
+  let expected = Document::new(value)?;
+  let diff = expected.diff(&Document::parse(&to_string(value, options)?)?);
+  if diff.is_empty() {
+    Ok(())
+  } else {
+    Err(Error::Roundtrip(diff.join("\n")))
+  }
+}
+
+/// Parse toml text into its root table, keeping the order of the keys.
+/// Datetimes and special floats (`inf`, `nan`) are kept as their toml text.
+pub fn from_str(input: &str) -> Result<Map> {
+  parse::parse(input)
+}
+
+/// Re-render an existing toml file (eg. a hand edited generated config) with the values of `value`,
+/// keeping its comments, blank lines and key order. Only the values are rewritten:
+/// keys missing from `value` are removed, and new keys are added at the end of their table.
+/// Arrays of tables (`[[array]]`) are left as they are in the existing file.
+pub fn render_template<T: Serialize + ?Sized>(
+  existing: &str,
+  value: &T,
+  options: Options<'_>,
+) -> Result<String> {
+  options.validate()?;
+  let layout = parse::parse_layout(existing)?;
+  let mut map = flatten_map(to_map(value, options)?);
+  // (range of existing to replace, replacement)
+  let mut edits = Vec::<(std::ops::Range<usize>, String)>::new();
+  for header in layout.headers.iter().filter(|header| header.array) {
+    map = map
+      .into_iter()
+      .filter(|(key, _)| !is_under(key, &header.path))
+      .collect();
+  }
+  for entry in &layout.entries {
+    if entry.path.contains('[') {
+      continue;
+    }
+    let val = match map.remove(&entry.path) {
+      Some(val) => {
+        let width = existing[entry.line.start..entry.value.start]
+          .chars()
+          .count();
+        entry_value(&entry.path, width, &val, options)?
+      }
+      None => {
+        // eg. an inline table in the existing file, which is flattened in the value.
+        let mut table = Map::new();
+        let nested = map
+          .keys()
+          .filter(|key| is_under(key, &entry.path))
+          .cloned()
+          .collect::<Vec<_>>();
+        for key in nested {
+          if let Some(val) = map.remove(&key) {
+            insert_path(&mut table, &key[entry.path.len() + 1..], val);
+          }
+        }
+        if table.is_empty() {
+          None
+        } else {
+          Some(inline_table_to_string(
+            &table,
+            &mut KeyCache::default(),
+            options,
+          )?)
+        }
+      }
+    };
+    match val {
+      Some(val) => edits.push((entry.value.clone(), val)),
+      None => edits.push((entry.line.clone(), String::new())),
+    }
+  }
+  for (path, val) in &map {
+    let header = layout
+      .headers
+      .iter()
+      .filter(|header| !header.array && is_under(path, &header.path) && path != &header.path)
+      .max_by_key(|header| header.path.len());
+    let (end, key) = match header {
+      Some(header) => (header.end, &path[header.path.len() + 1..]),
+      None => (layout.root_end, path.as_str()),
+    };
+    let width = written_key(key, options).chars().count() + " = ".len();
+    let Some(val) = entry_value(path, width, val, options)? else {
+      continue;
+    };
+    let newline = if end > 0 && !existing[..end].ends_with('\n') {
+      "\n"
+    } else {
+      ""
+    };
+    let mut line = String::from(newline);
+    options
+      .formatter
+      .write_key(&mut line, &written_key(key, options));
+    line.push_str(" = ");
+    line.push_str(&val);
+    line.push('\n');
+    edits.push((end..end, line));
+  }
+  // Stable, so new keys at the same position keep their order.
+  edits.sort_by_key(|(range, _)| range.start);
+  let mut res = String::with_capacity(existing.len());
+  let mut pos = 0;
+  for (range, replacement) in edits {
+    res.push_str(&existing[pos..range.start]);
+    res.push_str(&replacement);
+    pos = range.end;
+  }
+  res.push_str(&existing[pos..]);
+  Ok(res)
+}
+
+/// Extract the comments of existing toml text, keyed by path,
+/// so they can be re-attached to freshly serialized output with [Options::comments].
+/// Comments go to the key / table header / array element below them, or on the same line.
+/// Comments followed by a blank line (eg. a license header) are left out.
+pub fn extract_comments(input: &str) -> Result<CommentMap> {
+  parse::parse_comments(input)
+}
+
+/// Convert json text to pretty toml, for pipelines without Rust types for the data.
+/// Keys keep their order in the json. The top level must be an object.
+#[cfg(feature = "json")]
+pub fn json_to_toml(input: &str, options: Options<'_>) -> Result<String> {
+  let value = serde_json::from_str::<Value>(input)?;
+  to_string(&value, options)
+}
+
+/// Convert JSON5 / JSONC text to pretty toml, keeping the comments.
+/// Comments above a key / array element or after it on the same line are written above it,
+/// unless `options.comments` has a comment for the same path.
+#[cfg(feature = "json5")]
+pub fn json5_to_toml(input: &str, options: Options<'_>) -> Result<String> {
+  let (value, comments) = json5::parse(input)?;
+  to_string_with_input_comments(&value, comments, options)
+}
+
+/// Convert YAML text (eg. a docker compose style config) to pretty toml, keeping the comments.
+/// Anchors / aliases and `<<` merge keys are resolved, so the toml holds the merged values.
+/// Like json, null values (eg. a key with nothing after its `:`) are left out, as are empty
+/// mappings unless kept with [Options::empty_table]. `.inf` / `.nan` are written as toml's `inf` / `nan`.
+/// Comments are carried over like [json5_to_toml]. Multiple documents aren't supported.
+#[cfg(feature = "yaml")]
+pub fn yaml_to_toml(input: &str, options: Options<'_>) -> Result<String> {
+  let (value, comments) = yaml::parse(input)?;
+  to_string_with_input_comments(&value, comments, options)
+}
+
+/// Renders a parsed input along with its comments, which `options.comments` take precedence over.
+#[cfg(any(feature = "json5", feature = "yaml"))]
+fn to_string_with_input_comments(
+  value: &Value,
+  mut comments: CommentMap,
+  options: Options<'_>,
+) -> Result<String> {
+  for (path, comment) in options.comments.into_iter().flatten() {
+    match comments.get_mut(path) {
+      Some(existing) => existing.clone_from(comment),
+      None => {
+        comments.insert(path.clone(), comment.clone());
+      }
+    }
+  }
+  to_string(
+    value,
+    Options {
+      comments: Some(&comments),
+      ..options
+    },
+  )
+}
+
+/// Convert data in any serde format (msgpack, CBOR, YAML, ...) to pretty toml, via its [serde::Deserializer].
+/// The data is read into a [Value] first, since tables can only be formatted once they are complete.
+pub fn transcode_to_toml<'de, D: serde::Deserializer<'de>, W: std::io::Write>(
+  deserializer: D,
+  mut writer: W,
+  options: Options<'_>,
+) -> Result<()> {
+  let value = <Value as serde::Deserialize>::deserialize(deserializer)
+    .map_err(|e| Error::Custom(e.to_string()))?;
+  writer.write_all(to_string(&value, options)?.as_bytes())?;
+  Ok(())
+}
+
+/// Serialize the value to a pretty toml file, see [to_string].
+/// The file is replaced at once (by renaming a temporary file over it, synced to disk first),
+/// so readers never see a partially written file. The permissions of an existing file are kept.
+/// See [FileOptions] for locking and backups.
+pub fn to_file<T: Serialize + ?Sized>(
+  value: &T,
+  path: impl AsRef<Path>,
+  options: Options<'_>,
+  file_options: FileOptions<'_>,
+) -> Result<()> {
+  let path = path.as_ref();
+  let res = to_string(value, options)?;
+  let with_suffix = |suffix: &str| {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+  };
+  // Held until the end of the function. The lock is on a sibling file, as the config itself is replaced.
+  let _lock = if file_options.lock {
+    let lock = File::options()
+      .create(true)
+      .truncate(false)
+      .write(true)
+      .open(with_suffix(".lock"))?;
+    lock.lock()?;
+    Some(lock)
+  } else {
+    None
+  };
+  if let Some(suffix) = file_options.backup {
+    if path.exists() {
+      std::fs::copy(path, with_suffix(suffix))?;
+    }
+  }
+  // Unique per writer, so writers without the lock don't share a temporary file.
+  static WRITES: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+  let write = WRITES.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+  let tmp = with_suffix(&format!(".{}.{write}.tmp", std::process::id()));
+  let written = (|| {
+    let mut file = File::create_new(&tmp)?;
+    // Keep the mode of the existing file (eg. 0600 for secrets), before anything is written.
+    if let Ok(metadata) = std::fs::metadata(path) {
+      file.set_permissions(metadata.permissions())?;
+    }
+    std::io::Write::write_all(&mut file, res.as_bytes())?;
+    // On disk before the rename, so a crash can't leave an empty file in place of the config.
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)
+  })();
+  if written.is_err() {
+    let _ = std::fs::remove_file(&tmp);
+  }
+  written.map_err(Error::Io)
+}
+
+/// Serialize only the table at the (dotted) `path`, as if it were the document root.
+/// eg. to emit per component config fragments from one master struct.
+/// Paths in the options (comments, secrets, ...) are relative to the subtree,
+/// apart from [Ordering] priority paths, which are ordered before extracting the subtree.
+pub fn render_subtree<T: Serialize + ?Sized>(
+  value: &T,
+  path: &str,
+  options: Options<'_>,
+) -> Result<String> {
+  let mut val = to_value(value, options)?;
+  let segments = split_key(path).map_err(|_| Error::PathNotFound(path.to_string()))?;
+  for segment in &segments {
+    val = match val {
+      Value::Object(mut map) => map
+        .remove(segment)
+        .ok_or_else(|| Error::PathNotFound(path.to_string()))?,
+      _ => return Err(Error::PathNotFound(path.to_string())),
+    };
+  }
+  match val {
+    Value::Object(map) => render(&flatten_map(map), options),
+    _ => Err(Error::PathNotFound(path.to_string())),
+  }
+}
+
+/// Serialize entries to the writer as they are produced, so huge generated documents
+/// (eg. database exports) never need to be in memory at once.
+/// Each entry is formatted like a top level key of [to_string], and the `footer` is written after the last.
+/// In header mode, entries which are tables must come after all the other entries,
+/// since they can't be reordered. Each key may only be produced once, which is checked by
+/// keeping the keys (not the values) seen so far. `post_process` needs the whole document, so isn't supported.
+pub fn serialize_iter<T: Serialize, W: std::io::Write>(
+  iter: impl IntoIterator<Item = (String, T)>,
+  mut writer: W,
+  options: Options<'_>,
+) -> Result<()> {
+  options.validate()?;
+  if options.post_process.is_some() {
+    return Err(Error::InvalidOptions(String::from(
+      "post_process is not supported by serialize_iter",
+    )));
+  }
+  let mut written = false;
+  let mut opened_table = None::<String>;
+  let mut keys = HashSet::new();
+  for (key, val) in iter {
+    let map = flatten_map(to_map(&Entry(&key, &val), options)?);
+    let Some(root) = map
+      .keys()
+      .next()
+      .map(|key| key[..first_segment_len(key)].to_string())
+    else {
+      continue;
+    };
+    if !keys.insert(root.clone()) {
+      return Err(Error::DuplicateKey { key: root });
+    }
+    let rendered = map
+      .iter()
+      .filter(|(_, val)| renders(val, options))
+      .map(|(key, val)| is_header_entry(key, val, options));
+    let (mut root_lines, mut tables) = (false, false);
+    for header in rendered {
+      root_lines |= !header;
+      tables |= header;
+    }
+    if root_lines {
+      if let Some(table) = &opened_table {
+        return Err(Error::TableOrder {
+          key: root,
+          table: table.clone(),
+        });
+      }
+    }
+    if tables && opened_table.is_none() {
+      opened_table = Some(root.clone());
+    }
+    // Only the examples for this entry, so missing examples aren't added for every entry.
+    let examples = options
+      .examples
+      .iter()
+      .filter(|(path, _)| is_under(path, &root))
+      .copied()
+      .collect::<Vec<_>>();
+    let chunk = render(
+      &map,
+      Options {
+        footer: None,
+        examples: &examples,
+        ..options
+      },
+    )?;
+    if chunk.is_empty() {
+      continue;
+    }
+    if written {
+      writer.write_all(if root_lines { b"\n" } else { b"\n\n" })?;
+    }
+    writer.write_all(chunk.as_bytes())?;
+    written = true;
+  }
+  if let Some(footer) = options.footer.filter(|footer| !footer.is_empty()) {
+    let mut res = String::new();
+    write_comment(&mut res, footer, "");
+    res.pop();
+    if written {
+      writer.write_all(b"\n\n")?;
+    }
+    writer.write_all(res.as_bytes())?;
+    written = true;
+  }
+  if options.trailing_newline && written {
+    writer.write_all(b"\n")?;
+  }
+  Ok(())
+}
+
+/// Serialize the value to pretty toml a line at a time, rendered as the lines are pulled,
+/// eg. to stream a huge generated document as an HTTP response body.
+/// Each line keeps its `\n`, so writing the lines out as they come gives the output of [to_string].
+/// The value is still serialized up front, but only the output of one root key is held at once.
+/// `post_process` and `content_hash` need the whole document, so aren't supported.
+pub fn render_lines<'a, T: Serialize + ?Sized>(
+  value: &T,
+  options: Options<'a>,
+) -> impl Iterator<Item = Result<String>> + 'a {
+  RenderLines::new(value, options).unwrap_or_else(|error| RenderLines {
+    error: Some(error),
+    ..RenderLines::empty(options)
+  })
+}
+
+/// The iterator of [render_lines].
+struct RenderLines<'a> {
+  options: Options<'a>,
+  /// The comments along with the markers added while preparing the document.
+  comments: Option<CommentMap>,
+  /// The entries of each root key, with the ones under `[table]` headers
+  /// after all the others, and whether they are.
+  chunks: std::vec::IntoIter<(Map, bool)>,
+  /// The output not yielded yet, from `pos`.
+  buffer: String,
+  pos: usize,
+  /// The length of the output so far, for `max_output_bytes`.
+  len: usize,
+  written: bool,
+  /// Whether the end of the document is in the buffer.
+  finished: bool,
+  error: Option<Error>,
+}
+
+impl<'a> RenderLines<'a> {
+  fn empty(options: Options<'a>) -> Self {
+    RenderLines {
+      options,
+      comments: None,
+      chunks: Vec::new().into_iter(),
+      buffer: String::new(),
+      pos: 0,
+      len: 0,
+      written: false,
+      finished: false,
+      error: None,
+    }
+  }
+
+  fn new<T: Serialize + ?Sized>(value: &T, options: Options<'a>) -> Result<Self> {
+    for (name, unsupported) in [
+      ("post_process", options.post_process.is_some()),
+      ("content_hash", options.content_hash),
+    ] {
+      if unsupported {
+        return Err(Error::InvalidOptions(format!(
+          "{name} is not supported by render_lines"
+        )));
+      }
+    }
+    let map = flatten_map(to_map(value, options)?);
+    let Prepared {
+      map: prepared,
+      comments,
+      empty_table,
+    } = prepare(&map, options)?;
+    let map = match prepared {
+      Cow::Owned(prepared) => prepared,
+      borrowed @ Cow::Borrowed(_) => {
+        drop(borrowed);
+        map
+      }
+    };
+    let options = Options {
+      empty_table,
+      ..options
+    };
+    // Root keys are split between their root lines and the tables under headers,
+    // as no root line can follow a header.
+    let mut roots = OrderedHashMap::<String, (Map, Map)>::new();
+    for (key, val) in map {
+      let root = key[..first_segment_len(&key)].to_string();
+      let header = is_header_entry(&key, &val, options) || array_of_tables(&val, options).is_some();
+      if !roots.contains_key(&root) {
+        roots.insert(root.clone(), (Map::new(), Map::new()));
+      }
+      if let Some((lines, tables)) = roots.get_mut(&root) {
+        if header { tables } else { lines }.insert(key, val);
+      }
+    }
+    let (lines, tables): (Vec<_>, Vec<_>) = roots.into_iter().map(|(_, chunks)| chunks).unzip();
+    let chunks = lines
+      .into_iter()
+      .map(|map| (map, false))
+      .chain(tables.into_iter().map(|map| (map, true)))
+      .filter(|(map, _)| !map.is_empty())
+      .collect::<Vec<_>>();
+    Ok(RenderLines {
+      comments,
+      chunks: chunks.into_iter(),
+      ..RenderLines::empty(options)
+    })
+  }
+
+  /// Renders the next root key into the buffer, or the end of the document after the last.
+  fn fill(&mut self) -> Result<()> {
+    let start = self.buffer.len();
+    let path = match self.chunks.next() {
+      Some((map, header)) => {
+        let chunk = render_entries(
+          &map,
+          Options {
+            comments: self.comments.as_ref().or(self.options.comments),
+            ..self.options
+          },
+        )?;
+        if chunk.is_empty() {
+          return Ok(());
+        }
+        if self.written {
+          self.buffer.push_str(if header { "\n\n" } else { "\n" });
+        }
+        self.buffer.push_str(&chunk);
+        self.written = true;
+        map.keys().next().cloned().unwrap_or_default()
+      }
+      None => {
+        self.finished = true;
+        if let Some(footer) = self.options.footer.filter(|footer| !footer.is_empty()) {
+          if self.written {
+            self.buffer.push_str("\n\n");
+          }
+          write_comment(&mut self.buffer, footer, "");
+          self.buffer.pop();
+          self.written = true;
+        }
+        if self.options.trailing_newline && self.written {
+          self.buffer.push('\n');
+        }
+        String::from("(footer)")
+      }
+    };
+    self.len += self.buffer.len() - start;
+    match self.options.max_output_bytes {
+      Some(limit) if self.len > limit => Err(Error::OutputTooLarge { limit, path }),
+      _ => Ok(()),
+    }
+  }
+}
+
+impl Iterator for RenderLines<'_> {
+  type Item = Result<String>;
+
+  fn next(&mut self) -> Option<Result<String>> {
+    loop {
+      if let Some(error) = self.error.take() {
+        // Nothing more is yielded after an error.
+        *self = RenderLines {
+          finished: true,
+          ..RenderLines::empty(self.options)
+        };
+        return Some(Err(error));
+      }
+      if let Some(end) = self.buffer[self.pos..].find('\n') {
+        let line = self.buffer[self.pos..=self.pos + end].to_string();
+        self.pos += end + 1;
+        return Some(Ok(line));
+      }
+      if self.finished {
+        if self.pos == self.buffer.len() {
+          return None;
+        }
+        let line = self.buffer[self.pos..].to_string();
+        self.pos = self.buffer.len();
+        return Some(Ok(line));
+      }
+      // Only the last (unfinished) line is left.
+      self.buffer.drain(..self.pos);
+      self.pos = 0;
+      if let Err(error) = self.fill() {
+        self.error = Some(error);
+      }
+    }
+  }
+}
+
+/// Serializes as a map with a single entry.
+struct Entry<'a, T>(&'a str, &'a T);
+
+impl<T: Serialize> Serialize for Entry<'_, T> {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    use serde::ser::SerializeMap;
+    let mut map = serializer.serialize_map(Some(1))?;
+    map.serialize_entry(self.0, self.1)?;
+    map.end()
+  }
+}
+
+/// Moves the values at the old paths of the migrations (along with everything nested beneath them)
+/// to the new paths, keeping their position. Adds `moved from` comments to `comments` if given.
+fn migrate(
+  map: &Map,
+  migrations: &[(&str, &str)],
+  mut comments: Option<&mut CommentMap>,
+) -> Result<Map> {
+  // The new paths of the migrations with anything to move.
+  let mut moved = Vec::new();
+  let entries = map
+    .iter()
+    .map(
+      |(key, val)| match migrations.iter().find(|(old, _)| is_under(key, old)) {
+        Some((old, new)) => {
+          if let Some(comments) = comments.as_deref_mut() {
+            if !comments.contains_key(*new) {
+              comments.insert(new.to_string(), format!("moved from {old}"));
+            }
+          }
+          if !moved.contains(new) {
+            moved.push(*new);
+          }
+          (format!("{new}{}", &key[old.len()..]), val, true)
+        }
+        None => (key.clone(), val, false),
+      },
+    )
+    .collect::<Vec<_>>();
+  let mut res = Map::with_capacity(map.len());
+  for (key, val, migrated) in entries {
+    // A migrated value replaces the values already under the new path, whichever comes first.
+    if !migrated && moved.iter().any(|new| is_under(&key, new)) {
+      continue;
+    }
+    if let Some(new) = moved
+      .iter()
+      .find(|new| new.len() > key.len() && is_under(new, &key))
+    {
+      return Err(Error::PathConflict {
+        path: new.to_string(),
+        existing: key,
+      });
+    }
+    res.insert(key, val.clone());
+  }
+  Ok(res)
+}
+
+/// Copies the values at the new path of each `(old, new)` alias to the old path,
+/// right after the last value beneath the new path.
+fn alias(map: &Map, aliases: &[(&str, &str)], comments: &mut CommentMap) -> Result<Map> {
+  // The index of the last entry beneath each alias's new path.
+  let last = aliases
+    .iter()
+    .map(|(_, new)| map.keys().rposition(|key| is_under(key, new)))
+    .collect::<Vec<_>>();
+  for ((old, _), _) in aliases.iter().zip(&last).filter(|(_, last)| last.is_some()) {
+    // Values under the old path, or a value above it, would be replaced or make the toml invalid.
+    if let Some(key) = map
+      .keys()
+      .find(|key| is_under(key, old) || is_under(old, key))
+    {
+      return Err(Error::PathConflict {
+        path: old.to_string(),
+        existing: key.clone(),
+      });
+    }
+  }
+  let mut res = Map::with_capacity(map.len());
+  for (i, (key, val)) in map.iter().enumerate() {
+    res.insert(key.clone(), val.clone());
+    for ((old, new), _) in aliases
+      .iter()
+      .zip(&last)
+      .filter(|(_, last)| **last == Some(i))
+    {
+      for (key, val) in map.iter().filter(|(key, _)| is_under(key, new)) {
+        res.insert(format!("{old}{}", &key[new.len()..]), val.clone());
+      }
+      append_comment(
+        comments,
+        old.to_string(),
+        &format!("deprecated: renamed to {new}"),
+      );
+    }
+  }
+  Ok(res)
+}
+
+/// Adds `comment` to any comment `path` already has.
+pub(crate) fn append_comment(comments: &mut CommentMap, path: String, comment: &str) {
+  match comments.get_mut(&path) {
+    Some(existing) => {
+      existing.push('\n');
+      existing.push_str(comment);
+    }
+    None => {
+      comments.insert(path, comment.to_string());
+    }
+  }
+}
+
+/// Replaces the tables nested deeper than `depth` by empty tables at `depth`,
+/// returning the comments marking them.
+fn truncate_depth(map: &Map, depth: usize, options: Options<'_>) -> (Map, CommentMap) {
+  let mut res = Map::new();
+  let mut levels = OrderedHashMap::<&str, usize>::new();
+  for (key, val) in map {
+    let dots = segment_dots(key);
+    if dots.len() < depth {
+      // Empty tables still render (or not) as before.
+      if !(options.empty_table == EmptyTable::Skip && is_empty_table(val)) {
+        res.insert(key.clone(), val.clone());
+      }
+      continue;
+    }
+    let table = &key[..dots[depth - 1]];
+    let more = dots.len() + 1 - depth;
+    match levels.get_mut(table) {
+      Some(levels) => *levels = (*levels).max(more),
+      None => {
+        levels.insert(table, more);
+        res.insert(table.to_string(), Value::Object(Map::new()));
+      }
+    }
+  }
+  let markers = levels
+    .into_iter()
+    .map(|(table, more)| {
+      let levels = if more == 1 { "level" } else { "levels" };
+      (
+        table.to_string(),
+        format!("(truncated: {more} more {levels})"),
+      )
+    })
+    .collect();
+  (res, markers)
+}
+
+/// Whether the value produces any output.
+fn renders(val: &Value, options: Options<'_>) -> bool {
+  match val {
+    Value::Null => false,
+    Value::String(val) => !(options.skip_empty_string && val.is_empty()),
+    Value::Object(map) => !map.is_empty() || options.empty_table != EmptyTable::Skip,
+    _ => true,
+  }
+}
+
+/// Whether the entry goes under a `[table]` header, rather than being a root key.
+fn is_header_entry(key: &str, val: &Value, options: Options<'_>) -> bool {
+  if !options.table_headers {
+    false
+  } else if options.empty_table == EmptyTable::Header && is_empty_table(val) {
+    table_depth(key) > options.dotted_depth
+  } else {
+    table_depth(split_last_segment(key).0) > options.dotted_depth
+  }
+}
+
+fn to_map<T: Serialize + ?Sized>(value: &T, options: Options<'_>) -> Result<Map> {
+  top_level_map(to_value(value, options)?)
+}
+
+fn top_level_map(val: Value) -> Result<Map> {
+  match val {
+    Value::Object(map) => Ok(map),
+    _ => Err(Error::Custom(String::from(
+      "top level value must serialize to a struct or map",
+    ))),
+  }
+}
+
+/// The lossy decisions made when rendering `val`, see [Warning].
+fn collect_warnings(val: &Value, path: &str, warnings: &mut Vec<Warning>) {
+  match val {
+    Value::Array(vals) => {
+      for (i, val) in vals.iter().enumerate() {
+        collect_warnings(val, &format!("{path}[{i}]"), warnings);
+      }
+    }
+    Value::Object(map) => {
+      for (key, val) in map {
+        let path = join_path(path, key);
+        if matches!(quote_key(key), Cow::Owned(_)) {
+          warnings.push(Warning::KeyQuoted { path: path.clone() });
+        }
+        collect_warnings(val, &path, warnings);
+      }
+    }
+    _ => {}
+  }
+}
+
+fn to_value<T: Serialize + ?Sized>(value: &T, options: Options<'_>) -> Result<Value> {
+  to_value_with(value, options, None)
+}
+
+/// `diagnostics` collects warnings, and enables lenient mode if set.
+fn to_value_with<T: Serialize + ?Sized>(
+  value: &T,
+  options: Options<'_>,
+  diagnostics: Option<&RefCell<Diagnostics>>,
+) -> Result<Value> {
+  let keys = Cell::new(0);
+  let mut val = value.serialize(ValueSerializer {
+    sort_maps: options.ordering.alphabetical == Alphabetical::Maps,
+    non_utf8: options.non_utf8,
+    big_integers: options.big_integers,
+    max_depth: options.max_depth,
+    depth: 0,
+    max_keys: options.max_keys,
+    keys: Some(&keys),
+    diagnostics,
+  })?;
+  if let Some(key_transform) = options.key_transform {
+    transform_keys(&mut val, "", "", key_transform)?;
+  }
+  if !options.ordering.is_default() {
+    // The position of the first key beneath each table / key of the reference.
+    let mut reference = HashMap::new();
+    for (i, key) in options
+      .ordering
+      .reference
+      .iter()
+      .flat_map(|doc| doc.keys())
+      .enumerate()
+    {
+      for end in segment_dots(key).into_iter().chain([key.len()]) {
+        reference.entry(key[..end].to_string()).or_insert(i);
+      }
+    }
+    order_value(&mut val, "", &options.ordering, &reference, false);
+  }
+  Ok(val)
+}
+
+/// `original` is the path before transforming, for reporting collisions.
+fn transform_keys(
+  val: &mut Value,
+  path: &str,
+  original: &str,
+  key_transform: fn(&str) -> String,
+) -> Result<()> {
+  match val {
+    Value::Array(vals) => {
+      for val in vals {
+        transform_keys(val, path, original, key_transform)?;
+      }
+    }
+    Value::Object(map) => {
+      // The original key of each transformed key.
+      let mut originals = OrderedHashMap::<String, String>::new();
+      let mut transformed = Map::with_capacity(map.len());
+      for (key, mut val) in std::mem::take(map) {
+        let new_key = key_transform(&key);
+        if let Some(first) = originals.get(&new_key) {
+          return Err(Error::KeyCollision {
+            path: join_path(path, &new_key),
+            first: join_path(original, first),
+            second: join_path(original, &key),
+          });
+        }
+        transform_keys(
+          &mut val,
+          &join_path(path, &new_key),
+          &join_path(original, &key),
+          key_transform,
+        )?;
+        originals.insert(new_key.clone(), key);
+        transformed.insert(new_key, val);
+      }
+      *map = transformed;
+    }
+    _ => {}
+  }
+  Ok(())
+}
+
+/// Applies the [Ordering] to every table beneath `path`.
+/// Map keys are already sorted by the serializer for [Alphabetical::Maps].
+/// `element` is whether the value is inside an array, for [Ordering::tag_keys].
+fn order_value(
+  val: &mut Value,
+  path: &str,
+  ordering: &Ordering<'_>,
+  reference: &HashMap<String, usize>,
+  element: bool,
+) {
+  match val {
+    Value::Array(vals) => {
+      for val in vals {
+        order_value(val, path, ordering, reference, true);
+      }
+    }
+    Value::Object(map) => {
+      let mut entries = std::mem::take(map).into_iter().collect::<Vec<_>>();
+      for (key, val) in &mut entries {
+        order_value(val, &join_path(path, key), ordering, reference, false);
+      }
+      entries.sort_by_cached_key(|(key, val)| {
+        let tag = ordering
+          .tag_keys
+          .iter()
+          .position(|tag| element && tag == key)
+          .unwrap_or(usize::MAX);
+        let full_path = join_path(path, key);
+        let priority = ordering
+          .priority
+          .iter()
+          .position(|priority| *priority == full_path)
+          .unwrap_or(usize::MAX);
+        let canonical = ordering
+          .canonical
+          .and_then(|canonical| canonical.position(&full_path))
+          .unwrap_or(usize::MAX);
+        let position = reference.get(&full_path).copied().unwrap_or(usize::MAX);
+        let kind = if ordering.group_by_kind {
+          kind_rank(val)
+        } else {
+          u8::from(ordering.scalars_first && is_table(val))
+        };
+        let alphabetical = (ordering.alphabetical == Alphabetical::All).then(|| key.clone());
+        (tag, priority, canonical, position, kind, alphabetical)
+      });
+      *map = entries.into_iter().collect();
+    }
+    _ => {}
+  }
+}
+
+/// A segment of a validator / secret path, see [Options::validators].
+enum PathSegment {
+  Key(String),
+  /// `[0]`
+  Index(usize),
+  /// `[*]`
+  Each,
+}
+
+fn key_path(path: &str) -> Option<Vec<PathSegment>> {
+  let mut segments = Vec::new();
+  let mut rest = path;
+  while !rest.is_empty() {
+    // The keys up to the next index, which isn't inside a quoted key.
+    let mut quote = None;
+    let end = rest
+      .char_indices()
+      .find(|(_, c)| match quote {
+        Some(q) => {
+          if *c == q {
+            quote = None;
+          }
+          false
+        }
+        None if matches!(c, '"' | '\'') => {
+          quote = Some(*c);
+          false
+        }
+        None => *c == '[',
+      })
+      .map_or(rest.len(), |(i, _)| i);
+    let keys = rest[..end].strip_prefix('.').unwrap_or(&rest[..end]);
+    if !keys.is_empty() {
+      segments.extend(split_key(keys).ok()?.into_iter().map(PathSegment::Key));
+    } else if end > 0 {
+      return None;
+    }
+    rest = &rest[end..];
+    if let Some(index) = rest.strip_prefix('[') {
+      let (index, after) = index.split_once(']')?;
+      segments.push(match index {
+        "*" => PathSegment::Each,
+        _ => PathSegment::Index(index.parse().ok()?),
+      });
+      rest = after;
+      if !rest.is_empty() && !rest.starts_with(['.', '[']) {
+        return None;
+      }
+    }
+  }
+  match segments.first() {
+    Some(PathSegment::Key(_)) => Some(segments),
+    _ => None,
+  }
+}
+
+/// Replaces the values of `val` (at the `key` segments) which are beneath any of the `secrets`
+/// with the `secret_mask`, including inside arrays.
+fn redact_secrets(key: &[String], val: &mut Value, options: Options<'_>) {
+  for secret in options.secrets {
+    // Checked by Options::validate.
+    let Some(segments) = key_path(secret) else {
+      continue;
+    };
+    let matched = key
+      .iter()
+      .zip(&segments)
+      .take_while(|(key, segment)| matches!(segment, PathSegment::Key(secret) if secret == *key))
+      .count();
+    if matched == key.len() || matched == segments.len() {
+      redact_at(val, &segments[matched..], options.secret_mask);
+    }
+  }
+}
+
+fn redact_at(val: &mut Value, segments: &[PathSegment], mask: &str) {
+  let Some((first, rest)) = segments.split_first() else {
+    // Everything beneath a secret table, iteratively so deeply nested values can't overflow the stack.
+    let mut stack = vec![val];
+    while let Some(val) = stack.pop() {
+      match val {
+        Value::Null => {}
+        Value::Object(map) => stack.extend(map.values_mut()),
+        val => *val = Value::String(mask.to_string()),
+      }
+    }
+    return;
+  };
+  match (first, val) {
+    (PathSegment::Key(key), Value::Object(map)) => {
+      if let Some(val) = map.get_mut(key) {
+        redact_at(val, rest, mask);
+      }
+    }
+    (PathSegment::Key(_), Value::Array(vals)) => {
+      for val in vals {
+        redact_at(val, segments, mask);
+      }
+    }
+    (PathSegment::Each, Value::Array(vals)) => {
+      for val in vals {
+        redact_at(val, rest, mask);
+      }
+    }
+    (PathSegment::Index(i), Value::Array(vals)) => {
+      if let Some(val) = vals.get_mut(*i) {
+        redact_at(val, rest, mask);
+      }
+    }
+    _ => {}
+  }
+}
+
+/// The failures of [Options::validators] on the (flattened) map, in the order of the validators.
+fn validation_errors(map: &Map, options: Options<'_>) -> Vec<Error> {
+  let mut errors = Vec::new();
+  if options.validators.is_empty() {
+    return errors;
+  }
+  let mut root = Map::new();
+  for (key, val) in map {
+    insert_path(&mut root, key, val.clone());
+  }
+  let root = Value::Object(root);
+  for (path, validator) in options.validators {
+    // Checked by Options::validate.
+    let Some(segments) = key_path(path) else {
+      continue;
+    };
+    validate_at(&root, &segments, String::new(), *validator, &mut errors);
+  }
+  errors
+}
+
+fn validate_at(
+  val: &Value,
+  segments: &[PathSegment],
+  path: String,
+  validator: Validator,
+  errors: &mut Vec<Error>,
+) {
+  let Some((first, rest)) = segments.split_first() else {
+    if let Err(message) = validator(val) {
+      errors.push(Error::Validation { path, message });
+    }
+    return;
+  };
+  match (first, val) {
+    (PathSegment::Key(key), Value::Object(map)) => {
+      if let Some(val) = map.get(key) {
+        validate_at(val, rest, join_path(&path, key), validator, errors);
+      }
+    }
+    // A key beneath an array is looked up in each element.
+    (PathSegment::Key(_), Value::Array(vals)) => {
+      for (i, val) in vals.iter().enumerate() {
+        validate_at(val, segments, format!("{path}[{i}]"), validator, errors);
+      }
+    }
+    (PathSegment::Each, Value::Array(vals)) => {
+      for (i, val) in vals.iter().enumerate() {
+        validate_at(val, rest, format!("{path}[{i}]"), validator, errors);
+      }
+    }
+    (PathSegment::Index(i), Value::Array(vals)) => {
+      if let Some(val) = vals.get(*i) {
+        validate_at(val, rest, format!("{path}[{i}]"), validator, errors);
+      }
+    }
+    _ => {}
+  }
+}
+
+fn join_path(path: &str, key: &str) -> String {
+  if path.is_empty() {
+    quote_key(key).into_owned()
+  } else {
+    format!("{path}.{}", quote_key(key))
+  }
+}
+
+/// The group of a value for [Ordering::group_by_kind].
+fn kind_rank(val: &Value) -> u8 {
+  match val {
+    _ if is_table(val) => 3,
+    Value::Array(_) => 2,
+    Value::String(_) => 1,
+    _ => 0,
+  }
+}
+
+/// Tables and arrays of tables.
+fn is_table(val: &Value) -> bool {
+  match val {
+    Value::Object(_) => true,
+    Value::Array(vals) => {
+      !vals.is_empty() && vals.iter().all(|val| matches!(val, Value::Object(_)))
+    }
+    _ => false,
+  }
+}
+
+fn render(map: &Map, options: Options<'_>) -> Result<String> {
+  let prepared = prepare(map, options)?;
+  let mut res = render_entries(&prepared.map, prepared.options(options))?;
+  if let Some(footer) = options.footer.filter(|footer| !footer.is_empty()) {
+    if !res.is_empty() {
+      res.push_str("\n\n");
+    }
+    write_comment(&mut res, footer, "");
+    // No trailing newline, same as the rest of the document.
+    res.pop();
+    check_output_size(&res, "(footer)", options)?;
+  }
+  if options.trailing_newline && !res.is_empty() {
+    res.push('\n');
+  }
+  if let Some(post_process) = options.post_process {
+    res = post_process(res);
+    check_output_size(&res, "(post_process)", options)?;
+  }
+  if options.content_hash {
+    res = append_content_hash(res);
+    check_output_size(&res, "(content_hash)", options)?;
+  }
+  Ok(res)
+}
+
+/// The entries of a document after the steps of [render] which apply to the whole document
+/// (eg. migrations, secrets, scaffolding), ready for [render_entries].
+struct Prepared<'m> {
+  map: Cow<'m, Map>,
+  /// The comments along with the markers added while preparing, eg. `# moved from old.key`.
+  comments: Option<CommentMap>,
+  empty_table: EmptyTable,
+}
+
+impl Prepared<'_> {
+  /// The options to render the prepared entries with.
+  fn options<'o>(&'o self, options: Options<'o>) -> Options<'o> {
+    Options {
+      comments: self.comments.as_ref().or(options.comments),
+      empty_table: self.empty_table,
+      ..options
+    }
+  }
+}
+
+fn prepare<'m>(map: &'m Map, options: Options<'_>) -> Result<Prepared<'m>> {
+  options.validate()?;
+  let mut map = Cow::Borrowed(map);
+  apply_big_integers(&mut map, options)?;
+  // Comments added while rendering, eg. `# moved from old.key`.
+  let mut markers = CommentMap::new();
+  if !options.migrations.is_empty() {
+    *map.to_mut() = migrate(
+      &map,
+      options.migrations,
+      options.migration_comments.then_some(&mut markers),
+    )?;
+  }
+  if !options.aliases.is_empty() {
+    *map.to_mut() = alias(&map, options.aliases, &mut markers)?;
+  }
+  if let Some(deprecated) = options
+    .deprecated
+    .filter(|deprecated| !deprecated.is_empty())
+  {
+    if options.omit_deprecated {
+      *map.to_mut() = map
+        .iter()
+        .filter(|(key, _)| !deprecated.keys().any(|path| is_under(key, path)))
+        .map(|(key, val)| (key.clone(), val.clone()))
+        .collect();
+    } else {
+      for (path, message) in deprecated {
+        if map.keys().any(|key| is_under(key, path)) {
+          append_comment(
+            &mut markers,
+            path.clone(),
+            &format!("deprecated: {message}"),
+          );
+        }
+      }
+    }
+  }
+  if let Some(e) = validation_errors(&map, options).into_iter().next() {
+    return Err(e);
+  }
+  if options.redact_secrets && !options.secrets.is_empty() {
+    for (key, val) in map.to_mut().iter_mut() {
+      let key = split_key(key).unwrap_or_else(|_| vec![key.clone()]);
+      redact_secrets(&key, val, options);
+    }
+  }
+  if options.scaffold {
+    for (path, example) in options.examples {
+      let mut example = Some(flatten_at(path.to_string(), parse_value(example)?));
+      // The existing value, along with anything beneath it or a scalar above it, is replaced in place.
+      let replaced = |key: &str| is_under(key, path) || is_under(path, key);
+      if map.keys().any(|key| replaced(key)) {
+        let mut res = Map::new();
+        for (key, val) in map.iter() {
+          if !replaced(key) {
+            res.insert(key.clone(), val.clone());
+          } else if let Some(example) = example.take() {
+            res.extend(example);
+          }
+        }
+        map = Cow::Owned(res);
+      } else if let Some(example) = example {
+        map.to_mut().extend(example);
+      }
+    }
+  }
+  if options.nested_empty_table != NestedEmptyTable::Innermost {
+    let entries = map.iter().map(|(key, val)| (key.clone(), val.clone()));
+    *map.to_mut() = place_empty_tables(entries.collect(), options.nested_empty_table)
+      .into_iter()
+      .collect();
+  }
+  if !options.dependency_tables.is_empty() {
+    *map.to_mut() = format_dependencies(&map, options.dependency_tables);
+  }
+  let mut empty_table = options.empty_table;
+  if let Some(depth) = options.truncate_depth {
+    let (truncated, truncate_markers) = truncate_depth(&map, depth, options);
+    *map.to_mut() = truncated;
+    for (path, marker) in truncate_markers {
+      append_comment(&mut markers, path, &marker);
+    }
+    if empty_table == EmptyTable::Skip {
+      // Other empty tables were already skipped.
+      empty_table = EmptyTable::Inline;
+    }
+  }
+  if options.inline_tables {
+    *map.to_mut() = group_root_tables(
+      &map,
+      Options {
+        empty_table,
+        ..options
+      },
+    );
+  }
+  if options.type_comments {
+    for (key, val) in map.iter() {
+      if let Some(name) = type_name(val) {
+        append_comment(&mut markers, key.clone(), &format!("type: {name}"));
+      }
+    }
+  }
+  if let Some(prefix) = options.env_comments {
+    for (key, val) in map.iter() {
+      if !val.is_null() {
+        let var = env_var(prefix, key, options.env_case)?;
+        append_comment(&mut markers, key.clone(), &format!("env: {var}"));
+      }
+    }
+  }
+  let mut comments = None;
+  if !markers.is_empty() {
+    let mut all = options.comments.cloned().unwrap_or_default();
+    for (path, marker) in markers {
+      append_comment(&mut all, path, &marker);
+    }
+    comments = Some(all);
+  }
+  Ok(Prepared {
+    map,
+    comments,
+    empty_table,
+  })
+}
+
+const CONTENT_HASH: &str = "# content-hash: sha256:";
+
+/// Appends the [Options::content_hash] comment, hashing everything before it.
+fn append_content_hash(mut res: String) -> String {
+  let trailing_newline = res.ends_with('\n');
+  if !res.is_empty() && !trailing_newline {
+    res.push('\n');
+  }
+  let hash = sha256::sha256_hex(res.as_bytes());
+  res.push_str(CONTENT_HASH);
+  res.push_str(&hash);
+  if trailing_newline {
+    res.push('\n');
+  }
+  res
+}
+
+/// Check the [Options::content_hash] comment of a generated file. None if it has none,
+/// otherwise whether the rest of the file is unchanged since it was generated.
+pub fn verify_content_hash(text: &str) -> Option<bool> {
+  let text = text.strip_suffix('\n').unwrap_or(text);
+  let start = text.rfind('\n').map_or(0, |i| i + 1);
+  let hash = text[start..].strip_prefix(CONTENT_HASH)?;
+  Some(sha256::sha256_hex(&text.as_bytes()[..start]) == hash.trim_end())
+}
+
+/// The toml type of a value for [Options::type_comments], None for nulls (which aren't written).
+fn type_name(val: &Value) -> Option<Cow<'static, str>> {
+  let name = match val {
+    Value::Null => return None,
+    Value::Bool(_) => "boolean",
+    Value::Number(number) if number.is_f64() => "float",
+    Value::Number(_) => "integer",
+    Value::String(_) => "string",
+    Value::Object(_) => "table",
+    Value::Array(vals) => {
+      let mut names = vals.iter().filter_map(type_name);
+      let Some(first) = names.next() else {
+        return Some(Cow::Borrowed("array"));
+      };
+      // Just the outer type for nested arrays, eg. `array of arrays`.
+      let first = first.split(' ').next().unwrap_or_default().to_string();
+      if names.all(|name| name.split(' ').next() == Some(first.as_str())) {
+        return Some(Cow::Owned(format!("array of {first}s")));
+      }
+      "array"
+    }
+  };
+  Some(Cow::Borrowed(name))
+}
+
+/// The environment variable for the (dotted) key path, for [Options::env_comments].
+fn env_var(prefix: &str, path: &str, case: EnvCase) -> Result<String> {
+  let mut var = prefix.to_string();
+  for (i, segment) in split_key(path)?.iter().enumerate() {
+    if i > 0 {
+      var.push('_');
+    }
+    var.extend(segment.chars().map(|c| match c {
+      c if !c.is_ascii_alphanumeric() => '_',
+      c if case == EnvCase::Upper => c.to_ascii_uppercase(),
+      c if case == EnvCase::Lower => c.to_ascii_lowercase(),
+      c => c,
+    }));
+  }
+  Ok(var)
+}
+
+fn check_output_size(res: &str, path: &str, options: Options<'_>) -> Result<()> {
+  match options.max_output_bytes {
+    Some(limit) if res.len() > limit => Err(Error::OutputTooLarge {
+      limit,
+      path: path.to_string(),
+    }),
+    _ => Ok(()),
+  }
+}
+
+/// Groups the flattened entries of each dependency back into a single (sorted) entry,
+/// which is a plain version string when the dependency only has a version.
+fn format_dependencies(map: &Map, tables: &[&str]) -> Map {
+  enum Item<'m> {
+    Entry(&'m str, &'m Value),
+    Dependencies(&'m str),
+  }
+  let mut items = Vec::new();
+  let mut dependencies = OrderedHashMap::<&str, Vec<(&str, Value)>>::new();
+  for (key, val) in map {
+    let Some((table, name, rest)) = split_dependency(key, tables) else {
+      items.push(Item::Entry(key, val));
+      continue;
+    };
+    if !dependencies.contains_key(table) {
+      dependencies.insert(table, Vec::new());
+      items.push(Item::Dependencies(table));
+    }
+    let Some(entries) = dependencies.get_mut(table) else {
+      continue;
+    };
+    let index = match entries.iter().position(|(existing, _)| *existing == name) {
+      Some(index) => index,
+      None => {
+        entries.push((name, Value::Object(Map::new())));
+        entries.len() - 1
+      }
+    };
+    let entry = &mut entries[index].1;
+    match rest {
+      None => *entry = val.clone(),
+      Some(rest) => {
+        if !matches!(entry, Value::Object(_)) {
+          *entry = Value::Object(Map::new());
+        }
+        if let Value::Object(entry) = entry {
+          insert_path(entry, rest, val.clone());
+        }
+      }
+    }
+  }
+  let mut res = Map::with_capacity(map.len());
+  for item in items {
+    match item {
+      Item::Entry(key, val) => {
+        res.insert(key.to_string(), val.clone());
+      }
+      Item::Dependencies(table) => {
+        let Some(mut entries) = dependencies.remove(table) else {
+          continue;
+        };
+        entries.sort_by_key(|(name, _)| *name);
+        for (name, val) in entries {
+          res.insert(format!("{table}.{name}"), version_first(val));
+        }
+      }
+    }
+  }
+  res
+}
+
+/// Splits a key beneath one of the dependency `tables` into the table, dependency name,
+/// and the rest of the key after the name.
+fn split_dependency<'k>(
+  key: &'k str,
+  tables: &[&str],
+) -> Option<(&'k str, &'k str, Option<&'k str>)> {
+  let dots = segment_dots(key);
+  let segment = |i: usize| {
+    let start = if i == 0 { 0 } else { dots[i - 1] + 1 };
+    &key[start..dots.get(i).copied().unwrap_or(key.len())]
+  };
+  tables.iter().find_map(|table| {
+    let len = table.split('.').count();
+    let matches = dots.len() >= len
+      && table
+        .split('.')
+        .enumerate()
+        .all(|(i, pattern)| pattern == "*" || pattern == segment(i));
+    if !matches {
+      return None;
+    }
+    let table = &key[..dots[len - 1]];
+    let name = segment(len);
+    let rest = dots.get(len).map(|&i| &key[i + 1..]);
+    Some((table, name, rest))
+  })
+}
+
+/// Insert `val` at the dotted `path` beneath `map`, creating tables along the way.
+/// Groups the flattened entries back into a table per root key, for [Options::inline_tables].
+fn group_root_tables(map: &Map, options: Options<'_>) -> Map {
+  let mut res = Map::new();
+  for (key, val) in map.iter().filter(|(_, val)| is_written(val, options)) {
+    let Some(dot) = segment_dots(key).first().copied() else {
+      res.insert(key.clone(), val.clone());
+      continue;
+    };
+    let root = &key[..dot];
+    if !matches!(res.get(root), Some(Value::Object(_))) {
+      res.insert(root.to_string(), Value::Object(Map::new()));
+    }
+    if let Some(Value::Object(table)) = res.get_mut(root) {
+      insert_path(table, &key[dot + 1..], val.clone());
+    }
+  }
+  res
+}
+
+fn insert_path(map: &mut Map, path: &str, val: Value) {
+  let segments = split_key(path).unwrap_or_else(|_| vec![path.to_string()]);
+  insert_segments(map, &segments, val);
+}
+
+fn insert_segments(map: &mut Map, segments: &[String], val: Value) {
+  let Some((first, rest)) = segments.split_first() else {
+    return;
+  };
+  if rest.is_empty() {
+    map.insert(first.clone(), val);
+    return;
+  }
+  if !matches!(map.get(first), Some(Value::Object(_))) {
+    map.insert(first.clone(), Value::Object(Map::new()));
+  }
+  if let Some(Value::Object(map)) = map.get_mut(first) {
+    insert_segments(map, rest, val);
+  }
+}
+
+/// `{ version = "1.0" }` becomes `"1.0"`, otherwise `version` goes first.
+fn version_first(val: Value) -> Value {
+  let Value::Object(mut map) = val else {
+    return val;
+  };
+  let Some(version) = map.remove("version") else {
+    return Value::Object(map);
+  };
+  if map.is_empty() {
+    return version;
+  }
+  let mut res = Map::with_capacity(map.len() + 1);
+  res.insert(String::from("version"), version);
+  res.extend(map);
+  Value::Object(res)
+}
+
+fn render_entries(map: &Map, options: Options<'_>) -> Result<String> {
+  if options.table_headers {
+    return render_tables(map, options);
+  }
+  render_aligned_entries(map, 0, options)
+}
+
+/// Renders root keys only, padding the keys to `key_width` chars so their `=` line up.
+fn render_aligned_entries(map: &Map, key_width: usize, options: Options<'_>) -> Result<String> {
+  let mut res = String::new();
+  let mut commented_tables = HashSet::new();
+  for (key, val) in map {
+    let written = written_key(key, options);
+    let width = written.chars().count().max(key_width) + " = ".len();
+    let Some(val) = entry_value(key, width, val, options)? else {
+      continue;
+    };
+    if !res.is_empty() {
+      res.push('\n');
+    }
+    let mut trailing = None;
+    if let Some(comments) = options.comments {
+      // Comments on the tables containing the key go above the first key of the table.
+      write_table_comments(&mut res, key, comments, &mut commented_tables);
+      if let Some(comment) = comments.get(key) {
+        trailing = trailing_comment(comment, &val, options);
+        if trailing.is_none() {
+          write_comment(&mut res, comment, "");
+        }
+      }
+    }
+    let start = res.len();
+    options.formatter.write_key(&mut res, &written);
+    let padding = key_width.saturating_sub(written.chars().count());
+    res.extend(std::iter::repeat_n(' ', padding));
+    res.push_str(" = ");
+    res.push_str(&val);
+    if let Some((comment, column)) = trailing {
+      write_trailing_comment(&mut res, start, comment, column);
+    }
+    check_output_size(&res, key, options)?;
+  }
+  Ok(res)
+}
+
+/// Renders root keys first, then each table under its `[table]` header,
+/// in the order the tables first appear.
+fn render_tables(map: &Map, options: Options<'_>) -> Result<String> {
+  let mut tables = OrderedHashMap::<&str, Vec<(&str, &Value)>>::new();
+  let mut super_tables = HashSet::new();
+  let mut array_tables = HashMap::<&str, &[Value]>::new();
+  let deepest = deepest_tables(map, options);
+  tables.insert("", Vec::new());
+  for (key, val) in map {
+    let (table, entry) = if let Some(vals) = array_of_tables(val, options) {
+      array_tables.insert(key, vals);
+      (key.as_str(), None)
+    } else if options.empty_table == EmptyTable::Header
+      && is_empty_table(val)
+      && table_depth(key) > options.dotted_depth
+    {
+      (key.as_str(), None)
+    } else {
+      match split_last_segment(key) {
+        (table, _) if table_depth(table) <= options.dotted_depth => ("", Some((key.as_str(), val))),
+        (table, _) => {
+          let header = header_table(table, &deepest, options);
+          (header, Some((&key[header.len() + 1..], val)))
+        }
+      }
+    };
+    if !tables.contains_key(table) {
+      if options.super_tables {
+        // Super tables shallow enough to be dotted, or written as dotted keys under
+        // a header (see header_dotted_depth), are already defined by their dotted keys.
+        for i in segment_dots(table).into_iter().skip(options.dotted_depth) {
+          let super_table = &table[..i];
+          if !tables.contains_key(super_table)
+            && header_table(super_table, &deepest, options) == super_table
+          {
+            tables.insert(super_table, Vec::new());
+            super_tables.insert(super_table);
+          }
+        }
+      }
+      tables.insert(table, Vec::new());
+    }
+    if let (Some(entry), Some(entries)) = (entry, tables.get_mut(table)) {
+      entries.push(entry);
+    }
+  }
+  if options.collapse_tables {
+    // Keep only the super tables with several nested tables.
+    let mut children = HashMap::<&str, usize>::new();
+    for table in tables.keys().filter(|table| !table.is_empty()) {
+      *children.entry(split_last_segment(table).0).or_default() += 1;
+    }
+    super_tables.retain(|table| children.get(table).is_some_and(|children| *children > 1));
+  }
+  let mut res = String::new();
+  let mut commented_tables = HashSet::new();
+  for (table, entries) in tables {
+    if let Some(vals) = array_tables.get(table) {
+      render_array_tables(&mut res, table, vals, &mut commented_tables, options)?;
+      continue;
+    }
+    let mut lines = Vec::with_capacity(entries.len());
+    for (key, val) in entries {
+      let path = if table.is_empty() {
+        Cow::Borrowed(key)
+      } else {
+        Cow::Owned(format!("{table}.{key}"))
+      };
+      let width = written_key(key, options).chars().count() + " = ".len();
+      if let Some(val) = entry_value(&path, width, val, options)? {
+        lines.push((path, key, val));
+      }
+    }
+    // Tables left with no keys only get a header when they were empty to begin with.
+    let explicit_empty =
+      options.empty_table == EmptyTable::Header && map.get(table).is_some_and(is_empty_table);
+    if !table.is_empty() && (!lines.is_empty() || explicit_empty || super_tables.contains(table)) {
+      if !res.is_empty() {
+        res.push_str("\n\n");
+      }
+      if let Some(comments) = options.comments {
+        write_table_comments(&mut res, table, comments, &mut commented_tables);
+        if commented_tables.insert(table) {
+          if let Some(comment) = comments.get(table) {
+            write_comment(&mut res, comment, "");
+          }
+        }
+      }
+      options
+        .formatter
+        .write_header(&mut res, &written_key(table, options), false);
+    }
+    for (path, key, val) in lines {
+      if !res.is_empty() {
+        res.push('\n');
+      }
+      let comment = options
+        .comments
+        .and_then(|comments| comments.get(path.as_ref()));
+      let trailing = comment.and_then(|comment| trailing_comment(comment, &val, options));
+      if let (Some(comment), None) = (comment, trailing) {
+        write_comment(&mut res, comment, "");
+      }
+      let start = res.len();
+      options
+        .formatter
+        .write_key(&mut res, &written_key(key, options));
+      res.push_str(" = ");
+      res.push_str(&val);
+      if let Some((comment, column)) = trailing {
+        write_trailing_comment(&mut res, start, comment, column);
+      }
+      check_output_size(&res, &path, options)?;
+    }
+  }
+  Ok(res)
+}
+
+/// The depth of the deepest table (including its own) beneath each table, for [header_table].
+/// Empty unless [Options::header_dotted_depth] is set.
+fn deepest_tables<'m>(map: &'m Map, options: Options<'_>) -> HashMap<&'m str, usize> {
+  let mut deepest = HashMap::new();
+  if options.header_dotted_depth == 0 {
+    return deepest;
+  }
+  for (key, val) in map {
+    // Empty tables and arrays of tables are written under a header of their own.
+    let table = if array_of_tables(val, options).is_some()
+      || (options.empty_table == EmptyTable::Header && is_empty_table(val))
+    {
+      key.as_str()
+    } else {
+      split_last_segment(key).0
+    };
+    if table.is_empty() {
+      continue;
+    }
+    let dots = segment_dots(table);
+    let depth = dots.len() + 1;
+    for end in dots.into_iter().chain([table.len()]) {
+      let max = deepest.entry(&table[..end]).or_insert(0);
+      *max = (*max).max(depth);
+    }
+  }
+  deepest
+}
+
+/// The table whose header a table deeper than `dotted_depth` is written under:
+/// the shallowest ancestor (at least `dotted_depth + 1` deep) which has no nested table
+/// more than [Options::header_dotted_depth] levels below it.
+/// A table is never written both with dotted keys and under a header of its own.
+fn header_table<'k>(
+  table: &'k str,
+  deepest: &HashMap<&str, usize>,
+  options: Options<'_>,
+) -> &'k str {
+  if options.header_dotted_depth == 0 {
+    return table;
+  }
+  let dots = segment_dots(table);
+  // The ancestor with `depth` segments.
+  let ancestor = |depth: usize| &table[..dots.get(depth - 1).copied().unwrap_or(table.len())];
+  let mut depth = options.dotted_depth + 1;
+  while depth <= dots.len() {
+    let child = ancestor(depth + 1);
+    if deepest
+      .get(child)
+      .is_some_and(|deepest| deepest - depth <= options.header_dotted_depth)
+    {
+      break;
+    }
+    depth += 1;
+  }
+  ancestor(depth)
+}
+
+/// The elements of an array written as `[[array]]` sections, see [Options::array_tables].
+fn array_of_tables<'v>(val: &'v Value, options: Options<'_>) -> Option<&'v [Value]> {
+  match val {
+    Value::Array(vals)
+      if options.array_tables
+        && options.table_headers
+        && !vals.is_empty()
+        && vals.iter().all(|val| matches!(val, Value::Object(_))) =>
+    {
+      Some(vals)
+    }
+    _ => None,
+  }
+}
+
+fn render_array_tables<'k>(
+  res: &mut String,
+  key: &'k str,
+  vals: &[Value],
+  commented_tables: &mut HashSet<&'k str>,
+  options: Options<'_>,
+) -> Result<()> {
+  let (vals, truncated) = match options.max_array_items {
+    Some(max) if vals.len() > max => (&vals[..max], vals.len() - max),
+    _ => (vals, 0),
+  };
+  let mut cache = KeyCache::default();
+  let mut key_width = 0;
+  if options.align_array_tables {
+    for map in vals.iter().filter_map(Value::as_object) {
+      for (key, val) in &cache.flatten(map, options.nested_empty_table) {
+        if is_written(val, options) {
+          key_width = key_width.max(written_key(key, options).chars().count());
+        }
+      }
+    }
+  }
+  for (i, val) in vals.iter().enumerate() {
+    let Value::Object(map) = val else {
+      continue;
+    };
+    if !res.is_empty() {
+      res.push_str(if i == 0 || options.array_table_blank_lines {
+        "\n\n"
+      } else {
+        "\n"
+      });
+    }
+    // The comments of the entry's keys, relative to the entry.
+    let mut entry_comments = None;
+    if let Some(comments) = options.comments {
+      if i == 0 {
+        write_table_comments(res, key, comments, commented_tables);
+        if commented_tables.insert(key) {
+          if let Some(comment) = comments.get(key) {
+            write_comment(res, comment, "");
+          }
+        }
+      }
+      let path = format!("{key}[{i}]");
+      if let Some(comment) = comments.get(&path) {
+        write_comment(res, comment, "");
+      }
+      let prefix = format!("{path}.");
+      let relative = comments
+        .iter()
+        .filter_map(|(path, comment)| {
+          let path = path.strip_prefix(&prefix)?;
+          Some((path.to_string(), comment.clone()))
+        })
+        .collect::<CommentMap>();
+      entry_comments = (!relative.is_empty()).then_some(relative);
+    }
+    if let Some(template) = options.array_table_comment {
+      write_comment(res, &template.replace("{index}", &i.to_string()), "");
+    }
+    options
+      .formatter
+      .write_header(res, &written_key(key, options), true);
+    let entries = render_aligned_entries(
+      &cache.flatten(map, options.nested_empty_table),
+      key_width,
+      Options {
+        comments: entry_comments.as_ref(),
+        ..options
+      },
+    )?;
+    if !entries.is_empty() {
+      res.push('\n');
+      res.push_str(&entries);
+    }
+    check_output_size(res, key, options)?;
+  }
+  if truncated > 0 {
+    let items = if truncated == 1 { "item" } else { "items" };
+    res.push_str("\n\n");
+    write_comment(res, &format!("… {} more {items}", thousands(truncated)), "");
+    res.pop();
+  }
+  Ok(())
+}
+
+/// The rendered value of an entry, or None if it should be left out.
+fn entry_value(
+  key: &str,
+  width: usize,
+  val: &Value,
+  options: Options<'_>,
+) -> Result<Option<String>> {
+  let val = match val {
+    Value::Null => return Ok(None),
+    Value::String(val) if options.skip_empty_string && val.is_empty() => return Ok(None),
+    Value::Array(vals) => array_to_string(key, width, vals, options)?,
+    // flatten_map only leaves empty objects behind
+    Value::Object(map) if map.is_empty() => match options.empty_table {
+      EmptyTable::Skip => return Ok(None),
+      EmptyTable::Inline | EmptyTable::Header => {
+        inline_table_to_string(map, &mut KeyCache::default(), options)?
+      }
+    },
+    // Grouped back into a table on purpose, eg. by `dependency_tables`.
+    Value::Object(map) => inline_table_to_string(map, &mut KeyCache::default(), options)?,
+    val => {
+      let mut res = String::new();
+      write_scalar(&mut res, val, string_to_string, options);
+      res
+    }
+  };
+  Ok(Some(val))
+}
+
+/// Writes a bool / number / string with the [Options::formatter], rendering strings with `string`.
+/// Writes nothing for other values.
+fn write_scalar(
+  res: &mut String,
+  val: &Value,
+  string: fn(&str, Options<'_>) -> String,
+  options: Options<'_>,
+) {
+  match val {
+    Value::Bool(bool) => options.formatter.write_bool(res, *bool),
+    Value::Number(number) => options.formatter.write_number(res, &number.to_string()),
+    Value::String(val) => options
+      .formatter
+      .write_string(res, val, &string(val, options)),
+    Value::Null | Value::Array(_) | Value::Object(_) => {}
+  }
+}
+
+/// Whether [entry_value] writes the value, rather than leaving out its key.
+fn is_written(val: &Value, options: Options<'_>) -> bool {
+  match val {
+    Value::Null => false,
+    Value::String(string) => !(options.skip_empty_string && string.is_empty()),
+    Value::Object(map) => !map.is_empty() || options.empty_table != EmptyTable::Skip,
+    _ => true,
+  }
+}
+
+/// Moves or drops the empty tables among flattened entries, per [Options::nested_empty_table].
+fn place_empty_tables<V: Borrow<Value>>(
+  entries: Vec<(String, V)>,
+  nested_empty_table: NestedEmptyTable,
+) -> Vec<(String, V)> {
+  match nested_empty_table {
+    NestedEmptyTable::Innermost => entries,
+    NestedEmptyTable::Skip => entries
+      .into_iter()
+      .filter(|(key, val)| !is_empty_table(val.borrow()) || segment_dots(key).is_empty())
+      .collect(),
+    NestedEmptyTable::Outermost => {
+      // The tables with something other than empty tables beneath them.
+      let mut occupied = HashSet::new();
+      for (key, val) in &entries {
+        if !is_empty_table(val.borrow()) {
+          for end in segment_dots(key) {
+            occupied.insert(key[..end].to_string());
+          }
+        }
+      }
+      let mut placed = HashSet::new();
+      let mut res = Vec::with_capacity(entries.len());
+      for (key, val) in entries {
+        if !is_empty_table(val.borrow()) {
+          res.push((key, val));
+          continue;
+        }
+        let end = segment_dots(&key)
+          .into_iter()
+          .find(|end| !occupied.contains(&key[..*end]))
+          .unwrap_or(key.len());
+        let table = key[..end].to_string();
+        // The first empty table stands in for the whole (empty) ancestor.
+        if placed.insert(table.clone()) {
+          res.push((table, val));
+        }
+      }
+      res
+    }
+  }
+}
+
+fn is_empty_table(val: &Value) -> bool {
+  matches!(val, Value::Object(map) if map.is_empty())
+}
+
+fn is_big_integer(number: &Number) -> bool {
+  number.as_u64().is_some_and(|int| int > i64::MAX as u64)
+}
+
+/// Applies [Options::big_integers] to the integers beyond `i64::MAX` of values which didn't come
+/// through the serializer, eg. edited with [Document::walk].
+fn apply_big_integers(map: &mut Cow<Map>, options: Options<'_>) -> Result<()> {
+  fn find(val: &Value, path: String) -> Option<String> {
+    match val {
+      Value::Number(number) if is_big_integer(number) => Some(path),
+      Value::Array(vals) => vals
+        .iter()
+        .enumerate()
+        .find_map(|(i, val)| find(val, format!("{path}[{i}]"))),
+      Value::Object(map) => map
+        .iter()
+        .find_map(|(key, val)| find(val, join_path(&path, key))),
+      _ => None,
+    }
+  }
+  fn to_strings(val: &mut Value) {
+    match val {
+      Value::Number(number) if is_big_integer(number) => *val = Value::String(number.to_string()),
+      Value::Array(vals) => vals.iter_mut().for_each(to_strings),
+      Value::Object(map) => map.values_mut().for_each(to_strings),
+      _ => {}
+    }
+  }
+  let Some(path) = map.iter().find_map(|(key, val)| find(val, key.clone())) else {
+    return Ok(());
+  };
+  match options.big_integers {
+    BigIntegers::Error => Err(Error::IntegerOutOfRange { path }),
+    BigIntegers::String => {
+      map.to_mut().values_mut().for_each(to_strings);
+      Ok(())
+    }
+  }
+}
+
+/// Quotes a key segment, unless it is a valid bare key.
+pub(crate) fn quote_key(key: &str) -> Cow<'_, str> {
+  if !key.is_empty()
+    && key
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+  {
+    Cow::Borrowed(key)
+  } else {
+    Cow::Owned(format!("\"{}\"", escape_basic(key, Options::default())))
+  }
+}
+
+/// A (quoted as needed) dotted key as written, with every segment quoted for [Options::quote_all_keys].
+fn written_key<'k>(key: &'k str, options: Options<'_>) -> Cow<'k, str> {
+  if !options.quote_all_keys {
+    return Cow::Borrowed(key);
+  }
+  let mut res = String::with_capacity(key.len() + 2);
+  let mut start = 0;
+  for end in segment_dots(key).into_iter().chain([key.len()]) {
+    let segment = &key[start..end];
+    if segment.starts_with(['"', '\'']) {
+      res.push_str(segment);
+    } else {
+      // Bare keys only have characters which don't need escaping.
+      res.push('"');
+      res.push_str(segment);
+      res.push('"');
+    }
+    if end < key.len() {
+      res.push('.');
+    }
+    start = end + 1;
+  }
+  Cow::Owned(res)
+}
+
+/// Joins (unquoted) key segments into a dotted key, quoting them as needed.
+pub(crate) fn join_key(segments: &[String]) -> String {
+  segments
+    .iter()
+    .map(|segment| quote_key(segment))
+    .collect::<Vec<_>>()
+    .join(".")
+}
+
+/// The positions of the dots separating the segments of a dotted key.
+/// Dots inside quoted segments are ignored.
+fn segment_dots(key: &str) -> Vec<usize> {
+  let mut quote = None;
+  let mut dots = Vec::new();
+  let mut chars = key.char_indices();
+  while let Some((i, c)) = chars.next() {
+    match (quote, c) {
+      (None, '"' | '\'') => quote = Some(c),
+      // An escaped quote (`\"`) doesn't end a "basic" key.
+      (Some('"'), '\\') => {
+        chars.next();
+      }
+      (Some(q), c) if q == c => quote = None,
+      (None, '.') => dots.push(i),
+      _ => {}
+    }
+  }
+  dots
+}
+
+/// The number of segments in a dotted table key, 0 for the root table.
+fn table_depth(table: &str) -> usize {
+  if table.is_empty() {
+    0
+  } else {
+    segment_dots(table).len() + 1
+  }
+}
+
+/// The length of the first segment of a dotted key.
+fn first_segment_len(key: &str) -> usize {
+  segment_dots(key).first().copied().unwrap_or(key.len())
+}
+
+/// Splits a dotted key into its table and final key segment.
+fn split_last_segment(key: &str) -> (&str, &str) {
+  match segment_dots(key).last() {
+    Some(&i) => (&key[..i], &key[i + 1..]),
+    None => ("", key),
+  }
+}
+
+/// Writes the comments of all the tables containing `key` which haven't been written yet.
+fn write_table_comments<'k>(
+  res: &mut String,
+  key: &'k str,
+  comments: &CommentMap,
+  commented_tables: &mut HashSet<&'k str>,
+) {
+  for i in segment_dots(key) {
+    let table = &key[..i];
+    if commented_tables.insert(table) {
+      if let Some(comment) = comments.get(table) {
+        write_comment(res, comment, "");
+      }
+    }
+  }
+}
+
+/// The comment with its column, if it goes at the end of the entry's line, see [Options::comment_column].
+fn trailing_comment<'c>(
+  comment: &'c str,
+  val: &str,
+  options: Options<'_>,
+) -> Option<(&'c str, usize)> {
+  let column = options.comment_column?;
+  (!comment.is_empty() && !comment.contains('\n') && !val.contains('\n'))
+    .then_some((comment, column))
+}
+
+/// Writes the comment at the end of the line starting at `start`.
+fn write_trailing_comment(res: &mut String, start: usize, comment: &str, column: usize) {
+  let width = res[start..].chars().count();
+  res.extend(std::iter::repeat_n(
+    ' ',
+    column.saturating_sub(width).max(1),
+  ));
+  res.push_str("# ");
+  res.push_str(comment);
+}
+
+fn write_comment(res: &mut String, comment: &str, indent: &str) {
+  for line in comment.lines() {
+    res.push_str(indent);
+    if line.is_empty() {
+      res.push_str("#\n");
+    } else {
+      res.push_str("# ");
+      res.push_str(line);
+      res.push('\n');
+    }
+  }
+}
+
+/// Serialize only the value portion of a toml entry (no `key = `),
+/// eg. an array, inline table, or string. Useful for embedding into templates.
+/// With `inline_array(true)`, structs are written as a single line
+/// inline table, eg. for logging the effective config as one field of a structured log event.
+pub fn format_value<T: Serialize + ?Sized>(value: &T, options: Options<'_>) -> Result<String> {
+  options.validate()?;
+  match to_value(value, options)? {
+    Value::Array(vals) => array_to_string("", 0, &vals, options),
+    Value::Object(map) => inline_table_to_string(&map, &mut KeyCache::default(), options),
+    val => {
+      let mut res = String::new();
+      write_scalar(&mut res, &val, string_to_string, options);
+      Ok(res)
+    }
+  }
+}
+
+fn string_to_string(val: &str, options: Options<'_>) -> String {
+  let val = normalize_string(val, options);
+  if val.contains('\n') {
+    if options.multiline_indent {
+      return indented_multiline_string(&val, options);
+    }
+    let mut res = String::from("\"\"\"");
+    let val = match options.multiline_start {
+      MultilineStart::Inline if !val.starts_with('\n') => &val,
+      MultilineStart::Newline | MultilineStart::Inline => {
+        res.push('\n');
+        &val
+      }
+      MultilineStart::Backslash => {
+        res.push_str("\\\n");
+        push_leading_whitespace(&mut res, &val)
+      }
+    };
+    res.push_str(&escape_multiline(val, options));
+    res.push_str("\"\"\"");
+    res
+  } else {
+    format!("\"{}\"", escape_basic(&val, options))
+  }
+}
+
+/// A multiline string with each line indented by `tab`. Newlines in the value are written
+/// as `\n` escapes, and each line ends with a `\`, which in toml skips the line break
+/// along with the indentation and blank lines after it.
+fn indented_multiline_string(val: &str, options: Options<'_>) -> String {
+  let mut res = String::from("\"\"\"");
+  let mut lines = val.split('\n').peekable();
+  let mut indent = "";
+  // The first line can't be indented after a plain line break, since that is kept.
+  if options.multiline_start != MultilineStart::Inline {
+    res.push_str("\\\n");
+    indent = options.tab;
+  }
+  while let Some(line) = lines.next() {
+    let last = lines.peek().is_none();
+    if last && line.is_empty() {
+      break;
+    }
+    res.push_str(indent);
+    indent = options.tab;
+    let content = push_leading_whitespace(&mut res, line);
+    res.push_str(&escape_multiline(content, options));
+    if !last {
+      res.push_str("\\n");
+    }
+    res.push_str("\\\n");
+  }
+  res.push_str("\"\"\"");
+  res
+}
+
+/// Escapes the whitespace at the start of `val`, which would be skipped by
+/// a line continuation before it. Returns the rest of `val`.
+fn push_leading_whitespace<'v>(res: &mut String, val: &'v str) -> &'v str {
+  let rest = val.trim_start_matches([' ', '\t', '\n']);
+  for c in val[..val.len() - rest.len()].chars() {
+    res.push_str(match c {
+      ' ' => "\\u0020",
+      '\t' => "\\t",
+      _ => "\\n",
+    });
+  }
+  rest
+}
+
+/// A string on a single line, eg. inside an array.
+fn single_line_string_to_string(val: &str, options: Options<'_>) -> String {
+  format!(
+    "\"{}\"",
+    escape_basic(&normalize_string(val, options), options)
+  )
+}
+
+/// Applies the `carriage_return`, `string_tabs` and `datetime_offset` options.
+fn normalize_string<'v>(val: &'v str, options: Options<'_>) -> Cow<'v, str> {
+  if let Some(datetime) = datetime::normalize_offset(val, options.datetime_offset) {
+    return Cow::Owned(datetime);
+  }
+  let mut val = Cow::Borrowed(val);
+  if options.carriage_return == CarriageReturn::Normalize && val.contains('\r') {
+    val = Cow::Owned(val.replace("\r\n", "\n"));
+  }
+  if let StringTabs::Expand(width) = options.string_tabs {
+    if val.contains('\t') {
+      val = Cow::Owned(expand_tabs(&val, width));
+    }
+  }
+  val
+}
+
+/// Replaces tabs with spaces up to the next tab stop, every `width` columns.
+fn expand_tabs(val: &str, width: usize) -> String {
+  let mut res = String::with_capacity(val.len());
+  let mut column = 0;
+  for c in val.chars() {
+    match c {
+      '\t' => {
+        let spaces = width - column % width;
+        res.extend(std::iter::repeat_n(' ', spaces));
+        column += spaces;
+      }
+      '\n' => {
+        res.push(c);
+        column = 0;
+      }
+      c => {
+        res.push(c);
+        column += 1;
+      }
+    }
+  }
+  res
+}
+
+/// Escape the contents of a `"basic string"`. Borrows when there is nothing to escape.
+fn escape_basic<'v>(val: &'v str, options: Options<'_>) -> Cow<'v, str> {
+  if !val.chars().any(|c| needs_escape(c, options)) {
+    return Cow::Borrowed(val);
+  }
+  let mut res = String::with_capacity(val.len() + 8);
+  for c in val.chars() {
+    match c {
+      '\t' if options.string_tabs != StringTabs::Escape => res.push(c),
+      c => push_escaped(&mut res, c),
+    }
+  }
+  Cow::Owned(res)
+}
+
+/// Escape the contents of a `"""multiline string"""`. Newlines stay as is,
+/// quotes are only escaped where they would end the string. Borrows when there is nothing to escape.
+fn escape_multiline<'v>(val: &'v str, options: Options<'_>) -> Cow<'v, str> {
+  if !val
+    .chars()
+    .any(|c| c != '\n' && c != '"' && needs_escape(c, options))
+    && !val.contains("\"\"\"")
+    && !val.ends_with('"')
+  {
+    return Cow::Borrowed(val);
+  }
+  let mut res = String::with_capacity(val.len() + 8);
+  let mut quotes = 0;
+  for (i, c) in val.char_indices() {
+    match c {
+      '"' => {
+        quotes += 1;
+        // Every third quote in a row, and quotes right before the closing delimiter.
+        if quotes == 3 || i + 1 == val.len() {
+          res.push_str("\\\"");
+          quotes = 0;
+        } else {
+          res.push('"');
+        }
+      }
+      '\n' => {
+        quotes = 0;
+        res.push(c);
+      }
+      '\t' if options.string_tabs != StringTabs::Escape => {
+        quotes = 0;
+        res.push(c);
+      }
+      c => {
+        quotes = 0;
+        push_escaped(&mut res, c);
+      }
+    }
+  }
+  Cow::Owned(res)
+}
+
+/// Whether a char has to be escaped in a `"basic string"`.
+fn needs_escape(c: char, options: Options<'_>) -> bool {
+  match c {
+    '"' | '\\' => true,
+    '\t' => options.string_tabs == StringTabs::Escape,
+    c => c < ' ' || c == '\u{7f}',
+  }
+}
+
+fn push_escaped(res: &mut String, c: char) {
+  match c {
+    '"' => res.push_str("\\\""),
+    '\\' => res.push_str("\\\\"),
+    '\n' => res.push_str("\\n"),
+    '\r' => res.push_str("\\r"),
+    '\t' => res.push_str("\\t"),
+    '\u{8}' => res.push_str("\\b"),
+    '\u{c}' => res.push_str("\\f"),
+    c if c < ' ' || c == '\u{7f}' => res.push_str(&format!("\\u{:04X}", c as u32)),
+    c => res.push(c),
+  }
+}
+
+fn inline_table_to_string(map: &Map, cache: &mut KeyCache, options: Options<'_>) -> Result<String> {
+  let options = Options {
+    comments: None,
+    table_headers: false,
+    max_array_items: None,
+    empty_table: match options.empty_table {
+      EmptyTable::Skip => EmptyTable::Skip,
+      EmptyTable::Inline | EmptyTable::Header => EmptyTable::Inline,
+    },
+    ..options.inline_array(true)
+  };
+  let mut res = String::new();
+  options.formatter.begin_inline_table(&mut res);
+  // An explicit stack rather than recursion (through arrays of inline tables),
+  // so deeply nested values can't overflow the stack.
+  let mut stack = vec![Inline::Table {
+    entries: cache.inline_entries(map, options).into_iter(),
+    written: false,
+  }];
+  while let Some(frame) = stack.last_mut() {
+    // Whether an array value is inside of another array.
+    let (val, nested) = match frame {
+      Inline::Table { entries, written } => {
+        let Some((key, val)) = entries.next() else {
+          if *written {
+            res.push(' ');
+          }
+          options.formatter.end_inline_table(&mut res);
+          stack.pop();
+          continue;
+        };
+        if !is_written(val, options) {
+          continue;
+        }
+        res.push_str(if *written { ", " } else { " " });
+        *written = true;
+        options
+          .formatter
+          .write_key(&mut res, &written_key(&key, options));
+        res.push_str(" = ");
+        (val, false)
+      }
+      Inline::Array {
+        vals,
+        nested,
+        written,
+      } => {
+        let Some(val) = vals.next() else {
+          options.formatter.end_array(&mut res);
+          stack.pop();
+          continue;
+        };
+        match val {
+          Value::Null => continue,
+          // Like array_elements, empty strings are only skipped at the top level of the array.
+          Value::String(string) if !*nested && options.skip_empty_string && string.is_empty() => {
+            continue
+          }
+          Value::Array(_) if *nested => return Err(Error::TripleNestedArray),
+          _ => {}
+        }
+        if *written {
+          res.push_str(", ");
+        }
+        *written = true;
+        (val, true)
+      }
+    };
+    match val {
+      Value::Array(vals) => {
+        options.formatter.begin_array(&mut res);
+        stack.push(Inline::Array {
+          vals: vals.iter(),
+          nested,
+          written: false,
+        });
+      }
+      Value::Object(map) => {
+        options.formatter.begin_inline_table(&mut res);
+        stack.push(Inline::Table {
+          entries: cache.inline_entries(map, options).into_iter(),
+          written: false,
+        });
+      }
+      // Inline tables must fit on one line, so strings can't be multi-line.
+      val => write_scalar(&mut res, val, single_line_string_to_string, options),
+    }
+  }
+  Ok(res)
+}
+
+/// A table / array being written by [inline_table_to_string].
+enum Inline<'v> {
+  Table {
+    entries: std::vec::IntoIter<(String, &'v Value)>,
+    written: bool,
+  },
+  Array {
+    vals: std::slice::Iter<'v, Value>,
+    /// Inside of another array, so it can't contain arrays itself.
+    nested: bool,
+    written: bool,
+  },
+}
+
+/// `key` is used to look up comments on individual elements, eg. `servers[2]`.
+/// `width` is the number of chars written before the array on its line, eg. for `key = `.
+fn array_to_string(
+  key: &str,
+  width: usize,
+  vals: &[Value],
+  options: Options<'_>,
+) -> Result<String> {
+  let mut res = String::new();
+  options.formatter.begin_array(&mut res);
+  if vals.is_empty() {
+    options.formatter.end_array(&mut res);
+    return Ok(res);
+  }
+  let (mut strs, comments, truncated) = array_elements(key, vals, options)?;
+  let layout = array_layout(width, &strs, &comments, truncated, options);
+  if layout.inline() {
+    res.push_str(&strs.join(", "));
+    options.formatter.end_array(&mut res);
+    return Ok(res);
+  }
+  if options.align_numbers {
+    if let Some(aligned) = align_numbers(&vals[..strs.len()], options) {
+      strs = aligned;
+    }
+  }
+  let tab = options.array_indent.unwrap_or(options.tab);
+  res.push('\n');
+  for (i, (string, comment)) in strs.iter().zip(comments).enumerate() {
+    if let Some(comment) = comment {
+      write_comment(&mut res, comment, tab);
+    }
+    res.push_str(tab);
+    res.push_str(string);
+    if i + 1 < strs.len() || options.trailing_comma {
+      res.push(',');
+    }
+    res.push('\n');
+  }
+  if truncated > 0 {
+    let items = if truncated == 1 { "item" } else { "items" };
+    write_comment(
+      &mut res,
+      &format!("… {} more {items}", thousands(truncated)),
+      tab,
+    );
+  }
+  options.formatter.end_array(&mut res);
+  Ok(res)
+}
+
+/// Right aligns the elements of a numeric array, or the columns of a matrix.
+/// None if the array isn't numeric.
+fn align_numbers(vals: &[Value], options: Options<'_>) -> Option<Vec<String>> {
+  let number = |val: &Value| match val {
+    Value::Number(number) => Some(number.to_string()),
+    _ => None,
+  };
+  let aligned = |str: &str, width: usize| {
+    let mut res = " ".repeat(width.saturating_sub(str.len()));
+    options.formatter.write_number(&mut res, str);
+    res
+  };
+  if let Some(strs) = vals.iter().map(number).collect::<Option<Vec<_>>>() {
+    let width = strs.iter().map(String::len).max().unwrap_or_default();
+    return Some(strs.iter().map(|str| aligned(str, width)).collect());
+  }
+  let rows = vals
+    .iter()
+    .map(|val| match val {
+      Value::Array(row) => row.iter().map(number).collect::<Option<Vec<_>>>(),
+      _ => None,
+    })
+    .collect::<Option<Vec<_>>>()?;
+  let mut widths = Vec::<usize>::new();
+  for row in &rows {
+    for (i, str) in row.iter().enumerate() {
+      match widths.get_mut(i) {
+        Some(width) => *width = (*width).max(str.len()),
+        None => widths.push(str.len()),
+      }
+    }
+  }
+  let rows = rows.iter().map(|row| {
+    let row = row
+      .iter()
+      .zip(&widths)
+      .map(|(str, width)| aligned(str, *width))
+      .collect::<Vec<_>>();
+    let mut res = String::new();
+    options.formatter.begin_array(&mut res);
+    res.push_str(&row.join(", "));
+    options.formatter.end_array(&mut res);
+    res
+  });
+  Some(rows.collect())
+}
+
+/// The rendered elements of an array, their comments,
+/// and the number of elements cut off by [Options::max_array_items].
+#[allow(clippy::type_complexity)]
+fn array_elements<'o>(
+  key: &str,
+  vals: &[Value],
+  options: Options<'o>,
+) -> Result<(Vec<String>, Vec<Option<&'o str>>, usize)> {
+  let skip_empty_string = options.skip_empty_string;
+  let (vals, truncated) = match options.max_array_items {
+    Some(max) if vals.len() > max => (&vals[..max], vals.len() - max),
+    _ => (vals, 0),
+  };
+  let mut strs = Vec::<String>::with_capacity(vals.len());
+  let mut comments = Vec::<Option<&str>>::with_capacity(vals.len());
+  let mut cache = KeyCache::default();
+  for (i, val) in vals.iter().enumerate() {
+    let len = strs.len();
+    match val {
+      Value::Null => {}
+      Value::String(string) if skip_empty_string && string.is_empty() => continue,
+      Value::Object(map) => strs.push(inline_table_to_string(map, &mut cache, options)?),
+      Value::Array(vals) => {
+        let mut out = Vec::new();
+        for val in vals {
+          match val {
+            Value::Null => {}
+            Value::Object(map) => out.push(inline_table_to_string(map, &mut cache, options)?),
+            Value::Array(_) => return Err(Error::TripleNestedArray),
+            val => {
+              let mut res = String::new();
+              write_scalar(&mut res, val, single_line_string_to_string, options);
+              out.push(res);
+            }
+          }
+        }
+        let mut res = String::new();
+        options.formatter.begin_array(&mut res);
+        res.push_str(&out.join(", "));
+        options.formatter.end_array(&mut res);
+        strs.push(res);
+      }
+      val => {
+        let mut res = String::new();
+        write_scalar(&mut res, val, single_line_string_to_string, options);
+        strs.push(res);
+      }
+    }
+    if strs.len() > len {
+      comments.push(
+        options
+          .comments
+          .and_then(|comments| comments.get(&format!("{key}[{i}]")))
+          .map(String::as_str),
+      );
+    }
+  }
+  Ok((strs, comments, truncated))
+}
+
+/// Why an array is written inline or expanded over multiple lines.
+enum ArrayLayout {
+  Commented,
+  Truncated,
+  InlineArray,
+  Width { len: usize, max: usize },
+  Elements { len: usize, max: usize },
+  Length { len: usize, max: usize },
+}
+
+impl ArrayLayout {
+  fn inline(&self) -> bool {
+    match self {
+      ArrayLayout::Commented | ArrayLayout::Truncated => false,
+      ArrayLayout::InlineArray => true,
+      ArrayLayout::Width { len, max }
+      | ArrayLayout::Elements { len, max }
+      | ArrayLayout::Length { len, max } => len <= max,
+    }
+  }
+}
+
+impl std::fmt::Display for ArrayLayout {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let layout = if self.inline() { "inline" } else { "expanded" };
+    match self {
+      ArrayLayout::Commented => write!(f, "{layout}: elements have comments"),
+      ArrayLayout::Truncated => write!(f, "{layout}: truncated by max_array_items"),
+      ArrayLayout::InlineArray => write!(f, "{layout}: inline_array is set"),
+      ArrayLayout::Width { len, max } => {
+        let cmp = if len <= max { "<=" } else { ">" };
+        write!(f, "{layout}: line width {len} {cmp} max_line_width {max}")
+      }
+      ArrayLayout::Elements { len, max } => {
+        let cmp = if len <= max { "<=" } else { ">" };
+        write!(
+          f,
+          "{layout}: {len} elements {cmp} max_inline_array_elements {max}"
+        )
+      }
+      ArrayLayout::Length { len, max } => {
+        let cmp = if len <= max { "<=" } else { ">" };
+        write!(
+          f,
+          "{layout}: length {len} {cmp} max_inline_array_length {max}"
+        )
+      }
+    }
+  }
+}
+
+fn array_layout(
+  width: usize,
+  strs: &[String],
+  comments: &[Option<&str>],
+  truncated: usize,
+  options: Options<'_>,
+) -> ArrayLayout {
+  if comments.iter().any(Option::is_some) {
+    ArrayLayout::Commented
+  } else if truncated > 0 {
+    ArrayLayout::Truncated
+  } else if options.inline_array {
+    ArrayLayout::InlineArray
+  } else if let Some(max) = options.max_inline_array_elements {
+    ArrayLayout::Elements {
+      len: strs.len(),
+      max,
+    }
+  } else if let Some(max) = options.max_line_width {
+    // The inline array is the elements joined by ", " in brackets.
+    let inline = strs.iter().map(|str| str.chars().count()).sum::<usize>() + 2 * strs.len().max(1);
+    ArrayLayout::Width {
+      len: width + inline,
+      max,
+    }
+  } else {
+    ArrayLayout::Length {
+      len: strs.iter().map(String::len).sum(),
+      max: options.max_inline_array_length,
+    }
+  }
+}
+
+/// Formats a count with `,` thousands separators, eg. `9,987`.
+fn thousands(count: usize) -> String {
+  let digits = count.to_string();
+  let mut res = String::with_capacity(digits.len() + digits.len() / 3);
+  for (i, c) in digits.chars().enumerate() {
+    if i > 0 && (digits.len() - i).is_multiple_of(3) {
+      res.push(',');
+    }
+    res.push(c);
+  }
+  res
+}
+
+fn flatten_map(map: Map) -> Map {
+  let mut target = OrderedHashMap::new();
+  flatten_map_rec(&mut target, None, map);
+  target
+}
+
+/// Flattens `val` beneath the (already quoted) dotted key `path`.
+fn flatten_at(path: String, val: Value) -> Map {
+  let mut target = OrderedHashMap::new();
+  match val {
+    Value::Object(map) if !map.is_empty() => flatten_map_rec(&mut target, Some(path), map),
+    val => {
+      target.insert(path, val);
+    }
+  }
+  target
+}
+
+fn flatten_map_rec(target: &mut Map, parent_field: Option<String>, source: Map) {
+  // An explicit stack rather than recursion, so deeply nested tables can't overflow the stack.
+  let mut stack = vec![(parent_field, source.into_iter())];
+  while let Some((parent_field, source)) = stack.last_mut() {
+    let Some((field, val)) = source.next() else {
+      stack.pop();
+      continue;
+    };
+    let field = quote_key(&field);
+    let field = match parent_field {
+      Some(parent_field) => format!("{parent_field}.{field}"),
+      None => field.into_owned(),
+    };
+    match val {
+      Value::Object(source) if !source.is_empty() => stack.push((Some(field), source.into_iter())),
+      // Empty tables are kept so they can be rendered per EmptyTable
+      val => {
+        target.insert(field, val);
+      }
+    }
+  }
+}
+
+/// The quoted form of keys, by raw key. Shared between the entries of an array of tables
+/// (or of inline tables), which usually repeat the same keys.
+#[derive(Default)]
+struct KeyCache(HashMap<String, String>);
+
+impl KeyCache {
+  fn quote(&mut self, key: &str) -> &str {
+    if !self.0.contains_key(key) {
+      self.0.insert(key.to_string(), quote_key(key).into_owned());
+    }
+    &self.0[key]
+  }
+
+  /// Like [flatten_map], without cloning `map` first.
+  fn flatten(&mut self, map: &Map, nested_empty_table: NestedEmptyTable) -> Map {
+    self
+      .flatten_refs(map, nested_empty_table)
+      .into_iter()
+      .map(|(key, val)| (key, val.clone()))
+      .collect()
+  }
+
+  /// The flattened entries of `map`, borrowing the values,
+  /// with the empty tables placed per `nested_empty_table`.
+  fn flatten_refs<'v>(
+    &mut self,
+    map: &'v Map,
+    nested_empty_table: NestedEmptyTable,
+  ) -> Vec<(String, &'v Value)> {
+    let mut entries = Vec::new();
+    // An explicit stack rather than recursion, so deeply nested tables can't overflow the stack.
+    let mut stack = vec![(String::new(), map.iter())];
+    while let Some((parent_field, source)) = stack.last_mut() {
+      let Some((field, val)) = source.next() else {
+        stack.pop();
+        continue;
+      };
+      let field = self.quote(field);
+      let field = if parent_field.is_empty() {
+        field.to_string()
+      } else {
+        format!("{parent_field}.{field}")
+      };
+      match val {
+        Value::Object(source) if !source.is_empty() => stack.push((field, source.iter())),
+        val => entries.push((field, val)),
+      }
+    }
+    place_empty_tables(entries, nested_empty_table)
+  }
+
+  /// The entries of an inline table, flattened to dotted keys unless [Options::inline_tables].
+  fn inline_entries<'v>(&mut self, map: &'v Map, options: Options<'_>) -> Vec<(String, &'v Value)> {
+    if !options.inline_tables {
+      return self.flatten_refs(map, options.nested_empty_table);
+    }
+    map
+      .iter()
+      .map(|(key, val)| (self.quote(key).to_string(), val))
+      .collect()
+  }
+}
+
+// Flattens a nested bson document using the mongo '.' syntax. Useful for partial updates.
+// doc! { "f1": "yes", "f2": { "f3": "no" } } -> doc! { "f1": "yes", "f2.f3": "no" }
+// pub fn flatten_document(doc: Document) -> Document {
+//   let mut target = Document::new();
+//   flatten_document_rec(&mut target, None, doc);
+//   target
+// }
+
+/// Assert that two toml documents are semantically equal, ignoring formatting
+/// (key order, quoting, dotted keys vs tables, whitespace).
+/// On failure, panics with the differing key paths.
+///
+/// Either compare two toml strings, or a serializable value against a toml string:
+/// ```
+/// # #[derive(serde::Serialize)]
+/// # struct Config { port: u16 }
+/// toml_pretty::assert_toml_eq!("a = 1\nb.c = 2", "b = { c = 2 }\na = 1");
+/// toml_pretty::assert_toml_eq!(Config { port: 80 } => "port = 80");
+/// ```
+#[macro_export]
+macro_rules! assert_toml_eq {
+  ($value:expr => $expected:expr $(,)?) => {
+    $crate::__assert_toml_eq(
+      $crate::Document::new(&$value),
+      $crate::Document::parse(::core::convert::AsRef::<str>::as_ref(&$expected)),
+    )
+  };
+  ($left:expr, $right:expr $(,)?) => {
+    $crate::__assert_toml_eq(
+      $crate::Document::parse(::core::convert::AsRef::<str>::as_ref(&$left)),
+      $crate::Document::parse(::core::convert::AsRef::<str>::as_ref(&$right)),
+    )
+  };
+}
+
+#[doc(hidden)]
+#[track_caller]
+pub fn __assert_toml_eq(left: Result<Document>, right: Result<Document>) {
+  let left = left.unwrap_or_else(|e| panic!("assert_toml_eq: invalid left document: {e}"));
+  let right = right.unwrap_or_else(|e| panic!("assert_toml_eq: invalid right document: {e}"));
+  let diff = left.diff(&right);
+  if !diff.is_empty() {
+    panic!(
+      "assertion `left == right` failed: toml documents differ\n{}",
+      diff.join("\n")
+    );
+  }
+}
@@ -0,0 +1,962 @@
+use std::ops::Range;
+
+use crate::{datetime::days_in_month, join_key, CommentMap, Error, Map, Number, Result, Value};
+use ordered_hash_map::OrderedHashMap;
+
+/// Parse toml text into its root table. Nested tables become [Value::Object],
+/// arrays of tables become [Value::Array] of objects, and datetimes / special floats
+/// (`inf`, `nan`) are kept as their toml text in [Value::String].
+pub(crate) fn parse(input: &str) -> Result<Map> {
+  let mut parser = Parser::new(input);
+  let root = parser.parse_document()?;
+  Ok(table_to_map(root))
+}
+
+/// Parse toml text, returning only its comments, keyed by path like [Options::comments](crate::Options::comments).
+/// Comments go to the key / table header / array element below them, or on the same line.
+/// Comments followed by a blank line (eg. a license header) are dropped.
+pub(crate) fn parse_comments(input: &str) -> Result<CommentMap> {
+  let mut parser = Parser::new(input);
+  parser.parse_document()?;
+  Ok(parser.comments)
+}
+
+/// Parse toml text, returning where its keys and headers are.
+pub(crate) fn parse_layout(input: &str) -> Result<Layout> {
+  let mut parser = Parser::new(input);
+  parser.parse_document()?;
+  Ok(parser.layout)
+}
+
+/// Where the keys and table headers of a toml document are, to re-render it with new values.
+#[derive(Default)]
+pub(crate) struct Layout {
+  pub entries: Vec<EntrySpan>,
+  pub headers: Vec<HeaderSpan>,
+  /// The end of the root table's last key line (0 if it has no keys).
+  pub root_end: usize,
+}
+
+pub(crate) struct EntrySpan {
+  /// Like comment paths, keys beneath `[[array]]` headers include the index, eg. `servers[0].name`.
+  pub path: String,
+  pub value: Range<usize>,
+  /// The whole line, including the newline.
+  pub line: Range<usize>,
+}
+
+pub(crate) struct HeaderSpan {
+  pub path: String,
+  pub array: bool,
+  /// The end of the table's last key line, or of the header line if it has no keys.
+  pub end: usize,
+}
+
+/// Parse the text of a single toml value, eg. `"localhost"` or `[1, 2]`.
+pub(crate) fn parse_value(input: &str) -> Result<Value> {
+  let mut parser = Parser::new(input);
+  parser.skip_whitespace();
+  let val = parser.parse_value()?;
+  parser.skip_blank();
+  if parser.peek().is_some() {
+    return Err(parser.error("expected end of value"));
+  }
+  Ok(val)
+}
+
+/// Split a dotted key (eg. `target."cfg(unix)".dependencies`) into its unquoted segments.
+pub(crate) fn split_key(input: &str) -> Result<Vec<String>> {
+  let mut parser = Parser::new(input);
+  let key = parser.parse_key()?;
+  if parser.peek().is_some() {
+    return Err(parser.error("expected end of key"));
+  }
+  Ok(key)
+}
+
+/// How deeply arrays / inline tables, and the segments of keys, may nest.
+/// Deeper input is an error rather than overflowing the stack.
+const MAX_NESTING: usize = 128;
+
+enum Item {
+  Value(Value),
+  Table(Table, Defined),
+  Tables(Vec<Table>),
+}
+
+/// How a table was defined, which decides how it may be extended.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Defined {
+  /// Only as a parent of another table, eg. `a` for `[a.b]`, so it can still get a header.
+  Implicitly,
+  /// By its `[table]` header.
+  Header,
+  /// By dotted keys, eg. `a.b = 1`, so it can't get a header.
+  Dotted,
+  /// As an inline table, which can't be extended at all.
+  Inline,
+}
+
+type Table = OrderedHashMap<String, Item>;
+
+struct Parser<'a> {
+  input: &'a str,
+  pos: usize,
+  comments: CommentMap,
+  /// Comment lines waiting for the next key / header / array element.
+  pending: Vec<String>,
+  /// The comment path of the value being parsed.
+  path: String,
+  layout: Layout,
+  /// The number of arrays / inline tables the parser is inside of.
+  depth: usize,
+}
+
+impl<'a> Parser<'a> {
+  fn new(input: &'a str) -> Parser<'a> {
+    Parser {
+      input,
+      pos: 0,
+      comments: CommentMap::new(),
+      pending: Vec::new(),
+      path: String::new(),
+      layout: Layout::default(),
+      depth: 0,
+    }
+  }
+
+  fn parse_document(&mut self) -> Result<Table> {
+    let mut root = Table::new();
+    let mut header = Vec::<String>::new();
+    // The header as a comment path, including the index of arrays of tables.
+    let mut header_path = String::new();
+    loop {
+      self.skip_blank_lines();
+      let line_start = self.input[..self.pos].rfind('\n').map_or(0, |i| i + 1);
+      let mut value = None;
+      match self.peek() {
+        None => break,
+        Some('[') => {
+          let array = self.rest().starts_with("[[");
+          self.pos += if array { 2 } else { 1 };
+          let path = self.parse_key()?;
+          self.expect(if array { "]]" } else { "]" })?;
+          let Some((last, parents)) = path.split_last() else {
+            return Err(self.error("expected key"));
+          };
+          let parent =
+            open(&mut root, &[], parents, false).map_err(|message| self.error(message))?;
+          let name = path.join(".");
+          if array {
+            let index = match parent.get_mut(last) {
+              None => {
+                parent.insert(last.clone(), Item::Tables(vec![Table::new()]));
+                0
+              }
+              Some(Item::Tables(tables)) => {
+                tables.push(Table::new());
+                tables.len() - 1
+              }
+              Some(_) => return Err(self.error(format!("{name} is not an array of tables"))),
+            };
+            header_path = format!("{}[{index}]", join_key(&path));
+          } else {
+            match parent.get_mut(last) {
+              None => {
+                parent.insert(last.clone(), Item::Table(Table::new(), Defined::Header));
+              }
+              Some(Item::Table(_, defined @ Defined::Implicitly)) => *defined = Defined::Header,
+              Some(Item::Table(_, Defined::Header)) => {
+                return Err(self.error(format!("duplicate table {name}")))
+              }
+              Some(Item::Table(_, Defined::Dotted)) => {
+                return Err(self.error(format!("{name} is already defined by dotted keys")))
+              }
+              Some(Item::Table(_, Defined::Inline)) => {
+                return Err(self.error(format!("{name} is already defined as an inline table")))
+              }
+              Some(Item::Tables(_)) => {
+                return Err(self.error(format!("{name} is an array of tables")))
+              }
+              Some(Item::Value(_)) => return Err(self.not_a_table(&path)),
+            }
+            header_path = join_key(&path);
+          }
+          self.path.clone_from(&header_path);
+          self.take_pending();
+          self.layout.headers.push(HeaderSpan {
+            path: join_key(&path),
+            array,
+            end: 0,
+          });
+          header = path;
+        }
+        Some(_) => {
+          let key = self.parse_key()?;
+          self.path = if header_path.is_empty() {
+            join_key(&key)
+          } else {
+            format!("{header_path}.{}", join_key(&key))
+          };
+          self.take_pending();
+          self.expect("=")?;
+          self.skip_whitespace();
+          let value_start = self.pos;
+          let item = self.parse_item()?;
+          value = Some(value_start..self.pos);
+          let full = [header.as_slice(), key.as_slice()].concat();
+          if full.len() > MAX_NESTING {
+            return Err(self.error("key nested too deeply"));
+          }
+          // The header was checked when it was parsed.
+          let table = open(&mut root, &[], &header, false)
+            .and_then(|table| open(table, &header, &key[..key.len() - 1], true))
+            .map_err(|message| self.error(message))?;
+          self.insert(table, &full, item)?;
+        }
+      }
+      // A comment on the same line as the key / header.
+      self.end_of_line()?;
+      self.append_pending();
+      if let Some(value) = value {
+        self.layout.entries.push(EntrySpan {
+          path: self.path.clone(),
+          value,
+          line: line_start..self.pos,
+        });
+      }
+      match self.layout.headers.last_mut() {
+        Some(header) => header.end = self.pos,
+        None => self.layout.root_end = self.pos,
+      }
+    }
+    Ok(root)
+  }
+
+  fn insert(&self, table: &mut Table, path: &[String], item: Item) -> Result<()> {
+    let Some(last) = path.last() else {
+      return Err(self.error("expected key"));
+    };
+    if table.contains_key(last) {
+      return Err(self.error(format!("duplicate key {}", path.join("."))));
+    }
+    table.insert(last.clone(), item);
+    Ok(())
+  }
+
+  /// Parses a (possibly dotted) key, along with surrounding whitespace.
+  fn parse_key(&mut self) -> Result<Vec<String>> {
+    let mut path = Vec::new();
+    loop {
+      self.skip_whitespace();
+      let segment = match self.peek() {
+        Some('"') => self.parse_basic_string()?,
+        Some('\'') => self.parse_literal_string()?,
+        _ => {
+          let len = self
+            .rest()
+            .find(|c: char| !is_bare_key_char(c))
+            .unwrap_or(self.rest().len());
+          if len == 0 {
+            return Err(self.error("expected key"));
+          }
+          let segment = self.rest()[..len].to_string();
+          self.pos += len;
+          segment
+        }
+      };
+      path.push(segment);
+      if path.len() > MAX_NESTING {
+        return Err(self.error("key nested too deeply"));
+      }
+      self.skip_whitespace();
+      if self.peek() == Some('.') {
+        self.pos += 1;
+      } else {
+        return Ok(path);
+      }
+    }
+  }
+
+  /// Parses the value on the right hand side of `key = `.
+  /// Inline tables are kept as tables so they flatten like dotted keys.
+  fn parse_item(&mut self) -> Result<Item> {
+    if self.peek() == Some('{') {
+      let table = self.nested(Parser::parse_inline_table)?;
+      Ok(Item::Table(table, Defined::Inline))
+    } else {
+      self.parse_value().map(Item::Value)
+    }
+  }
+
+  fn parse_value(&mut self) -> Result<Value> {
+    match self.peek() {
+      Some('"') if self.rest().starts_with("\"\"\"") => {
+        self.parse_multiline_basic_string().map(Value::String)
+      }
+      Some('"') => self.parse_basic_string().map(Value::String),
+      Some('\'') if self.rest().starts_with("'''") => {
+        self.parse_multiline_literal_string().map(Value::String)
+      }
+      Some('\'') => self.parse_literal_string().map(Value::String),
+      Some('[') => self.nested(Parser::parse_array),
+      Some('{') => {
+        let table = self.nested(Parser::parse_inline_table)?;
+        Ok(Value::Object(table_to_object(table)))
+      }
+      Some(_) => self.parse_scalar(),
+      None => Err(self.error("expected value")),
+    }
+  }
+
+  /// Parses an array / inline table with `parse`, one level deeper.
+  fn nested<T>(&mut self, parse: fn(&mut Self) -> Result<T>) -> Result<T> {
+    if self.depth == MAX_NESTING {
+      return Err(self.error("nested too deeply"));
+    }
+    self.depth += 1;
+    let res = parse(self);
+    self.depth -= 1;
+    res
+  }
+
+  fn parse_array(&mut self) -> Result<Value> {
+    self.pos += 1;
+    let mut vals = Vec::new();
+    let path_len = self.path.len();
+    loop {
+      self.skip_blank();
+      if self.peek() == Some(']') {
+        self.pos += 1;
+        self.pending.clear();
+        return Ok(Value::Array(vals));
+      }
+      self.path.truncate(path_len);
+      self.path.push_str(&format!("[{}]", vals.len()));
+      self.take_pending();
+      vals.push(self.parse_value()?);
+      self.skip_whitespace();
+      let comma = self.peek() == Some(',');
+      if comma {
+        self.pos += 1;
+        self.skip_whitespace();
+      }
+      // A comment on the same line as the element.
+      if self.peek() == Some('#') {
+        self.skip_comment();
+        self.append_pending();
+      }
+      self.path.truncate(path_len);
+      self.skip_blank();
+      if !comma {
+        if self.next_char() != Some(']') {
+          return Err(self.error("expected ',' or ']' in array"));
+        }
+        self.pending.clear();
+        return Ok(Value::Array(vals));
+      }
+    }
+  }
+
+  fn parse_inline_table(&mut self) -> Result<Table> {
+    self.pos += 1;
+    let mut table = Table::new();
+    self.skip_whitespace();
+    if self.peek() == Some('}') {
+      self.pos += 1;
+      return Ok(table);
+    }
+    loop {
+      let key = self.parse_key()?;
+      self.expect("=")?;
+      self.skip_whitespace();
+      let item = self.parse_item()?;
+      let parent = open(&mut table, &[], &key[..key.len() - 1], true)
+        .map_err(|message| self.error(message))?;
+      self.insert(parent, &key, item)?;
+      self.skip_whitespace();
+      match self.next_char() {
+        Some(',') => {}
+        Some('}') => return Ok(table),
+        _ => return Err(self.error("expected ',' or '}' in inline table")),
+      }
+    }
+  }
+
+  fn parse_basic_string(&mut self) -> Result<String> {
+    self.pos += 1;
+    let mut res = String::new();
+    loop {
+      match self.next_char() {
+        Some('"') => return Ok(res),
+        Some('\\') => res.push(self.parse_escape()?),
+        Some('\n') | None => return Err(self.error("unterminated string")),
+        Some(c) if is_control(c) => return Err(self.control_character(c)),
+        Some(c) => res.push(c),
+      }
+    }
+  }
+
+  fn parse_multiline_basic_string(&mut self) -> Result<String> {
+    self.pos += 3;
+    self.skip_newline();
+    let mut res = String::new();
+    loop {
+      if self.rest().starts_with("\"\"\"") {
+        // Up to two quotes directly before the closing delimiter belong to the content.
+        let extra = self.rest()[3..]
+          .chars()
+          .take(2)
+          .take_while(|c| *c == '"')
+          .count();
+        res.extend(std::iter::repeat_n('"', extra));
+        self.pos += 3 + extra;
+        return Ok(res);
+      }
+      match self.next_char() {
+        Some('\\') => {
+          let trimmed = self.rest().trim_start_matches([' ', '\t']);
+          if trimmed.starts_with('\n') || trimmed.starts_with("\r\n") {
+            // Line ending backslash trims all whitespace up to the next content.
+            let content = self.rest().trim_start_matches([' ', '\t', '\r', '\n']);
+            self.pos = self.input.len() - content.len();
+          } else {
+            res.push(self.parse_escape()?);
+          }
+        }
+        Some('\r') if !self.rest().starts_with('\n') => return Err(self.control_character('\r')),
+        Some(c) if is_control(c) && !matches!(c, '\n' | '\r') => {
+          return Err(self.control_character(c))
+        }
+        Some(c) => res.push(c),
+        None => return Err(self.error("unterminated multiline string")),
+      }
+    }
+  }
+
+  fn parse_literal_string(&mut self) -> Result<String> {
+    self.pos += 1;
+    let Some(len) = self.rest().find(['\'', '\n']) else {
+      return Err(self.error("unterminated literal string"));
+    };
+    if !self.rest()[len..].starts_with('\'') {
+      return Err(self.error("unterminated literal string"));
+    }
+    if let Some(c) = self.rest()[..len].chars().find(|c| is_control(*c)) {
+      return Err(self.control_character(c));
+    }
+    let res = self.rest()[..len].to_string();
+    self.pos += len + 1;
+    Ok(res)
+  }
+
+  fn parse_multiline_literal_string(&mut self) -> Result<String> {
+    self.pos += 3;
+    self.skip_newline();
+    let Some(len) = self.rest().find("'''") else {
+      return Err(self.error("unterminated multiline literal string"));
+    };
+    let extra = self.rest()[len + 3..]
+      .chars()
+      .take(2)
+      .take_while(|c| *c == '\'')
+      .count();
+    let res = self.rest()[..len + extra].to_string();
+    let mut chars = res.chars().peekable();
+    while let Some(c) = chars.next() {
+      let line_break = c == '\n' || (c == '\r' && chars.peek() == Some(&'\n'));
+      if is_control(c) && !line_break {
+        return Err(self.control_character(c));
+      }
+    }
+    self.pos += len + extra + 3;
+    Ok(res)
+  }
+
+  /// Parses the escape sequence following a backslash.
+  fn parse_escape(&mut self) -> Result<char> {
+    let c = match self.next_char() {
+      Some('b') => '\u{8}',
+      Some('t') => '\t',
+      Some('n') => '\n',
+      Some('f') => '\u{c}',
+      Some('r') => '\r',
+      Some('e') => '\u{1b}',
+      Some('"') => '"',
+      Some('\\') => '\\',
+      Some('u') => self.parse_unicode_escape(4)?,
+      Some('U') => self.parse_unicode_escape(8)?,
+      _ => return Err(self.error("invalid escape sequence")),
+    };
+    Ok(c)
+  }
+
+  fn parse_unicode_escape(&mut self, len: usize) -> Result<char> {
+    let hex = self.rest().get(..len).unwrap_or_default();
+    let c = u32::from_str_radix(hex, 16)
+      .ok()
+      .and_then(char::from_u32)
+      .ok_or_else(|| self.error("invalid unicode escape"))?;
+    self.pos += len;
+    Ok(c)
+  }
+
+  /// Parses booleans, numbers, and datetimes.
+  fn parse_scalar(&mut self) -> Result<Value> {
+    let mut len = self
+      .rest()
+      .find(|c: char| c.is_whitespace() || matches!(c, ',' | ']' | '}' | '#'))
+      .unwrap_or(self.rest().len());
+    // A date may be separated from its time by a space.
+    if is_date(&self.rest()[..len]) && self.rest()[len..].starts_with(' ') {
+      let time = &self.rest()[len + 1..];
+      let bytes = time.as_bytes();
+      if bytes.len() >= 3 && bytes[..2].iter().all(u8::is_ascii_digit) && bytes[2] == b':' {
+        len += 1
+          + time
+            .find(|c: char| c.is_whitespace() || matches!(c, ',' | ']' | '}' | '#'))
+            .unwrap_or(time.len());
+      }
+    }
+    let token = &self.rest()[..len];
+    let val = match token {
+      "true" => Value::Bool(true),
+      "false" => Value::Bool(false),
+      "inf" | "+inf" | "-inf" | "nan" | "+nan" | "-nan" => Value::String(token.to_string()),
+      _ if is_date(token) || token.contains(':') => {
+        if !is_datetime(token) {
+          return Err(self.error(format!("invalid datetime {token}")));
+        }
+        Value::String(token.to_string())
+      }
+      _ => parse_number(token).ok_or_else(|| self.error(format!("invalid value {token}")))?,
+    };
+    self.pos += len;
+    Ok(val)
+  }
+
+  /// After a key / value pair or header, only a comment may follow on the line.
+  fn end_of_line(&mut self) -> Result<()> {
+    self.skip_whitespace();
+    self.skip_comment();
+    if self.peek().is_none() || self.skip_newline() {
+      Ok(())
+    } else {
+      Err(self.error("expected newline"))
+    }
+  }
+
+  fn expect(&mut self, token: &str) -> Result<()> {
+    self.skip_whitespace();
+    if self.rest().starts_with(token) {
+      self.pos += token.len();
+      Ok(())
+    } else {
+      Err(self.error(format!("expected '{token}'")))
+    }
+  }
+
+  /// Attach the pending comment lines to the current path.
+  fn take_pending(&mut self) {
+    if !self.pending.is_empty() {
+      let comment = std::mem::take(&mut self.pending).join("\n");
+      self.comments.insert(self.path.clone(), comment);
+    }
+  }
+
+  /// Add the pending comment lines to any comment the current path already has.
+  fn append_pending(&mut self) {
+    if self.pending.is_empty() {
+      return;
+    }
+    let comment = std::mem::take(&mut self.pending).join("\n");
+    match self.comments.get_mut(&self.path) {
+      Some(existing) => {
+        existing.push('\n');
+        existing.push_str(&comment);
+      }
+      None => {
+        self.comments.insert(self.path.clone(), comment);
+      }
+    }
+  }
+
+  /// Like [Parser::skip_blank], but comments followed by a blank line are dropped.
+  fn skip_blank_lines(&mut self) {
+    loop {
+      self.skip_whitespace();
+      let comment = self.peek() == Some('#');
+      self.skip_comment();
+      if !self.skip_newline() {
+        return;
+      }
+      if !comment {
+        self.pending.clear();
+      }
+    }
+  }
+
+  /// Skips whitespace, newlines, and comments.
+  fn skip_blank(&mut self) {
+    loop {
+      self.skip_whitespace();
+      self.skip_comment();
+      if !self.skip_newline() {
+        return;
+      }
+    }
+  }
+
+  fn skip_whitespace(&mut self) {
+    let rest = self.rest().trim_start_matches([' ', '\t']);
+    self.pos = self.input.len() - rest.len();
+  }
+
+  fn skip_comment(&mut self) {
+    if self.peek() == Some('#') {
+      let start = self.pos + 1;
+      self.pos += self.rest().find('\n').unwrap_or(self.rest().len());
+      if self.input[..self.pos].ends_with('\r') {
+        self.pos -= 1;
+      }
+      let line = &self.input[start..self.pos];
+      self.pending.push(
+        line
+          .strip_prefix(' ')
+          .unwrap_or(line)
+          .trim_end()
+          .to_string(),
+      );
+    }
+  }
+
+  fn skip_newline(&mut self) -> bool {
+    if self.rest().starts_with('\n') {
+      self.pos += 1;
+      true
+    } else if self.rest().starts_with("\r\n") {
+      self.pos += 2;
+      true
+    } else {
+      false
+    }
+  }
+
+  fn rest(&self) -> &str {
+    &self.input[self.pos..]
+  }
+
+  fn peek(&self) -> Option<char> {
+    self.rest().chars().next()
+  }
+
+  fn next_char(&mut self) -> Option<char> {
+    let c = self.peek()?;
+    self.pos += c.len_utf8();
+    Some(c)
+  }
+
+  fn control_character(&self, c: char) -> Error {
+    self.error(format!("control character {c:?} in string"))
+  }
+
+  fn not_a_table(&self, path: &[String]) -> Error {
+    self.error(format!("{} is not a table", path.join(".")))
+  }
+
+  fn error(&self, message: impl Into<String>) -> Error {
+    Error::Parse {
+      line: self.input[..self.pos].matches('\n').count() + 1,
+      message: message.into(),
+    }
+  }
+}
+
+/// Get the table at `path` (relative to `table`, itself at `base`), creating missing tables along the way.
+/// For the tables of a `dotted` key, which may only extend tables defined by dotted keys.
+/// Otherwise (for headers), arrays of tables resolve to their last entry.
+/// Errs with a message when the path runs into a value, or a table which can't be extended.
+fn open<'t>(
+  mut table: &'t mut Table,
+  base: &[String],
+  path: &[String],
+  dotted: bool,
+) -> std::result::Result<&'t mut Table, String> {
+  for (i, segment) in path.iter().enumerate() {
+    let name = || [base, &path[..=i]].concat().join(".");
+    if !table.contains_key(segment) {
+      let defined = if dotted {
+        Defined::Dotted
+      } else {
+        Defined::Implicitly
+      };
+      table.insert(segment.clone(), Item::Table(Table::new(), defined));
+    }
+    table = match table.get_mut(segment) {
+      Some(Item::Table(_, Defined::Inline)) => {
+        return Err(format!(
+          "{} is an inline table, which can't be extended",
+          name()
+        ))
+      }
+      Some(Item::Table(_, Defined::Header)) if dotted => {
+        return Err(format!("{} is already defined by its header", name()))
+      }
+      Some(Item::Table(table, defined)) => {
+        if dotted {
+          *defined = Defined::Dotted;
+        }
+        table
+      }
+      Some(Item::Tables(_)) if dotted => return Err(format!("{} is an array of tables", name())),
+      Some(Item::Tables(tables)) => match tables.last_mut() {
+        Some(table) => table,
+        None => return Err(format!("{} is an empty array of tables", name())),
+      },
+      Some(Item::Value(_)) | None => return Err(format!("{} is not a table", name())),
+    };
+  }
+  Ok(table)
+}
+
+fn table_to_map(table: Table) -> Map {
+  table
+    .into_iter()
+    .map(|(key, item)| (key, item_to_value(item)))
+    .collect()
+}
+
+fn table_to_object(table: Table) -> Map {
+  table
+    .into_iter()
+    .map(|(key, item)| (key, item_to_value(item)))
+    .collect()
+}
+
+fn item_to_value(item: Item) -> Value {
+  match item {
+    Item::Value(val) => val,
+    Item::Table(table, _) => Value::Object(table_to_object(table)),
+    Item::Tables(tables) => Value::Array(
+      tables
+        .into_iter()
+        .map(|table| Value::Object(table_to_object(table)))
+        .collect(),
+    ),
+  }
+}
+
+/// Control characters can't be written raw in strings, apart from tabs (and line breaks in multiline strings).
+fn is_control(c: char) -> bool {
+  (c < ' ' && c != '\t') || c == '\u{7f}'
+}
+
+fn is_bare_key_char(c: char) -> bool {
+  c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+/// Whether the (underscore free) token is a decimal integer or float,
+/// with no leading zeros and digits on both sides of any `.`.
+fn is_decimal(token: &str) -> bool {
+  let token = token.strip_prefix(['+', '-']).unwrap_or(token);
+  let (mantissa, exponent) = match token.split_once(['e', 'E']) {
+    Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+    None => (token, None),
+  };
+  let (int, fraction) = match mantissa.split_once('.') {
+    Some((int, fraction)) => (int, Some(fraction)),
+    None => (mantissa, None),
+  };
+  let digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+  digits(int)
+    && (int == "0" || !int.starts_with('0'))
+    && fraction.is_none_or(digits)
+    && exponent.is_none_or(|exponent| digits(exponent.strip_prefix(['+', '-']).unwrap_or(exponent)))
+}
+
+/// Whether the token starts with a full date, eg. `1979-05-27`.
+fn is_date(token: &str) -> bool {
+  let bytes = token.as_bytes();
+  bytes.len() >= 10
+    && bytes[..4].iter().all(u8::is_ascii_digit)
+    && bytes[4] == b'-'
+    && bytes[5..7].iter().all(u8::is_ascii_digit)
+    && bytes[7] == b'-'
+    && bytes[8..10].iter().all(u8::is_ascii_digit)
+}
+
+/// Whether the token is a valid datetime, offset datetime, local date or local time.
+/// Seconds may be left out, as in toml 1.1.
+fn is_datetime(token: &str) -> bool {
+  if !is_date(token) {
+    return is_time(token, false);
+  }
+  let field = |range: Range<usize>| token[range].parse::<i64>().unwrap_or(0);
+  let (year, month, day) = (field(0..4), field(5..7), field(8..10));
+  if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+    return false;
+  }
+  match token.as_bytes().get(10) {
+    None => true,
+    Some(b'T' | b't' | b' ') => is_time(&token[11..], true),
+    Some(_) => false,
+  }
+}
+
+/// Whether the token is a time, eg. `07:32`, `07:32:00.999` or (when `offset` is allowed) `07:32:00-07:00`.
+fn is_time(token: &str, offset: bool) -> bool {
+  let two_digits = |part: &str, max: u8| {
+    part.len() == 2
+      && part.bytes().all(|b| b.is_ascii_digit())
+      && part.parse::<u8>().unwrap_or(99) <= max
+  };
+  let (time, zone) = match token.find(['Z', 'z', '+', '-']) {
+    Some(i) if offset => token.split_at(i),
+    Some(_) => return false,
+    None => (token, ""),
+  };
+  let (time, fraction) = time.split_once('.').unwrap_or((time, "0"));
+  let mut parts = time.split(':');
+  let valid_time = two_digits(parts.next().unwrap_or_default(), 23)
+    && two_digits(parts.next().unwrap_or_default(), 59)
+    && parts.next().is_none_or(|seconds| two_digits(seconds, 60))
+    && parts.next().is_none()
+    && !fraction.is_empty()
+    && fraction.bytes().all(|b| b.is_ascii_digit());
+  let valid_zone = match zone {
+    "" | "Z" | "z" => true,
+    _ if zone.starts_with(['+', '-']) => zone[1..]
+      .split_once(':')
+      .is_some_and(|(hours, minutes)| two_digits(hours, 23) && two_digits(minutes, 59)),
+    _ => false,
+  };
+  valid_time && valid_zone
+}
+
+fn parse_number(token: &str) -> Option<Value> {
+  let (prefix, radix) = match token.get(..2) {
+    Some("0x") => ("0x", 16),
+    Some("0o") => ("0o", 8),
+    Some("0b") => ("0b", 2),
+    _ => ("", 10),
+  };
+  // Underscores must sit between two digits.
+  let bytes = &token.as_bytes()[prefix.len()..];
+  let is_digit = |b: &u8| (*b as char).is_digit(radix);
+  let underscores = bytes
+    .windows(3)
+    .all(|w| w[1] != b'_' || (is_digit(&w[0]) && is_digit(&w[2])));
+  if !underscores || bytes.first() == Some(&b'_') || bytes.last() == Some(&b'_') {
+    return None;
+  }
+  let digits = token[prefix.len()..].replace('_', "");
+  if radix != 10 {
+    if digits.is_empty() || !digits.bytes().all(|b| is_digit(&b)) {
+      return None;
+    }
+    return i64::from_str_radix(&digits, radix)
+      .ok()
+      .map(|int| Value::Number(int.into()));
+  }
+  if !is_decimal(&digits) {
+    return None;
+  }
+  if digits.contains(['.', 'e', 'E']) {
+    let float = digits.parse::<f64>().ok()?;
+    return Number::from_f64(float).map(Value::Number);
+  }
+  digits
+    .parse::<i64>()
+    .ok()
+    .map(|int| Value::Number(int.into()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn valid(input: &str) {
+    if let Err(e) = parse(input) {
+      panic!("failed to parse {input:?}: {e}");
+    }
+  }
+
+  fn invalid(input: &str) {
+    assert!(parse(input).is_err(), "parsed invalid {input:?}");
+  }
+
+  #[test]
+  fn tables() {
+    valid("[a.b]\nx = 1\n[a]\ny = 2");
+    valid("[a]\nb.c = 1\n[a.b.d]\nx = 1");
+    valid("[[a]]\nx = 1\n[[a]]\nx = 2\n[a.b]\ny = 1");
+    valid("a = { b.c = 1, b.d = 2 }");
+    invalid("[a]\n[a]");
+    invalid("[a.b]\n[a]\n[a]");
+    invalid("[a]\nb.c = 1\n[a.b]");
+    invalid("[a.b]\nx = 1\n[a]\nb.y = 2");
+    invalid("[[a]]\n[a]");
+    invalid("a = []\n[[a]]");
+  }
+
+  #[test]
+  fn inline_tables_are_closed() {
+    invalid("a = { b = 1 }\na.c = 2");
+    invalid("a = { b = 1 }\n[a]");
+    invalid("a = { b = 1 }\n[a.c]");
+    invalid("a = { b = { c = 1 }, b.d = 2 }");
+  }
+
+  #[test]
+  fn numbers() {
+    let map = parse("a = 0\nb = -0\nc = 1_000\nd = 0.5\ne = 1e06").unwrap();
+    assert_eq!(map.get("c"), Some(&Value::from(1000)));
+    assert_eq!(map.get("e"), Some(&Value::from(1e6)));
+    valid("a = 0xdead_beef\nb = 0o17\nc = 0b1_0\nd = +1.5e-3");
+    for number in [
+      "01", "+01", "-01", "01.5", "1.", ".5", "1.e5", "1__0", "1_", "_1", "1_.5", "0x", "0x+1",
+      "0x_1",
+    ] {
+      invalid(&format!("a = {number}"));
+    }
+  }
+
+  #[test]
+  fn strings() {
+    valid("a = \"tab\tseparated\"\nb = 'tab\tseparated'\nc = \"\"\"\r\nline\r\n\"\"\"");
+    for string in [
+      "\"a\u{0}b\"",
+      "'a\u{1b}b'",
+      "\"\"\"a\u{7f}\"\"\"",
+      "'''a\rb'''",
+      "\"\"\"a\rb\"\"\"",
+    ] {
+      invalid(&format!("a = {string}"));
+    }
+  }
+
+  #[test]
+  fn datetimes() {
+    valid("a = 1979-05-27T07:32:00Z\nb = 1979-05-27 07:32:00.999-07:00\nc = 2000-02-29\nd = 07:32\ne = 23:59:60");
+    for datetime in [
+      "1979-13-45",
+      "1979-02-29",
+      "1979-05-27T24:00:00",
+      "1979-05-27T07:60",
+      "07:32Z",
+      "1979-05-27T07:32+7",
+    ] {
+      invalid(&format!("a = {datetime}"));
+    }
+  }
+
+  #[test]
+  fn nesting_limit() {
+    let deep = |depth: usize| format!("a = {}{}", "[".repeat(depth), "]".repeat(depth));
+    valid(&deep(MAX_NESTING));
+    invalid(&deep(MAX_NESTING + 1));
+    invalid(&"[".repeat(50_000));
+    invalid(&format!("a = {}", "{ b = ".repeat(50_000)));
+    invalid(&format!("{} = 1", vec!["a"; 50_000].join(".")));
+  }
+}
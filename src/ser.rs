@@ -0,0 +1,825 @@
+use std::cell::{Cell, RefCell};
+
+use serde::ser::{self, Impossible, Serialize};
+
+use crate::{
+  quote_key, value::SPECIAL_FLOAT, BigIntegers, Error, Map, NonUtf8, Number, Result, Value, Warning,
+};
+
+/// Serializes any [Serialize] value into a [Value], keeping the order of struct fields / map entries.
+/// The representation matches serde_json's, eg. enum variants become `{ variant = ... }`.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct ValueSerializer<'a> {
+  /// Sort the keys of maps, leaving struct fields in declaration order.
+  pub sort_maps: bool,
+  pub non_utf8: NonUtf8,
+  pub big_integers: BigIntegers,
+  pub max_depth: Option<usize>,
+  /// The number of tables / arrays the serializer is inside of.
+  pub depth: usize,
+  pub max_keys: Option<usize>,
+  /// Counts the keys serialized so far, when `max_keys` is set.
+  pub keys: Option<&'a Cell<usize>>,
+  /// Collects warnings, and in lenient mode the errors of nested values, which are skipped.
+  pub diagnostics: Option<&'a RefCell<Diagnostics>>,
+}
+
+#[derive(Default)]
+pub(crate) struct Diagnostics {
+  /// Record errors of nested values, rather than failing.
+  lenient: bool,
+  /// The keys / indices the serializer is currently inside of.
+  path: Vec<String>,
+  pub errors: Vec<(String, Error)>,
+  pub warnings: Vec<Warning>,
+}
+
+impl Diagnostics {
+  pub fn lenient() -> Diagnostics {
+    Diagnostics {
+      lenient: true,
+      ..Default::default()
+    }
+  }
+
+  fn path(&self) -> String {
+    self.path.iter().fold(String::new(), |path, segment| {
+      if path.is_empty() || segment.starts_with('[') {
+        path + segment
+      } else {
+        path + "." + segment
+      }
+    })
+  }
+
+  fn record(&mut self, error: Error) {
+    let path = self.path();
+    self.errors.push((path.clone(), at_path(error, &path)));
+  }
+}
+
+impl ValueSerializer<'_> {
+  /// Serialize a struct field / map value / array element at `segment` (quoted like output keys).
+  /// In lenient mode, errors are recorded and the value is skipped (None) instead.
+  fn serialize_nested<T: Serialize + ?Sized>(
+    self,
+    value: &T,
+    segment: &str,
+  ) -> Result<Option<Value>> {
+    let ser = ValueSerializer {
+      depth: self.depth + 1,
+      ..self
+    };
+    if let Some(limit) = self.max_depth.filter(|limit| ser.depth > *limit) {
+      return Err(Error::TooDeep {
+        limit,
+        path: segment.to_string(),
+      });
+    }
+    let Some(diagnostics) = self.diagnostics else {
+      return value
+        .serialize(ser)
+        .map(Some)
+        .map_err(|e| at_path(e, segment));
+    };
+    diagnostics.borrow_mut().path.push(segment.to_string());
+    let res = value.serialize(ser);
+    let mut diagnostics = diagnostics.borrow_mut();
+    let res = match res {
+      Ok(val) => Ok(Some(val)),
+      // Resource limits always fail, rather than skipping the rest of a pathological value piece by piece.
+      Err(e)
+        if diagnostics.lenient
+          && !matches!(e, Error::TooDeep { .. } | Error::TooManyKeys { .. }) =>
+      {
+        diagnostics.record(e);
+        Ok(None)
+      }
+      Err(e) => Err(at_path(e, segment)),
+    };
+    diagnostics.path.pop();
+    res
+  }
+
+  /// An integer outside of toml's 64 bit signed range, per [BigIntegers].
+  fn big_integer(self, v: impl std::fmt::Display) -> Result<Value> {
+    match self.big_integers {
+      BigIntegers::Error => Err(Error::IntegerOutOfRange {
+        path: String::new(),
+      }),
+      BigIntegers::String => {
+        self.warn(|path| Warning::IntegerAsString { path });
+        Ok(Value::String(v.to_string()))
+      }
+    }
+  }
+
+  /// Counts a key against `max_keys`, before its value is serialized.
+  fn count_key(self, segment: &str) -> Result<()> {
+    let (Some(limit), Some(keys)) = (self.max_keys, self.keys) else {
+      return Ok(());
+    };
+    keys.set(keys.get() + 1);
+    if keys.get() > limit {
+      return Err(Error::TooManyKeys {
+        limit,
+        path: segment.to_string(),
+      });
+    }
+    Ok(())
+  }
+
+  fn warn(self, warning: fn(String) -> Warning) {
+    if let Some(diagnostics) = self.diagnostics {
+      let mut diagnostics = diagnostics.borrow_mut();
+      let path = diagnostics.path();
+      diagnostics.warnings.push(warning(path));
+    }
+  }
+}
+
+/// The message of the error serde's `Path` impl returns for non UTF-8 paths.
+const PATH_INVALID_UTF8: &str = "path contains invalid UTF-8 characters";
+
+/// Adds the key / index (`[i]`) the serializer was inside of to the path of an error.
+fn at_path(error: Error, segment: &str) -> Error {
+  let join = |path: String| {
+    if path.is_empty() || path.starts_with('[') {
+      format!("{segment}{path}")
+    } else {
+      format!("{segment}.{path}")
+    }
+  };
+  match error {
+    Error::InvalidUtf8 { path } => Error::InvalidUtf8 { path: join(path) },
+    Error::IntegerOutOfRange { path } => Error::IntegerOutOfRange { path: join(path) },
+    Error::TooDeep { limit, path } => Error::TooDeep {
+      limit,
+      path: join(path),
+    },
+    Error::TooManyKeys { limit, path } => Error::TooManyKeys {
+      limit,
+      path: join(path),
+    },
+    Error::Custom(message) if message == PATH_INVALID_UTF8 => Error::InvalidUtf8 {
+      path: segment.to_string(),
+    },
+    error => error,
+  }
+}
+
+/// `OsString` serializes as an enum over the platform's raw representation.
+/// Converts it to a string according to the [NonUtf8] policy, along with whether anything was replaced.
+fn os_string_to_value(variant: &str, units: Value, non_utf8: NonUtf8) -> Result<(Value, bool)> {
+  let units = units
+    .as_array()
+    .map(|units| units.iter().filter_map(Value::as_u64).collect::<Vec<_>>())
+    .unwrap_or_default();
+  let mut res = String::new();
+  let mut replaced = false;
+  match variant {
+    "Unix" => {
+      let bytes = units.iter().map(|&unit| unit as u8).collect::<Vec<_>>();
+      for chunk in bytes.utf8_chunks() {
+        push_valid(&mut res, chunk.valid(), non_utf8);
+        if !chunk.invalid().is_empty() {
+          push_invalid(&mut res, chunk.invalid(), non_utf8)?;
+          replaced = true;
+        }
+      }
+    }
+    _ => {
+      for c in char::decode_utf16(units.iter().map(|&unit| unit as u16)) {
+        match c {
+          Ok(c) => push_valid(&mut res, c.encode_utf8(&mut [0; 4]), non_utf8),
+          // Unpaired surrogates are percent encoded as their WTF-8 bytes.
+          Err(e) => {
+            let surrogate = e.unpaired_surrogate();
+            push_invalid(
+              &mut res,
+              &[
+                0xE0 | (surrogate >> 12) as u8,
+                0x80 | ((surrogate >> 6) & 0x3F) as u8,
+                0x80 | (surrogate & 0x3F) as u8,
+              ],
+              non_utf8,
+            )?;
+            replaced = true;
+          }
+        }
+      }
+    }
+  }
+  Ok((Value::String(res), replaced))
+}
+
+fn push_valid(res: &mut String, valid: &str, non_utf8: NonUtf8) {
+  if non_utf8 == NonUtf8::PercentEncode {
+    // Keep the encoding reversible.
+    res.push_str(&valid.replace('%', "%25"));
+  } else {
+    res.push_str(valid);
+  }
+}
+
+fn push_invalid(res: &mut String, invalid: &[u8], non_utf8: NonUtf8) -> Result<()> {
+  match non_utf8 {
+    NonUtf8::Error => {
+      return Err(Error::InvalidUtf8 {
+        path: String::new(),
+      })
+    }
+    NonUtf8::Lossy => res.push(char::REPLACEMENT_CHARACTER),
+    NonUtf8::PercentEncode => {
+      for byte in invalid {
+        res.push_str(&format!("%{byte:02X}"));
+      }
+    }
+  }
+  Ok(())
+}
+
+impl ser::Error for Error {
+  fn custom<T: std::fmt::Display>(msg: T) -> Error {
+    Error::Custom(msg.to_string())
+  }
+}
+
+impl<'a> ser::Serializer for ValueSerializer<'a> {
+  type Ok = Value;
+  type Error = Error;
+
+  type SerializeSeq = SerializeVec<'a>;
+  type SerializeTuple = SerializeVec<'a>;
+  type SerializeTupleStruct = SerializeVec<'a>;
+  type SerializeTupleVariant = SerializeTupleVariant<'a>;
+  type SerializeMap = SerializeMap<'a>;
+  type SerializeStruct = SerializeMap<'a>;
+  type SerializeStructVariant = SerializeStructVariant<'a>;
+
+  fn serialize_bool(self, v: bool) -> Result<Value> {
+    Ok(Value::Bool(v))
+  }
+
+  fn serialize_i8(self, v: i8) -> Result<Value> {
+    Ok(Value::from(v))
+  }
+
+  fn serialize_i16(self, v: i16) -> Result<Value> {
+    Ok(Value::from(v))
+  }
+
+  fn serialize_i32(self, v: i32) -> Result<Value> {
+    Ok(Value::from(v))
+  }
+
+  fn serialize_i64(self, v: i64) -> Result<Value> {
+    Ok(Value::from(v))
+  }
+
+  fn serialize_i128(self, v: i128) -> Result<Value> {
+    match (i64::try_from(v), u64::try_from(v)) {
+      (Ok(v), _) => Ok(Value::from(v)),
+      (_, Ok(v)) => self.serialize_u64(v),
+      _ => self.big_integer(v),
+    }
+  }
+
+  fn serialize_u8(self, v: u8) -> Result<Value> {
+    Ok(Value::from(v))
+  }
+
+  fn serialize_u16(self, v: u16) -> Result<Value> {
+    Ok(Value::from(v))
+  }
+
+  fn serialize_u32(self, v: u32) -> Result<Value> {
+    Ok(Value::from(v))
+  }
+
+  fn serialize_u64(self, v: u64) -> Result<Value> {
+    if v > i64::MAX as u64 {
+      return self.big_integer(v);
+    }
+    Ok(Value::from(v))
+  }
+
+  fn serialize_u128(self, v: u128) -> Result<Value> {
+    match u64::try_from(v) {
+      Ok(v) => self.serialize_u64(v),
+      Err(_) => self.big_integer(v),
+    }
+  }
+
+  fn serialize_f32(self, v: f32) -> Result<Value> {
+    // Go through the shortest f32 representation, so eg. 0.1f32 stays 0.1
+    // rather than 0.10000000149011612.
+    if !v.is_finite() {
+      return self.serialize_f64(v as f64);
+    }
+    match Number::from_f64(v.to_string().parse().unwrap_or(v as f64)) {
+      Some(number) => Ok(Value::Number(number)),
+      None => Ok(Value::Null),
+    }
+  }
+
+  fn serialize_f64(self, v: f64) -> Result<Value> {
+    if !v.is_finite() {
+      self.warn(|path| Warning::NonFiniteDropped { path });
+    }
+    Ok(Value::from(v))
+  }
+
+  fn serialize_char(self, v: char) -> Result<Value> {
+    Ok(Value::String(v.to_string()))
+  }
+
+  fn serialize_str(self, v: &str) -> Result<Value> {
+    Ok(Value::from(v))
+  }
+
+  fn serialize_bytes(self, v: &[u8]) -> Result<Value> {
+    Ok(Value::Array(
+      v.iter().map(|byte| Value::from(*byte)).collect(),
+    ))
+  }
+
+  fn serialize_none(self) -> Result<Value> {
+    Ok(Value::Null)
+  }
+
+  fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value> {
+    value.serialize(self)
+  }
+
+  fn serialize_unit(self) -> Result<Value> {
+    Ok(Value::Null)
+  }
+
+  fn serialize_unit_struct(self, _name: &'static str) -> Result<Value> {
+    Ok(Value::Null)
+  }
+
+  fn serialize_unit_variant(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    variant: &'static str,
+  ) -> Result<Value> {
+    Ok(Value::from(variant))
+  }
+
+  fn serialize_newtype_struct<T: Serialize + ?Sized>(
+    self,
+    name: &'static str,
+    value: &T,
+  ) -> Result<Value> {
+    let val = value.serialize(self)?;
+    if name == SPECIAL_FLOAT {
+      if let Some(number) = val.as_str().and_then(Number::special) {
+        return Ok(Value::Number(number));
+      }
+    }
+    Ok(val)
+  }
+
+  fn serialize_newtype_variant<T: Serialize + ?Sized>(
+    self,
+    name: &'static str,
+    _variant_index: u32,
+    variant: &'static str,
+    value: &T,
+  ) -> Result<Value> {
+    if name == "OsString" && matches!(variant, "Unix" | "Windows") {
+      let (val, replaced) = os_string_to_value(variant, value.serialize(self)?, self.non_utf8)?;
+      if replaced {
+        self.warn(|path| Warning::NonUtf8Replaced { path });
+      }
+      return Ok(val);
+    }
+    let mut map = Map::new();
+    match self.serialize_nested(value, &quote_key(variant))? {
+      Some(val) => map.insert(variant.to_string(), val),
+      // Skipped in lenient mode: drop the whole variant.
+      None => return Ok(Value::Null),
+    };
+    Ok(Value::Object(map))
+  }
+
+  fn serialize_seq(self, len: Option<usize>) -> Result<SerializeVec<'a>> {
+    Ok(SerializeVec {
+      ser: self,
+      vals: Vec::with_capacity(len.unwrap_or(0)),
+      index: 0,
+    })
+  }
+
+  fn serialize_tuple(self, len: usize) -> Result<SerializeVec<'a>> {
+    self.serialize_seq(Some(len))
+  }
+
+  fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SerializeVec<'a>> {
+    self.serialize_seq(Some(len))
+  }
+
+  fn serialize_tuple_variant(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    variant: &'static str,
+    len: usize,
+  ) -> Result<SerializeTupleVariant<'a>> {
+    Ok(SerializeTupleVariant {
+      variant,
+      vec: self.serialize_seq(Some(len))?,
+    })
+  }
+
+  fn serialize_map(self, len: Option<usize>) -> Result<SerializeMap<'a>> {
+    Ok(SerializeMap {
+      ser: self,
+      map: Map::with_capacity(len.unwrap_or(0)),
+      next_key: None,
+      skip_value: false,
+      sort: self.sort_maps,
+    })
+  }
+
+  fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeMap<'a>> {
+    Ok(SerializeMap {
+      sort: false,
+      ..self.serialize_map(Some(len))?
+    })
+  }
+
+  fn serialize_struct_variant(
+    self,
+    name: &'static str,
+    _variant_index: u32,
+    variant: &'static str,
+    len: usize,
+  ) -> Result<SerializeStructVariant<'a>> {
+    Ok(SerializeStructVariant {
+      variant,
+      map: self.serialize_struct(name, len)?,
+    })
+  }
+}
+
+pub(crate) struct SerializeVec<'a> {
+  ser: ValueSerializer<'a>,
+  vals: Vec<Value>,
+  /// The index of the next element, including skipped ones.
+  index: usize,
+}
+
+impl SerializeVec<'_> {
+  fn push<T: Serialize + ?Sized>(&mut self, value: &T, segment: &str) -> Result<()> {
+    self.index += 1;
+    if let Some(val) = self.ser.serialize_nested(value, segment)? {
+      self.vals.push(val);
+    }
+    Ok(())
+  }
+}
+
+impl ser::SerializeSeq for SerializeVec<'_> {
+  type Ok = Value;
+  type Error = Error;
+
+  fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+    self.push(value, &format!("[{}]", self.index))
+  }
+
+  fn end(self) -> Result<Value> {
+    Ok(Value::Array(self.vals))
+  }
+}
+
+impl ser::SerializeTuple for SerializeVec<'_> {
+  type Ok = Value;
+  type Error = Error;
+
+  fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+    ser::SerializeSeq::serialize_element(self, value)
+  }
+
+  fn end(self) -> Result<Value> {
+    ser::SerializeSeq::end(self)
+  }
+}
+
+impl ser::SerializeTupleStruct for SerializeVec<'_> {
+  type Ok = Value;
+  type Error = Error;
+
+  fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+    ser::SerializeSeq::serialize_element(self, value)
+  }
+
+  fn end(self) -> Result<Value> {
+    ser::SerializeSeq::end(self)
+  }
+}
+
+pub(crate) struct SerializeTupleVariant<'a> {
+  variant: &'static str,
+  vec: SerializeVec<'a>,
+}
+
+impl ser::SerializeTupleVariant for SerializeTupleVariant<'_> {
+  type Ok = Value;
+  type Error = Error;
+
+  fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+    let segment = format!("{}[{}]", quote_key(self.variant), self.vec.index);
+    self.vec.push(value, &segment)
+  }
+
+  fn end(self) -> Result<Value> {
+    let mut map = Map::new();
+    map.insert(self.variant.to_string(), ser::SerializeSeq::end(self.vec)?);
+    Ok(Value::Object(map))
+  }
+}
+
+pub(crate) struct SerializeMap<'a> {
+  ser: ValueSerializer<'a>,
+  map: Map,
+  next_key: Option<String>,
+  /// The key failed to serialize in lenient mode.
+  skip_value: bool,
+  sort: bool,
+}
+
+impl SerializeMap<'_> {
+  fn insert(&mut self, key: String, val: Value) {
+    // Keep the first position of duplicate keys, like serde_json's preserve_order.
+    match self.map.get_mut(&key) {
+      Some(existing) => *existing = val,
+      None => {
+        self.map.insert(key, val);
+      }
+    }
+  }
+}
+
+impl ser::SerializeMap for SerializeMap<'_> {
+  type Ok = Value;
+  type Error = Error;
+
+  fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+    match (key.serialize(MapKeySerializer), self.ser.diagnostics) {
+      (Ok(key), _) => self.next_key = Some(key),
+      (Err(e), Some(diagnostics)) if diagnostics.borrow().lenient => {
+        diagnostics.borrow_mut().record(e);
+        self.skip_value = true;
+      }
+      (Err(e), _) => return Err(e),
+    }
+    Ok(())
+  }
+
+  fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+    if std::mem::take(&mut self.skip_value) {
+      return Ok(());
+    }
+    let key = self
+      .next_key
+      .take()
+      .ok_or_else(|| Error::Custom(String::from("serialize_value called before serialize_key")))?;
+    let segment = quote_key(&key);
+    self.ser.count_key(&segment)?;
+    if let Some(val) = self.ser.serialize_nested(value, &segment)? {
+      self.insert(key, val);
+    }
+    Ok(())
+  }
+
+  fn end(self) -> Result<Value> {
+    if !self.sort {
+      return Ok(Value::Object(self.map));
+    }
+    let mut entries = self.map.into_iter().collect::<Vec<_>>();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(Value::Object(entries.into_iter().collect()))
+  }
+}
+
+impl SerializeMap<'_> {
+  fn field<T: Serialize + ?Sized>(&mut self, key: &str, value: &T, segment: &str) -> Result<()> {
+    self.ser.count_key(segment)?;
+    if let Some(val) = self.ser.serialize_nested(value, segment)? {
+      self.insert(key.to_string(), val);
+    }
+    Ok(())
+  }
+}
+
+impl ser::SerializeStruct for SerializeMap<'_> {
+  type Ok = Value;
+  type Error = Error;
+
+  fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+    self.field(key, value, &quote_key(key))
+  }
+
+  fn end(self) -> Result<Value> {
+    ser::SerializeMap::end(self)
+  }
+}
+
+pub(crate) struct SerializeStructVariant<'a> {
+  variant: &'static str,
+  map: SerializeMap<'a>,
+}
+
+impl ser::SerializeStructVariant for SerializeStructVariant<'_> {
+  type Ok = Value;
+  type Error = Error;
+
+  fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+    self.map.field(
+      key,
+      value,
+      &format!("{}.{}", quote_key(self.variant), quote_key(key)),
+    )
+  }
+
+  fn end(self) -> Result<Value> {
+    let mut map = Map::new();
+    map.insert(self.variant.to_string(), ser::SerializeMap::end(self.map)?);
+    Ok(Value::Object(map))
+  }
+}
+
+/// Map keys must be strings in toml. Like serde_json, numbers / bools / chars are stringified.
+struct MapKeySerializer;
+
+fn key_must_be_a_string() -> Error {
+  Error::Custom(String::from("key must be a string"))
+}
+
+impl ser::Serializer for MapKeySerializer {
+  type Ok = String;
+  type Error = Error;
+
+  type SerializeSeq = Impossible<String, Error>;
+  type SerializeTuple = Impossible<String, Error>;
+  type SerializeTupleStruct = Impossible<String, Error>;
+  type SerializeTupleVariant = Impossible<String, Error>;
+  type SerializeMap = Impossible<String, Error>;
+  type SerializeStruct = Impossible<String, Error>;
+  type SerializeStructVariant = Impossible<String, Error>;
+
+  fn serialize_bool(self, v: bool) -> Result<String> {
+    Ok(v.to_string())
+  }
+
+  fn serialize_i8(self, v: i8) -> Result<String> {
+    Ok(v.to_string())
+  }
+
+  fn serialize_i16(self, v: i16) -> Result<String> {
+    Ok(v.to_string())
+  }
+
+  fn serialize_i32(self, v: i32) -> Result<String> {
+    Ok(v.to_string())
+  }
+
+  fn serialize_i64(self, v: i64) -> Result<String> {
+    Ok(v.to_string())
+  }
+
+  fn serialize_i128(self, v: i128) -> Result<String> {
+    Ok(v.to_string())
+  }
+
+  fn serialize_u8(self, v: u8) -> Result<String> {
+    Ok(v.to_string())
+  }
+
+  fn serialize_u16(self, v: u16) -> Result<String> {
+    Ok(v.to_string())
+  }
+
+  fn serialize_u32(self, v: u32) -> Result<String> {
+    Ok(v.to_string())
+  }
+
+  fn serialize_u64(self, v: u64) -> Result<String> {
+    Ok(v.to_string())
+  }
+
+  fn serialize_u128(self, v: u128) -> Result<String> {
+    Ok(v.to_string())
+  }
+
+  fn serialize_f32(self, _v: f32) -> Result<String> {
+    Err(key_must_be_a_string())
+  }
+
+  fn serialize_f64(self, _v: f64) -> Result<String> {
+    Err(key_must_be_a_string())
+  }
+
+  fn serialize_char(self, v: char) -> Result<String> {
+    Ok(v.to_string())
+  }
+
+  fn serialize_str(self, v: &str) -> Result<String> {
+    Ok(v.to_string())
+  }
+
+  fn serialize_bytes(self, _v: &[u8]) -> Result<String> {
+    Err(key_must_be_a_string())
+  }
+
+  fn serialize_none(self) -> Result<String> {
+    Err(key_must_be_a_string())
+  }
+
+  fn serialize_some<T: Serialize + ?Sized>(self, _value: &T) -> Result<String> {
+    Err(key_must_be_a_string())
+  }
+
+  fn serialize_unit(self) -> Result<String> {
+    Err(key_must_be_a_string())
+  }
+
+  fn serialize_unit_struct(self, _name: &'static str) -> Result<String> {
+    Err(key_must_be_a_string())
+  }
+
+  fn serialize_unit_variant(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    variant: &'static str,
+  ) -> Result<String> {
+    Ok(variant.to_string())
+  }
+
+  fn serialize_newtype_struct<T: Serialize + ?Sized>(
+    self,
+    _name: &'static str,
+    value: &T,
+  ) -> Result<String> {
+    value.serialize(self)
+  }
+
+  fn serialize_newtype_variant<T: Serialize + ?Sized>(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    _variant: &'static str,
+    _value: &T,
+  ) -> Result<String> {
+    Err(key_must_be_a_string())
+  }
+
+  fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+    Err(key_must_be_a_string())
+  }
+
+  fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+    Err(key_must_be_a_string())
+  }
+
+  fn serialize_tuple_struct(
+    self,
+    _name: &'static str,
+    _len: usize,
+  ) -> Result<Self::SerializeTupleStruct> {
+    Err(key_must_be_a_string())
+  }
+
+  fn serialize_tuple_variant(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    _variant: &'static str,
+    _len: usize,
+  ) -> Result<Self::SerializeTupleVariant> {
+    Err(key_must_be_a_string())
+  }
+
+  fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+    Err(key_must_be_a_string())
+  }
+
+  fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+    Err(key_must_be_a_string())
+  }
+
+  fn serialize_struct_variant(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    _variant: &'static str,
+    _len: usize,
+  ) -> Result<Self::SerializeStructVariant> {
+    Err(key_must_be_a_string())
+  }
+}
@@ -0,0 +1,1072 @@
+use serde::Serialize;
+
+use crate::*;
+
+#[derive(Serialize)]
+struct Plugin {
+  name: &'static str,
+  port: u16,
+}
+
+#[test]
+fn dyn_serialize() {
+  let plugin: Box<dyn SerializeDyn> = Box::new(Plugin {
+    name: "cache",
+    port: 80,
+  });
+  let expected = "name = \"cache\"\nport = 80";
+  assert_eq!(
+    to_string_dyn(&*plugin, Options::default()).unwrap(),
+    expected
+  );
+
+  let mut plugins = OrderedHashMap::<&str, Box<dyn SerializeDyn>>::new();
+  plugins.insert("cache", plugin);
+  plugins.insert("flag", Box::new(true));
+  assert_eq!(
+    to_string(&plugins, Options::default()).unwrap(),
+    "cache.name = \"cache\"\ncache.port = 80\nflag = true"
+  );
+}
+
+fn port(val: &Value) -> std::result::Result<(), String> {
+  match val.as_u64() {
+    Some(port) if port <= u16::MAX as u64 => Ok(()),
+    _ => Err(format!("{val:?} is not a port")),
+  }
+}
+
+fn validation_error(input: &str, path: &str) -> Option<String> {
+  let validators = [(path, port as Validator)];
+  match to_string(
+    &from_str(input).unwrap(),
+    Options::default().validators(&validators),
+  ) {
+    Ok(_) => None,
+    Err(e) => Some(e.path().unwrap().to_string()),
+  }
+}
+
+#[test]
+fn validators_reach_into_arrays() {
+  let servers = "[[servers]]\nport = 80\n[[servers]]\nport = 70000";
+  for path in ["servers.port", "servers[*].port", "servers[1].port"] {
+    assert_eq!(
+      validation_error(servers, path).as_deref(),
+      Some("servers[1].port")
+    );
+  }
+  assert_eq!(validation_error(servers, "servers[0].port"), None);
+  assert_eq!(validation_error(servers, "servers[2].port"), None);
+  assert_eq!(
+    validation_error("ports = [80, 70000]", "ports[*]").as_deref(),
+    Some("ports[1]")
+  );
+  assert_eq!(
+    validation_error("[server]\nport = 70000", "server.port").as_deref(),
+    Some("server.port")
+  );
+  assert_eq!(validation_error("[server]\nport = 80", "server.port"), None);
+}
+
+#[test]
+fn validators_see_tables() {
+  fn has_port(val: &Value) -> std::result::Result<(), String> {
+    match val {
+      Value::Object(map) if map.contains_key("port") => Ok(()),
+      _ => Err(String::from("missing port")),
+    }
+  }
+  let validators = [("server", has_port as Validator)];
+  let options = Options::default().validators(&validators);
+  assert!(to_string(&from_str("[server]\nport = 80").unwrap(), options).is_ok());
+  assert!(to_string(&from_str("[server]\nhost = \"a\"").unwrap(), options).is_err());
+}
+
+#[test]
+fn invalid_validator_paths() {
+  for path in [
+    "servers[x]",
+    "[0].port",
+    "servers[0",
+    "servers[0]port",
+    "servers..port",
+  ] {
+    let validators = [(path, port as Validator)];
+    assert!(
+      matches!(
+        Options::default().validators(&validators).validate(),
+        Err(Error::InvalidOptions(_))
+      ),
+      "{path}"
+    );
+  }
+}
+
+#[test]
+fn lenient_drops_invalid_values() {
+  let validators = [("servers.port", port as Validator)];
+  let input = from_str("name = \"a\"\n[[servers]]\nport = 70000").unwrap();
+  let partial = to_string_lenient(&input, Options::default().validators(&validators));
+  assert_eq!(partial.output, "name = \"a\"");
+  assert_eq!(partial.errors[0].0, "servers[0].port");
+}
+
+#[test]
+fn secrets_inside_arrays() {
+  let input =
+    from_str("name = \"a\"\n[[db]]\npassword = \"x\"\nhost = \"h\"\n[[db]]\npassword = \"y\"")
+      .unwrap();
+  for secret in ["db.password", "db[*].password"] {
+    let secrets = [secret];
+    let options = Options::default().secrets(&secrets).redact_secrets(true);
+    for res in [
+      to_string(&input, options),
+      to_string_compact(&input, options),
+    ] {
+      let res = res.unwrap();
+      assert!(
+        !res.contains("\"x\"") && !res.contains("\"y\""),
+        "{secret}: {res}"
+      );
+      assert!(res.contains("\"h\""), "{secret}: {res}");
+    }
+  }
+  let secrets = ["db[1].password"];
+  let options = Options::default().secrets(&secrets).redact_secrets(true);
+  let res = to_string_compact(&input, options).unwrap();
+  assert!(res.contains("\"x\"") && !res.contains("\"y\""), "{res}");
+}
+
+#[test]
+fn secret_tables() {
+  let input = from_str("[db]\npassword = \"x\"\n[db.nested]\ntoken = \"y\"").unwrap();
+  let secrets = ["db"];
+  let options = Options::default().secrets(&secrets).redact_secrets(true);
+  for res in [
+    to_string(&input, options),
+    to_string_compact(&input, options),
+  ] {
+    let res = res.unwrap();
+    assert!(!res.contains("\"x\"") && !res.contains("\"y\""), "{res}");
+    assert!(res.contains("token"), "{res}");
+  }
+  let secrets = ["db[x]"];
+  let options = Options::default().secrets(&secrets);
+  assert!(matches!(options.validate(), Err(Error::InvalidOptions(_))));
+}
+
+fn scaffold(input: &str, examples: &[(&str, &str)]) -> String {
+  let options = Options::default().scaffold(true).examples(examples);
+  to_string(&from_str(input).unwrap(), options).unwrap()
+}
+
+#[test]
+fn scaffold_replaces_values() {
+  assert_eq!(
+    scaffold("a = 1\np = 0\nb = 2", &[("p", "{ a = 1 }")]),
+    "a = 1\np.a = 1\nb = 2"
+  );
+  assert_eq!(
+    scaffold("a = 1\np.x = 0\np.y = 0\nb = 2", &[("p", "\"s\"")]),
+    "a = 1\np = \"s\"\nb = 2"
+  );
+  assert_eq!(scaffold("p = 0", &[("p.a", "1")]), "p.a = 1");
+  assert_eq!(scaffold("a = 1", &[("p", "2")]), "a = 1\np = 2");
+  assert_eq!(scaffold("p = 0\nq = 0", &[("p", "1")]), "p = 1\nq = 0");
+}
+
+fn stream<T: Serialize>(entries: Vec<(&str, T)>, options: Options<'_>) -> Result<String> {
+  let mut res = Vec::new();
+  let entries = entries.into_iter().map(|(key, val)| (key.to_string(), val));
+  serialize_iter(entries, &mut res, options)?;
+  Ok(String::from_utf8(res).unwrap())
+}
+
+#[test]
+fn serialize_iter_rejects_duplicate_keys() {
+  assert_eq!(
+    stream(vec![("a", 1), ("b", 2)], Options::default()).unwrap(),
+    "a = 1\nb = 2"
+  );
+  let e = stream(vec![("a", 1), ("b", 2), ("a", 3)], Options::default()).unwrap_err();
+  assert!(
+    matches!(&e, Error::DuplicateKey { key } if key == "a"),
+    "{e}"
+  );
+  assert!(e.help().is_some());
+  // Keys are compared as written, so quoted keys are duplicates too.
+  let e = stream(vec![("a b", 1), ("a b", 2)], Options::default()).unwrap_err();
+  assert_eq!(e.path(), Some("\"a b\""));
+}
+
+#[test]
+fn serialize_iter_table_order() {
+  let table = Value::from(Map::from_iter([(String::from("x"), Value::from(1))]));
+  let entries = vec![("t", table), ("a", Value::from(1))];
+  let e = stream(entries.clone(), Options::default().table_headers(true)).unwrap_err();
+  assert!(
+    matches!(&e, Error::TableOrder { key, table } if key == "a" && table == "t"),
+    "{e}"
+  );
+  assert_eq!(e.path(), Some("a"));
+  assert!(stream(entries, Options::default()).is_ok());
+}
+
+#[test]
+fn big_integers_in_documents() {
+  let mut document = Document::parse("a = 1\nb = [2]").unwrap();
+  document.walk(|_, val| {
+    if let Value::Array(vals) = val {
+      vals[0] = Value::from(u64::MAX);
+    }
+  });
+  let e = document.to_string(Options::default()).unwrap_err();
+  assert!(
+    matches!(&e, Error::IntegerOutOfRange { path } if path == "b[0]"),
+    "{e}"
+  );
+  let options = Options::default().big_integers(BigIntegers::String);
+  assert_eq!(
+    document.to_string(options).unwrap(),
+    format!("a = 1\nb = [\"{}\"]", u64::MAX)
+  );
+}
+
+#[test]
+fn big_integer_warnings() {
+  let options = Options::default().big_integers(BigIntegers::String);
+  let (res, warnings) = to_string_with_warnings(
+    &OrderedHashMap::<_, _>::from_iter([("a", u64::MAX)]),
+    options,
+  )
+  .unwrap();
+  assert_eq!(res, format!("a = \"{}\"", u64::MAX));
+  assert_eq!(
+    warnings,
+    [Warning::IntegerAsString {
+      path: String::from("a")
+    }]
+  );
+  assert!(to_string_with_warnings(
+    &OrderedHashMap::<_, _>::from_iter([("a", u64::MAX)]),
+    Options::default()
+  )
+  .is_err());
+}
+
+#[test]
+fn toml_crate_compat() {
+  #[derive(Serialize)]
+  struct Owner {
+    name: &'static str,
+  }
+  #[derive(Serialize)]
+  struct Server {
+    name: &'static str,
+    ports: Vec<u16>,
+  }
+  #[derive(Serialize)]
+  struct Empty {}
+  #[derive(Serialize)]
+  struct Config {
+    title: &'static str,
+    owner: Owner,
+    servers: Vec<Server>,
+    empty: Empty,
+    tags: Vec<&'static str>,
+  }
+  let config = Config {
+    title: "example",
+    owner: Owner { name: "Tom" },
+    servers: vec![
+      Server {
+        name: "alpha",
+        ports: vec![8000, 8001],
+      },
+      Server {
+        name: "beta",
+        ports: vec![],
+      },
+    ],
+    empty: Empty {},
+    tags: vec!["a"],
+  };
+  // What toml 0.8's to_string_pretty writes, by hand since the toml crate isn't a dependency.
+  let expected = r#"title = "example"
+tags = ["a"]
+
+[owner]
+name = "Tom"
+
+[[servers]]
+name = "alpha"
+ports = [
+    8000,
+    8001,
+]
+
+[[servers]]
+name = "beta"
+ports = []
+
+[empty]
+"#;
+  assert_eq!(
+    to_string(&config, Options::toml_crate_compat()).unwrap(),
+    expected
+  );
+}
+
+#[derive(Serialize)]
+struct Big {
+  small: u64,
+  big: u64,
+  huge: u128,
+  low: i128,
+}
+
+#[test]
+fn big_integers_error_by_default() {
+  let e = to_string(
+    &Big {
+      small: 1,
+      big: u64::MAX,
+      huge: 0,
+      low: 0,
+    },
+    Options::default(),
+  )
+  .unwrap_err();
+  assert!(
+    matches!(&e, Error::IntegerOutOfRange { path } if path == "big"),
+    "{e}"
+  );
+  let e = to_string(
+    &Big {
+      small: 1,
+      big: 0,
+      huge: u128::MAX,
+      low: 0,
+    },
+    Options::default(),
+  )
+  .unwrap_err();
+  assert_eq!(e.path(), Some("huge"));
+  let e = to_string(
+    &Big {
+      small: 1,
+      big: 0,
+      huge: 0,
+      low: i128::MIN,
+    },
+    Options::default(),
+  )
+  .unwrap_err();
+  assert_eq!(e.path(), Some("low"));
+  let list = OrderedHashMap::<_, _>::from_iter([("list", vec![1, u64::MAX])]);
+  assert!(to_string_compact(&list, Options::default()).is_err());
+  // The limits themselves fit.
+  let res = to_string(
+    &Big {
+      small: i64::MAX as u64,
+      big: 0,
+      huge: 0,
+      low: i64::MIN as i128,
+    },
+    Options::default(),
+  )
+  .unwrap();
+  assert_eq!(
+    res,
+    "small = 9223372036854775807\nbig = 0\nhuge = 0\nlow = -9223372036854775808"
+  );
+}
+
+#[test]
+fn big_integers_as_strings() {
+  let big = Big {
+    small: 1,
+    big: u64::MAX,
+    huge: u128::MAX,
+    low: i128::MIN,
+  };
+  let options = Options::default().big_integers(BigIntegers::String);
+  let (res, warnings) = to_string_with_warnings(&big, options).unwrap();
+  assert_eq!(
+    res,
+    format!(
+      "small = 1\nbig = \"{}\"\nhuge = \"{}\"\nlow = \"{}\"",
+      u64::MAX,
+      u128::MAX,
+      i128::MIN
+    )
+  );
+  let paths = warnings.iter().map(Warning::path).collect::<Vec<_>>();
+  assert_eq!(paths, ["big", "huge", "low"]);
+}
+
+#[cfg(feature = "yaml")]
+#[test]
+fn yaml_special_floats_and_empty_tables() {
+  let input = "limit: .inf\nratio: -.Inf\nmissing: .nan\nvolumes:\n  data: {}\n";
+  assert_eq!(
+    yaml_to_toml(input, Options::default()).unwrap(),
+    "limit = inf\nratio = -inf\nmissing = nan"
+  );
+  let options = Options::default().empty_table(EmptyTable::Inline);
+  assert_eq!(
+    yaml_to_toml(input, options).unwrap(),
+    "limit = inf\nratio = -inf\nmissing = nan\nvolumes.data = {}"
+  );
+  let options = Options::default()
+    .table_headers(true)
+    .empty_table(EmptyTable::Header);
+  assert_eq!(
+    yaml_to_toml(input, options).unwrap(),
+    "limit = inf\nratio = -inf\nmissing = nan\n\n[volumes.data]"
+  );
+  // Other formats see the toml text.
+  #[cfg(feature = "json")]
+  {
+    let (value, _) = yaml::parse(input).unwrap();
+    assert_eq!(serde_json::to_value(&value).unwrap()["ratio"], "-inf");
+  }
+}
+
+#[test]
+fn super_tables_skip_dotted_tables() {
+  // d.c.1 is defined by the dotted `1.a` under [d.c], so it can't get a [d.c.1] header.
+  let options = Options::default()
+    .table_headers(true)
+    .header_dotted_depth(2)
+    .dotted_depth(1)
+    .array_tables(true)
+    .super_tables(true);
+  let value = from_str("[d.c.1]\na = []\n\n[[d.c.1.s]]\nx = 1").unwrap();
+  assert_eq!(
+    to_string(&value, options).unwrap(),
+    "[d.c]\n1.a = []\n\n[[d.c.1.s]]\nx = 1"
+  );
+  check_roundtrip(&value, options).unwrap();
+}
+
+/// Marks each piece it writes, and counts the headers.
+#[derive(Default)]
+struct Marked {
+  headers: std::sync::atomic::AtomicUsize,
+}
+
+impl Formatter for Marked {
+  fn write_key(&self, out: &mut String, key: &str) {
+    out.push_str(&format!("k({key})"));
+  }
+
+  fn write_string(&self, out: &mut String, value: &str, quoted: &str) {
+    out.push_str(&format!("s({value}|{quoted})"));
+  }
+
+  fn write_number(&self, out: &mut String, number: &str) {
+    out.push_str(&format!("n({number})"));
+  }
+
+  fn write_bool(&self, out: &mut String, bool: bool) {
+    out.push_str(&format!("b({bool})"));
+  }
+
+  fn begin_inline_table(&self, out: &mut String) {
+    out.push_str("<{");
+  }
+
+  fn write_header(&self, out: &mut String, table: &str, array: bool) {
+    self
+      .headers
+      .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    out.push_str(&format!("h({table}{})", if array { ", array" } else { "" }));
+  }
+}
+
+#[test]
+fn custom_formatter() {
+  let formatter = Marked::default();
+  let input = r#"name = "say \"hi\""
+ports = [80, 443]
+
+[server]
+enabled = true
+
+[server.limits]
+cpu = 1.5
+
+[[plugins]]
+name = "cache""#;
+  let map = from_str(input).unwrap();
+  let options = Options::default()
+    .table_headers(true)
+    .array_tables(true)
+    .formatter(&formatter);
+  let expected = r#"k(name) = s(say "hi"|"say \"hi\"")
+k(ports) = [n(80), n(443)]
+
+h(server)
+k(enabled) = b(true)
+
+h(server.limits)
+k(cpu) = n(1.5)
+
+h(plugins, array)
+k(name) = s(cache|"cache")"#;
+  assert_eq!(to_string(&map, options).unwrap(), expected);
+  assert_eq!(
+    formatter.headers.load(std::sync::atomic::Ordering::Relaxed),
+    3
+  );
+  assert_eq!(
+    format_value(&map["server"], options).unwrap(),
+    "<{ k(enabled) = b(true), k(limits.cpu) = n(1.5) }"
+  );
+  // The default formatter writes the text unchanged.
+  assert_eq!(
+    to_string(&map, options.formatter(&DefaultFormatter)).unwrap(),
+    input
+  );
+}
+
+#[test]
+fn options_are_unwind_safe() {
+  fn assert_traits<T: Send + Sync + std::panic::UnwindSafe + std::panic::RefUnwindSafe>(_: &T) {}
+  let formatter = Marked::default();
+  let options = Options::default().formatter(&formatter);
+  assert_traits(&options);
+  let res = std::panic::catch_unwind(|| to_string(&from_str("a = 1").unwrap(), options));
+  assert_eq!(res.unwrap().unwrap(), "k(a) = n(1)");
+}
+
+#[test]
+fn migrations_replace_the_new_path() {
+  let options = Options::default()
+    .migrations(&[("a", "b")])
+    .migration_comments(true);
+  // Whichever comes first, the migrated value wins.
+  for input in ["a = 1\nb = 2", "b = 2\na = 1"] {
+    let res = to_string(&from_str(input).unwrap(), options).unwrap();
+    assert_eq!(res, "# moved from a\nb = 1", "{input}");
+  }
+  let res = to_string(&from_str("b.x = 2\nc = 3\na = 1").unwrap(), options).unwrap();
+  assert_eq!(res, "c = 3\n# moved from a\nb = 1");
+  // Nothing to move leaves the new path alone.
+  let res = to_string(&from_str("b = 2").unwrap(), options).unwrap();
+  assert_eq!(res, "b = 2");
+}
+
+#[test]
+fn migrations_beneath_a_value() {
+  let options = Options::default()
+    .migrations(&[("a", "b.c")])
+    .migration_comments(true);
+  let e = to_string(&from_str("a = 1\nb = 2").unwrap(), options).unwrap_err();
+  assert!(
+    matches!(&e, Error::PathConflict { path, existing } if path == "b.c" && existing == "b"),
+    "{e}"
+  );
+  // Into an existing table is fine.
+  let res = to_string(&from_str("a = 1\nb.d = 2").unwrap(), options).unwrap();
+  assert_eq!(res, "# moved from a\nb.c = 1\nb.d = 2");
+}
+
+#[test]
+fn aliases() {
+  let options = Options::default().aliases(&[("old", "new")]);
+  let res = to_string(&from_str("new.a = 1\nz = 2").unwrap(), options).unwrap();
+  assert_eq!(
+    res,
+    "new.a = 1\n# deprecated: renamed to new\nold.a = 1\nz = 2"
+  );
+  // The old path beneath a value, or with values of its own, conflicts.
+  let options = Options::default().aliases(&[("a.b", "new")]);
+  let e = to_string(&from_str("a = 1\nnew = 3").unwrap(), options).unwrap_err();
+  assert!(
+    matches!(&e, Error::PathConflict { path, existing } if path == "a.b" && existing == "a"),
+    "{e}"
+  );
+  let e = to_string(&from_str("a.b.c = 1\nnew = 3").unwrap(), options).unwrap_err();
+  assert_eq!(e.path(), Some("a.b"));
+  // Nothing to alias is fine.
+  let res = to_string(&from_str("a = 1").unwrap(), options).unwrap();
+  assert_eq!(res, "a = 1");
+}
+
+#[test]
+fn max_inline_array_elements() {
+  let map =
+    from_str("one = [\"a long element which is well over the length limit\"]\ntwo = [1, 2]")
+      .unwrap();
+  let options = Options::default()
+    .tab("  ")
+    .max_inline_array_length(0)
+    .max_inline_array_elements(1);
+  assert_eq!(
+    to_string(&map, options).unwrap(),
+    "one = [\"a long element which is well over the length limit\"]\ntwo = [\n  1,\n  2\n]"
+  );
+}
+
+#[test]
+fn to_file_replaces_the_file() {
+  let dir = std::env::temp_dir().join(format!("toml_pretty_to_file_{}", std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  let path = dir.join("config.toml");
+  let value = from_str("a = 1").unwrap();
+  to_file(&value, &path, Options::default(), FileOptions::default()).unwrap();
+  assert_eq!(std::fs::read_to_string(&path).unwrap(), "a = 1");
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+    let value = from_str("a = 2").unwrap();
+    to_file(&value, &path, Options::default(), FileOptions::default()).unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "a = 2");
+  }
+  // Only the config itself is left, no temporary files.
+  assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn compact_prepares_like_to_string() {
+  let map = from_str(
+    "old = 1\nkey = \"hunter2\"\ngone = 2\n\n[deep.a.b]\nc = 1\n\n[list]\nx = [{ key = \"k\" }]",
+  )
+  .unwrap();
+  let deprecated = CommentMap::from_iter([(String::from("gone"), String::from("unused"))]);
+  let options = Options::default()
+    .migrations(&[("old", "new")])
+    .aliases(&[("alias", "new")])
+    .secrets(&["key", "list.x.key"])
+    .redact_secrets(true)
+    .deprecated(&deprecated)
+    .omit_deprecated(true)
+    .truncate_depth(2);
+  assert_eq!(
+    to_string_compact(&map, options).unwrap(),
+    "new=1\nalias=1\nkey=\"********\"\ndeep={a={}}\nlist={x=[{key=\"********\"}]}"
+  );
+  // key isn't a port.
+  let options = Options::default().validators(&[("key", port)]);
+  let e = to_string_compact(&map, options).unwrap_err();
+  assert_eq!(e.path(), Some("key"));
+}
+
+#[test]
+fn max_line_width_measures_the_written_line() {
+  let map = from_str(
+    "[a_fairly_long_section_name.nested]\nvals = [\"one\", \"two\", \"three\", \"four\", \"five\"]",
+  )
+  .unwrap();
+  // The line below the header is 46 wide, the full dotted key would make it 80.
+  let options = Options::default()
+    .tab("  ")
+    .table_headers(true)
+    .max_line_width(46);
+  assert_eq!(
+    to_string(&map, options).unwrap(),
+    "[a_fairly_long_section_name.nested]\nvals = [\"one\", \"two\", \"three\", \"four\", \"five\"]"
+  );
+  let explained = explain(&map, options).unwrap();
+  assert_eq!(
+    explained.last().map(|(_, decision)| decision.as_str()),
+    Some("inline: line width 46 <= max_line_width 46")
+  );
+  let res = to_string(&map, options.max_line_width(45)).unwrap();
+  assert!(
+    res.ends_with("vals = [\n  \"one\",\n  \"two\",\n  \"three\",\n  \"four\",\n  \"five\"\n]")
+  );
+  // Without headers, the dotted key is on the line.
+  let res = to_string(&map, options.table_headers(false).max_line_width(79)).unwrap();
+  assert!(res.starts_with("a_fairly_long_section_name.nested.vals = [\n"));
+  let res = to_string(&map, options.table_headers(false).max_line_width(80)).unwrap();
+  assert_eq!(res.lines().count(), 1);
+}
+
+fn strings(entries: &[(&str, &str)]) -> Map {
+  entries
+    .iter()
+    .map(|(key, val)| (key.to_string(), Value::from(*val)))
+    .collect()
+}
+
+#[test]
+fn carriage_returns() {
+  let map = strings(&[("text", "a\r\nb\rc")]);
+  let escaped = to_string(&map, Options::default()).unwrap();
+  assert_eq!(escaped, "text = \"\"\"\na\\r\nb\\rc\"\"\"");
+  check_roundtrip(&map, Options::default()).unwrap();
+  let options = Options::default().carriage_return(CarriageReturn::Normalize);
+  let normalized = to_string(&map, options).unwrap();
+  // Lone carriage returns are still escaped.
+  assert_eq!(normalized, "text = \"\"\"\na\nb\\rc\"\"\"");
+  assert_eq!(
+    from_str(&normalized).unwrap()["text"].as_str(),
+    Some("a\nb\rc")
+  );
+}
+
+#[test]
+fn string_tabs() {
+  let map = strings(&[("a", "x\ty\tz"), ("b", "just\ttab")]);
+  assert_eq!(
+    to_string(&map, Options::default()).unwrap(),
+    "a = \"x\ty\tz\"\nb = \"just\ttab\""
+  );
+  let options = Options::default().string_tabs(StringTabs::Escape);
+  assert_eq!(
+    to_string(&map, options).unwrap(),
+    "a = \"x\\ty\\tz\"\nb = \"just\\ttab\""
+  );
+  check_roundtrip(&map, options).unwrap();
+  // To the next multiple of 4 columns of the value.
+  let options = Options::default().string_tabs(StringTabs::Expand(4));
+  assert_eq!(
+    to_string(&map, options).unwrap(),
+    "a = \"x   y   z\"\nb = \"just    tab\""
+  );
+}
+
+#[test]
+fn key_transform_collisions() {
+  fn kebab(key: &str) -> String {
+    key.replace('_', "-")
+  }
+  let options = Options::default().key_transform(kebab);
+  let map = from_str("some_key = 1\n\n[a_b]\nc_d = 2").unwrap();
+  assert_eq!(
+    to_string(&map, options).unwrap(),
+    "some-key = 1\na-b.c-d = 2"
+  );
+  // The same key in different tables is fine.
+  let map = from_str("a_b = 1\n\n[x]\na-b = 2").unwrap();
+  assert_eq!(to_string(&map, options).unwrap(), "a-b = 1\nx.a-b = 2");
+  let collision = |input: &str| match to_string(&from_str(input).unwrap(), options) {
+    Err(Error::KeyCollision {
+      path,
+      first,
+      second,
+    }) => (path, first, second),
+    res => panic!("{input}: {res:?}"),
+  };
+  assert_eq!(
+    collision("[srv]\nmax_conn = 1\nmax-conn = 2"),
+    (
+      String::from("srv.max-conn"),
+      String::from("srv.max_conn"),
+      String::from("srv.max-conn")
+    )
+  );
+  // Tables collide too, even with different keys inside.
+  assert_eq!(collision("a_b.c = 1\na-b.d = 2").0, "a-b");
+  collision("[[list]]\nk_1 = 1\nk-1 = 2");
+}
+
+#[test]
+fn max_array_items() {
+  let options = Options::default().tab("  ").max_array_items(2);
+  let map = from_str("list = [1, 2, 3]\nshort = [1, 2]").unwrap();
+  assert_eq!(
+    to_string(&map, options).unwrap(),
+    "list = [\n  1,\n  2\n  # … 1 more item\n]\nshort = [1, 2]"
+  );
+  let map = from_str(&format!(
+    "list = [{}]",
+    (0..10_000)
+      .map(|i| i.to_string())
+      .collect::<Vec<_>>()
+      .join(", ")
+  ))
+  .unwrap();
+  assert_eq!(
+    to_string(&map, options.max_array_items(1)).unwrap(),
+    "list = [\n  0\n  # … 9,999 more items\n]"
+  );
+  // Arrays of tables are cut off after their last section.
+  let map = from_str("[[s]]\nn = 1\n\n[[s]]\nn = 2\n\n[[s]]\nn = 3").unwrap();
+  let options = options.table_headers(true).array_tables(true);
+  assert_eq!(
+    to_string(&map, options).unwrap(),
+    "[[s]]\nn = 1\n[[s]]\nn = 2\n\n# … 1 more item"
+  );
+}
+
+#[test]
+fn truncate_depth() {
+  let map = from_str("top = 3\n\n[a]\nx = 2\n\n[a.b.c]\nd = 1").unwrap();
+  let options = Options::default().truncate_depth(2);
+  assert_eq!(
+    to_string(&map, options).unwrap(),
+    "top = 3\na.x = 2\n# (truncated: 2 more levels)\na.b = {}"
+  );
+  assert_eq!(
+    to_string(&map, Options::default().truncate_depth(1)).unwrap(),
+    "top = 3\n# (truncated: 3 more levels)\na = {}"
+  );
+  assert_eq!(
+    to_string(&map, Options::default().truncate_depth(3)).unwrap(),
+    "top = 3\na.x = 2\n# (truncated: 1 more level)\na.b.c = {}"
+  );
+  assert_eq!(
+    to_string(&map, options.table_headers(true)).unwrap(),
+    "top = 3\n\n[a]\nx = 2\n# (truncated: 2 more levels)\nb = {}"
+  );
+  // Shallow enough documents are left as they are.
+  assert_eq!(
+    to_string(&map, Options::default().truncate_depth(4)).unwrap(),
+    to_string(&map, Options::default()).unwrap()
+  );
+}
+
+#[test]
+fn align_numbers() {
+  let map =
+    from_str("xs = [1, 200, -3.5, 42]\nm = [[1, 20], [300, 4]]\ns = [\"a\", \"bb\"]").unwrap();
+  let options = Options::default()
+    .tab("  ")
+    .max_inline_array_length(0)
+    .align_numbers(true);
+  // Other arrays are left as they are.
+  assert_eq!(
+    to_string(&map, options).unwrap(),
+    "xs = [\n     1,\n   200,\n  -3.5,\n    42\n]\nm = [\n  [  1, 20],\n  [300,  4]\n]\ns = [\n  \"a\",\n  \"bb\"\n]"
+  );
+  check_roundtrip(&map, options).unwrap();
+  // Inline arrays aren't padded.
+  let options = options.max_inline_array_length(50);
+  assert_eq!(
+    to_string(&map, options).unwrap(),
+    "xs = [1, 200, -3.5, 42]\nm = [[1, 20], [300, 4]]\ns = [\"a\", \"bb\"]"
+  );
+}
+
+fn multiline_strings() -> Map {
+  strings(&[
+    ("text", "line one\nline two\n"),
+    ("lead", "\nstarts with a newline"),
+  ])
+}
+
+#[test]
+fn multiline_indent() {
+  let map = multiline_strings();
+  let options = Options::default().tab("  ");
+  assert_eq!(
+    to_string(&map, options).unwrap(),
+    "text = \"\"\"\nline one\nline two\n\"\"\"\nlead = \"\"\"\n\nstarts with a newline\"\"\""
+  );
+  // The line continuations drop the indentation from the value.
+  let options = options.multiline_indent(true);
+  assert_eq!(
+    to_string(&map, options).unwrap(),
+    "text = \"\"\"\\\n  line one\\n\\\n  line two\\n\\\n\"\"\"\nlead = \"\"\"\\\n  \\n\\\n  starts with a newline\\\n\"\"\""
+  );
+  check_roundtrip(&map, options).unwrap();
+}
+
+#[test]
+fn multiline_start() {
+  let map = multiline_strings();
+  let options = Options::default().tab("  ");
+  let inline = options.multiline_start(MultilineStart::Inline);
+  assert_eq!(
+    to_string(&map, inline).unwrap(),
+    "text = \"\"\"line one\nline two\n\"\"\"\nlead = \"\"\"\n\nstarts with a newline\"\"\""
+  );
+  check_roundtrip(&map, inline).unwrap();
+  let backslash = options.multiline_start(MultilineStart::Backslash);
+  assert_eq!(
+    to_string(&map, backslash).unwrap(),
+    "text = \"\"\"\\\nline one\nline two\n\"\"\"\nlead = \"\"\"\\\n\\nstarts with a newline\"\"\""
+  );
+  check_roundtrip(&map, backslash).unwrap();
+  let indented = inline.multiline_indent(true);
+  assert_eq!(
+    to_string(&map, indented).unwrap(),
+    "text = \"\"\"line one\\n\\\n  line two\\n\\\n\"\"\"\nlead = \"\"\"\\n\\\n  starts with a newline\\\n\"\"\""
+  );
+  check_roundtrip(&map, indented).unwrap();
+}
+
+#[test]
+fn comment_column() {
+  let map = strings(&[
+    ("host", "localhost"),
+    ("a_much_longer_key", "a value reaching the column"),
+    ("motd", "hello"),
+    ("notes", "one\ntwo"),
+  ]);
+  let comments = CommentMap::from_iter(
+    [
+      ("host", "the host"),
+      ("a_much_longer_key", "past the column"),
+      ("motd", "first line\nsecond line"),
+      ("notes", "multiline value"),
+    ]
+    .map(|(key, comment)| (key.to_string(), comment.to_string())),
+  );
+  let options = Options::default().comments(&comments).comment_column(24);
+  assert_eq!(
+    to_string(&map, options).unwrap(),
+    "host = \"localhost\"      # the host
+a_much_longer_key = \"a value reaching the column\" # past the column
+# first line
+# second line
+motd = \"hello\"
+# multiline value
+notes = \"\"\"
+one
+two\"\"\""
+  );
+}
+
+#[test]
+fn source_map() {
+  let map = from_str(
+    "name = \"é\"\nnotes = \"\"\"\none\ntwo\"\"\"\n\n[[servers]]\nport = 80\n\n[[servers]]\nport = 8080",
+  )
+  .unwrap();
+  let (res, source_map) = to_string_with_source_map(
+    &map,
+    Options::default().table_headers(true).array_tables(true),
+  )
+  .unwrap();
+  assert_eq!(
+    res,
+    "name = \"é\"\nnotes = \"\"\"\none\ntwo\"\"\"\n\n[[servers]]\nport = 80\n[[servers]]\nport = 8080"
+  );
+  // Columns and lengths count characters, multiline values include their line breaks.
+  let spans = [
+    ("name", (1, 8, 3)),
+    ("notes", (2, 9, 14)),
+    ("servers[0].port", (7, 8, 2)),
+    ("servers[1].port", (9, 8, 4)),
+  ];
+  assert_eq!(source_map.len(), spans.len());
+  for (path, (line, column, length)) in spans {
+    assert_eq!(
+      source_map[path],
+      Span {
+        line,
+        column,
+        length
+      },
+      "{path}"
+    );
+  }
+}
+
+#[test]
+fn quote_all_keys() {
+  let map = from_str(
+    "name = \"a\"\nserver.host = \"h\"\nservers = [{ port = 80 }]\n\n[db]\n\"user name\" = \"u\"",
+  )
+  .unwrap();
+  let cases = [
+    (
+      Options::default(),
+      "\"name\" = \"a\"\n\"server\".\"host\" = \"h\"\n\"servers\" = [{ \"port\" = 80 }]\n\"db\".\"user name\" = \"u\"",
+    ),
+    (
+      Options::default().table_headers(true).array_tables(true),
+      "\"name\" = \"a\"\n\n[\"server\"]\n\"host\" = \"h\"\n\n[[\"servers\"]]\n\"port\" = 80\n\n[\"db\"]\n\"user name\" = \"u\"",
+    ),
+    (
+      Options::default().inline_tables(true),
+      "\"name\" = \"a\"\n\"server\" = { \"host\" = \"h\" }\n\"servers\" = [{ \"port\" = 80 }]\n\"db\" = { \"user name\" = \"u\" }",
+    ),
+  ];
+  for (options, expected) in cases {
+    let options = options.quote_all_keys(true);
+    assert_eq!(to_string(&map, options).unwrap(), expected);
+    check_roundtrip(&map, options).unwrap();
+  }
+}
+
+#[test]
+fn inline_tables() {
+  let map =
+    from_str("name = \"a\"\n\n[server]\nhost = \"h\"\ntls.cert = \"c\"\n\n[[servers]]\nport = 80")
+      .unwrap();
+  let comments = CommentMap::from_iter(
+    [("server", "the server"), ("server.host", "not written")]
+      .map(|(key, comment)| (key.to_string(), comment.to_string())),
+  );
+  let options = Options::default().inline_tables(true).comments(&comments);
+  // Only the root key's comment is written, nested keys have nowhere to put one.
+  assert_eq!(
+    to_string(&map, options).unwrap(),
+    "name = \"a\"\n# the server\nserver = { host = \"h\", tls = { cert = \"c\" } }\nservers = [{ port = 80 }]"
+  );
+  check_roundtrip(&map, options).unwrap();
+  // Arrays of tables still follow array_tables.
+  let options = options.table_headers(true).array_tables(true);
+  assert_eq!(
+    to_string(&map, options).unwrap(),
+    "name = \"a\"\n# the server\nserver = { host = \"h\", tls = { cert = \"c\" } }\n\n[[servers]]\nport = 80"
+  );
+  check_roundtrip(&map, options).unwrap();
+}
+
+#[test]
+fn render_lines() {
+  let map = from_str(
+    "name = \"a\"\nnotes = \"one\\ntwo\"\n\n[server]\nhost = \"h\"\n\n[[servers]]\nport = 80\n\n[[servers]]\nport = 8080",
+  )
+  .unwrap();
+  let comments = CommentMap::from_iter(
+    [("name", "the name"), ("server", "the server")]
+      .map(|(key, comment)| (key.to_string(), comment.to_string())),
+  );
+  for options in [
+    Options::default(),
+    Options::default().comments(&comments),
+    Options::default()
+      .comments(&comments)
+      .table_headers(true)
+      .array_tables(true)
+      .footer("regenerate with `make config`"),
+  ] {
+    let lines = super::render_lines(&map, options)
+      .collect::<Result<Vec<_>>>()
+      .unwrap();
+    // One line at a time, even within multiline values.
+    assert!(lines
+      .iter()
+      .all(|line| !line.trim_end_matches('\n').contains('\n')));
+    assert_eq!(lines.concat(), to_string(&map, options).unwrap());
+  }
+  let options = Options::default().post_process(|res| res);
+  assert!(matches!(
+    super::render_lines(&map, options).next(),
+    Some(Err(Error::InvalidOptions(_)))
+  ));
+}
+
+#[test]
+fn array_indent() {
+  let map =
+    from_str("notes = \"one\\ntwo\"\nports = [80, 443]\nmatrix = [[1, 2], [3, 4]]").unwrap();
+  let options = Options::default()
+    .tab("  ")
+    .multiline_indent(true)
+    .max_inline_array_elements(1)
+    .array_indent("\t");
+  // Multiline strings are still indented with tab.
+  assert_eq!(
+    to_string(&map, options).unwrap(),
+    "notes = \"\"\"\\\n  one\\n\\\n  two\\\n\"\"\"\nports = [\n\t80,\n\t443\n]\nmatrix = [\n\t[1, 2],\n\t[3, 4]\n]"
+  );
+  check_roundtrip(&map, options).unwrap();
+  assert!(matches!(
+    to_string(&map, options.array_indent("--")),
+    Err(Error::InvalidOptions(_))
+  ));
+}
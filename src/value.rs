@@ -0,0 +1,534 @@
+use std::{fmt, ops::Index};
+
+use ordered_hash_map::OrderedHashMap;
+use serde::{
+  de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor},
+  ser::{Serialize, SerializeMap, Serializer},
+};
+
+/// The newtype struct name a non finite [Number] serializes as.
+pub(crate) const SPECIAL_FLOAT: &str = "$toml_pretty::SpecialFloat";
+
+/// Object keys in the order they were serialized.
+pub type Map = OrderedHashMap<String, Value>;
+
+/// The intermediate representation between serde and toml.
+/// Unlike `serde_json::Value`, objects always keep their keys in serialization order,
+/// independent of whether serde_json's `preserve_order` feature is enabled.
+#[derive(Debug, Clone, Default)]
+pub enum Value {
+  #[default]
+  Null,
+  Bool(bool),
+  Number(Number),
+  String(String),
+  Array(Vec<Value>),
+  Object(Map),
+}
+
+/// An integer or finite float. Only `yaml_to_toml` keeps infinite / NaN floats
+/// (from `.inf` / `.nan`), which are written as toml's `inf` / `nan`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Number(N);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum N {
+  PosInt(u64),
+  NegInt(i64),
+  Float(f64),
+}
+
+impl Number {
+  /// None if the float is not finite (NaN / infinity).
+  pub fn from_f64(float: f64) -> Option<Number> {
+    float.is_finite().then_some(Number(N::Float(float)))
+  }
+
+  /// From the toml text of an infinite / NaN float, eg. `-inf`.
+  pub(crate) fn special(text: &str) -> Option<Number> {
+    let float = match text {
+      "inf" | "+inf" => f64::INFINITY,
+      "-inf" => f64::NEG_INFINITY,
+      "nan" | "+nan" | "-nan" => f64::NAN,
+      _ => return None,
+    };
+    Some(Number(N::Float(float)))
+  }
+
+  pub fn as_i64(&self) -> Option<i64> {
+    match self.0 {
+      N::PosInt(int) => i64::try_from(int).ok(),
+      N::NegInt(int) => Some(int),
+      N::Float(_) => None,
+    }
+  }
+
+  pub fn as_u64(&self) -> Option<u64> {
+    match self.0 {
+      N::PosInt(int) => Some(int),
+      N::NegInt(_) | N::Float(_) => None,
+    }
+  }
+
+  pub fn as_f64(&self) -> Option<f64> {
+    match self.0 {
+      N::PosInt(int) => Some(int as f64),
+      N::NegInt(int) => Some(int as f64),
+      N::Float(float) => Some(float),
+    }
+  }
+
+  pub fn is_f64(&self) -> bool {
+    matches!(self.0, N::Float(_))
+  }
+}
+
+impl fmt::Display for Number {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.0 {
+      N::PosInt(int) => write!(f, "{int}"),
+      N::NegInt(int) => write!(f, "{int}"),
+      N::Float(float) if float.is_nan() => write!(f, "nan"),
+      N::Float(float) if float.is_infinite() => {
+        write!(f, "{}inf", if float < 0.0 { "-" } else { "" })
+      }
+      // Debug keeps the '.0' on whole floats, so they stay floats in toml.
+      N::Float(float) => write!(f, "{float:?}"),
+    }
+  }
+}
+
+macro_rules! impl_from_unsigned {
+  ($($ty:ty),*) => {
+    $(impl From<$ty> for Number {
+      fn from(int: $ty) -> Number {
+        Number(N::PosInt(int as u64))
+      }
+    })*
+  };
+}
+
+macro_rules! impl_from_signed {
+  ($($ty:ty),*) => {
+    $(impl From<$ty> for Number {
+      fn from(int: $ty) -> Number {
+        if int < 0 {
+          Number(N::NegInt(int as i64))
+        } else {
+          Number(N::PosInt(int as u64))
+        }
+      }
+    })*
+  };
+}
+
+impl_from_unsigned!(u8, u16, u32, u64, usize);
+impl_from_signed!(i8, i16, i32, i64, isize);
+
+impl Value {
+  pub fn is_null(&self) -> bool {
+    matches!(self, Value::Null)
+  }
+
+  pub fn as_bool(&self) -> Option<bool> {
+    match self {
+      Value::Bool(bool) => Some(*bool),
+      _ => None,
+    }
+  }
+
+  pub fn as_i64(&self) -> Option<i64> {
+    match self {
+      Value::Number(number) => number.as_i64(),
+      _ => None,
+    }
+  }
+
+  pub fn as_u64(&self) -> Option<u64> {
+    match self {
+      Value::Number(number) => number.as_u64(),
+      _ => None,
+    }
+  }
+
+  pub fn as_f64(&self) -> Option<f64> {
+    match self {
+      Value::Number(number) => number.as_f64(),
+      _ => None,
+    }
+  }
+
+  pub fn as_str(&self) -> Option<&str> {
+    match self {
+      Value::String(string) => Some(string),
+      _ => None,
+    }
+  }
+
+  pub fn as_array(&self) -> Option<&Vec<Value>> {
+    match self {
+      Value::Array(vals) => Some(vals),
+      _ => None,
+    }
+  }
+
+  pub fn as_object(&self) -> Option<&Map> {
+    match self {
+      Value::Object(map) => Some(map),
+      _ => None,
+    }
+  }
+}
+
+static NULL: Value = Value::Null;
+
+/// `value["key"]`, like serde_json: [Value::Null] for missing keys, or if the value isn't an object.
+impl Index<&str> for Value {
+  type Output = Value;
+
+  fn index(&self, key: &str) -> &Value {
+    match self {
+      Value::Object(map) => map.get(key).unwrap_or(&NULL),
+      _ => &NULL,
+    }
+  }
+}
+
+/// `value[0]`, like serde_json: [Value::Null] for missing elements, or if the value isn't an array.
+impl Index<usize> for Value {
+  type Output = Value;
+
+  fn index(&self, index: usize) -> &Value {
+    match self {
+      Value::Array(vals) => vals.get(index).unwrap_or(&NULL),
+      _ => &NULL,
+    }
+  }
+}
+
+/// Objects compare equal regardless of key order.
+impl PartialEq for Value {
+  fn eq(&self, other: &Value) -> bool {
+    match (self, other) {
+      (Value::Null, Value::Null) => true,
+      (Value::Bool(a), Value::Bool(b)) => a == b,
+      (Value::Number(a), Value::Number(b)) => a == b,
+      (Value::String(a), Value::String(b)) => a == b,
+      (Value::Array(a), Value::Array(b)) => a == b,
+      (Value::Object(a), Value::Object(b)) => {
+        a.len() == b.len() && a.iter().all(|(key, a)| b.get(key) == Some(a))
+      }
+      _ => false,
+    }
+  }
+}
+
+impl<T: Into<Number>> From<T> for Value {
+  fn from(number: T) -> Value {
+    Value::Number(number.into())
+  }
+}
+
+impl From<bool> for Value {
+  fn from(bool: bool) -> Value {
+    Value::Bool(bool)
+  }
+}
+
+impl From<f64> for Value {
+  /// Non finite floats become [Value::Null].
+  fn from(float: f64) -> Value {
+    Number::from_f64(float)
+      .map(Value::Number)
+      .unwrap_or_default()
+  }
+}
+
+impl From<String> for Value {
+  fn from(string: String) -> Value {
+    Value::String(string)
+  }
+}
+
+impl From<&str> for Value {
+  fn from(string: &str) -> Value {
+    Value::String(string.to_string())
+  }
+}
+
+impl From<Vec<Value>> for Value {
+  fn from(vals: Vec<Value>) -> Value {
+    Value::Array(vals)
+  }
+}
+
+impl From<Map> for Value {
+  fn from(map: Map) -> Value {
+    Value::Object(map)
+  }
+}
+
+impl Serialize for Number {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    match self.0 {
+      N::PosInt(int) => serializer.serialize_u64(int),
+      N::NegInt(int) => serializer.serialize_i64(int),
+      // As its toml text, which ValueSerializer turns back into the float rather than dropping it.
+      N::Float(float) if !float.is_finite() => {
+        serializer.serialize_newtype_struct(SPECIAL_FLOAT, &self.to_string())
+      }
+      N::Float(float) => serializer.serialize_f64(float),
+    }
+  }
+}
+
+impl Serialize for Value {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    match self {
+      Value::Null => serializer.serialize_unit(),
+      Value::Bool(bool) => serializer.serialize_bool(*bool),
+      Value::Number(number) => number.serialize(serializer),
+      Value::String(string) => serializer.serialize_str(string),
+      Value::Array(vals) => vals.serialize(serializer),
+      Value::Object(map) => {
+        let mut ser = serializer.serialize_map(Some(map.len()))?;
+        for (key, val) in map {
+          ser.serialize_entry(key, val)?;
+        }
+        ser.end()
+      }
+    }
+  }
+}
+
+impl<'de> Deserialize<'de> for Value {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Value, D::Error> {
+    deserializer.deserialize_any(ValueVisitor)
+  }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+  type Value = Value;
+
+  fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+    formatter.write_str("any valid value")
+  }
+
+  fn visit_bool<E>(self, bool: bool) -> Result<Value, E> {
+    Ok(Value::Bool(bool))
+  }
+
+  fn visit_i64<E>(self, int: i64) -> Result<Value, E> {
+    Ok(Value::from(int))
+  }
+
+  fn visit_u64<E>(self, int: u64) -> Result<Value, E> {
+    Ok(Value::from(int))
+  }
+
+  fn visit_i128<E: serde::de::Error>(self, int: i128) -> Result<Value, E> {
+    i64::try_from(int)
+      .map(Value::from)
+      .or_else(|_| u64::try_from(int).map(Value::from))
+      .map_err(|_| E::custom("number out of range"))
+  }
+
+  fn visit_u128<E: serde::de::Error>(self, int: u128) -> Result<Value, E> {
+    u64::try_from(int)
+      .map(Value::from)
+      .map_err(|_| E::custom("number out of range"))
+  }
+
+  fn visit_f64<E>(self, float: f64) -> Result<Value, E> {
+    Ok(Value::from(float))
+  }
+
+  fn visit_str<E>(self, string: &str) -> Result<Value, E> {
+    Ok(Value::from(string))
+  }
+
+  fn visit_string<E>(self, string: String) -> Result<Value, E> {
+    Ok(Value::String(string))
+  }
+
+  /// Bytes (eg. from CBOR / msgpack) become arrays of numbers, like when serializing.
+  fn visit_bytes<E>(self, bytes: &[u8]) -> Result<Value, E> {
+    Ok(Value::Array(
+      bytes.iter().map(|byte| Value::from(*byte)).collect(),
+    ))
+  }
+
+  fn visit_none<E>(self) -> Result<Value, E> {
+    Ok(Value::Null)
+  }
+
+  fn visit_unit<E>(self) -> Result<Value, E> {
+    Ok(Value::Null)
+  }
+
+  fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+    Deserialize::deserialize(deserializer)
+  }
+
+  fn visit_newtype_struct<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+    Deserialize::deserialize(deserializer)
+  }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+    let mut vals = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+    while let Some(val) = seq.next_element()? {
+      vals.push(val);
+    }
+    Ok(Value::Array(vals))
+  }
+
+  fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Value, A::Error> {
+    let mut map = Map::with_capacity(access.size_hint().unwrap_or(0));
+    while let Some((key, val)) = access.next_entry::<String, Value>()? {
+      // Keep the first position of duplicate keys, like serde_json's preserve_order.
+      match map.get_mut(&key) {
+        Some(existing) => *existing = val,
+        None => {
+          map.insert(key, val);
+        }
+      }
+    }
+    Ok(Value::Object(map))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde::Serialize;
+
+  use super::*;
+  use crate::{to_string, Options};
+
+  #[cfg(feature = "json")]
+  fn keys(val: &Value) -> Vec<&str> {
+    val
+      .as_object()
+      .unwrap()
+      .keys()
+      .map(String::as_str)
+      .collect()
+  }
+
+  #[test]
+  fn flatten_keeps_declaration_order() {
+    #[derive(Serialize)]
+    struct Config {
+      z: u8,
+      #[serde(flatten)]
+      inner: Inner,
+      a: u8,
+    }
+    #[derive(Serialize)]
+    struct Inner {
+      y: u8,
+      b: u8,
+    }
+    let config = Config {
+      z: 1,
+      inner: Inner { y: 2, b: 3 },
+      a: 4,
+    };
+    let res = to_string(&config, Options::default()).unwrap();
+    assert_eq!(res, "z = 1\ny = 2\nb = 3\na = 4");
+  }
+
+  #[cfg(feature = "json")]
+  #[test]
+  fn deserialize_keeps_order() {
+    let val: Value =
+      serde_json::from_str(r#"{"z": 1, "a": {"y": 2, "b": 3}, "m": 4, "z": 5}"#).unwrap();
+    // Duplicates keep their first position, with the last value.
+    assert_eq!(keys(&val), ["z", "a", "m"]);
+    assert_eq!(keys(&val["a"]), ["y", "b"]);
+    assert_eq!(val["z"], Value::from(5));
+  }
+
+  #[test]
+  fn objects_equal_regardless_of_order() {
+    let a = Map::from_iter([
+      (String::from("a"), Value::from(1)),
+      (String::from("b"), Value::from(2)),
+    ]);
+    let b = Map::from_iter([
+      (String::from("b"), Value::from(2)),
+      (String::from("a"), Value::from(1)),
+    ]);
+    assert_eq!(Value::from(a.clone()), Value::from(b));
+    let c = Map::from_iter([(String::from("a"), Value::from(1))]);
+    assert_ne!(Value::from(a), Value::from(c));
+    // Arrays are ordered.
+    assert_ne!(
+      Value::from(vec![Value::from(1), Value::from(2)]),
+      Value::from(vec![Value::from(2), Value::from(1)])
+    );
+  }
+
+  #[test]
+  fn indexing() {
+    let val = Value::from(Map::from_iter([(
+      String::from("servers"),
+      Value::from(vec![Value::from(Map::from_iter([(
+        String::from("port"),
+        Value::from(80),
+      )]))]),
+    )]));
+    assert_eq!(val["servers"][0]["port"].as_u64(), Some(80));
+    assert!(val["missing"].is_null());
+    assert!(val["servers"][1].is_null());
+    assert!(val["servers"]["port"].is_null());
+    assert!(val[0].is_null());
+  }
+
+  #[test]
+  fn number_conversions() {
+    assert_eq!(Value::from(-1i8).as_i64(), Some(-1));
+    assert_eq!(Value::from(-1i8).as_u64(), None);
+    assert_eq!(Value::from(1i64), Value::from(1u8));
+    assert_eq!(Value::from(u64::MAX).as_u64(), Some(u64::MAX));
+    assert_eq!(Value::from(u64::MAX).as_i64(), None);
+    assert_eq!(Value::from(i64::MIN).as_i64(), Some(i64::MIN));
+    assert_eq!(Value::from(3u16).as_f64(), Some(3.0));
+    assert_eq!(Value::from(1.5).as_f64(), Some(1.5));
+    assert_eq!(Value::from(1.5).as_i64(), None);
+    assert!(Value::from(f64::NAN).is_null());
+    assert!(Value::from(f64::INFINITY).is_null());
+    assert_eq!(Number::from_f64(f64::NEG_INFINITY), None);
+    assert!(Number::from_f64(1.0).unwrap().is_f64());
+    assert!(!Number::from(1).is_f64());
+    // Whole floats stay floats.
+    assert_eq!(Number::from_f64(1.0).unwrap().to_string(), "1.0");
+    assert_eq!(Number::from(-7).to_string(), "-7");
+  }
+
+  #[cfg(feature = "json")]
+  #[test]
+  fn deserialize_numbers() {
+    let val: Value =
+      serde_json::from_str("[18446744073709551615, -9223372036854775808, 1.0]").unwrap();
+    assert_eq!(val[0].as_u64(), Some(u64::MAX));
+    assert_eq!(val[1].as_i64(), Some(i64::MIN));
+    assert_eq!(val[2].as_f64(), Some(1.0));
+    assert!(ValueVisitor
+      .visit_i128::<serde::de::value::Error>(i128::MAX)
+      .is_err());
+    assert_eq!(
+      ValueVisitor
+        .visit_u128::<serde::de::value::Error>(7)
+        .unwrap(),
+      Value::from(7)
+    );
+    assert_eq!(
+      ValueVisitor
+        .visit_i128::<serde::de::value::Error>(u64::MAX as i128)
+        .unwrap(),
+      Value::from(u64::MAX)
+    );
+  }
+}
@@ -0,0 +1,993 @@
+use std::collections::HashMap;
+
+use crate::{join_path, CommentMap, Error, Map, Number, Result, Value};
+
+/// Parse YAML text, along with its comments, keyed by path like the [CommentMap] passed to
+/// [Options::comments](crate::Options::comments). Covers the YAML found in config files
+/// (eg. docker compose files): block and flow collections, plain / quoted / block scalars,
+/// anchors, aliases and `<<` merge keys. Tags are ignored, apart from `!!str`.
+/// Complex (`? `) keys and multiple documents aren't supported.
+pub(crate) fn parse(input: &str) -> Result<(Value, CommentMap)> {
+  let mut parser = Parser {
+    input: input.strip_prefix('\u{feff}').unwrap_or(input),
+    pos: 0,
+    comments: CommentMap::new(),
+    pending: Vec::new(),
+    anchors: HashMap::new(),
+  };
+  // Directives (eg. `%YAML 1.2`) only affect tags.
+  while parser.skip_blank_lines()? == Some(0) && parser.rest().starts_with('%') {
+    parser.skip_line();
+  }
+  if parser.skip_blank_lines()? == Some(0) && parser.at_marker("---") {
+    parser.pos += 3;
+  }
+  let mut val = parser.parse_node(-1, "", Context::Root)?;
+  if parser.skip_blank_lines()? == Some(0) && parser.at_marker("...") {
+    parser.pos += 3;
+    parser.end_of_line(None)?;
+  }
+  match parser.skip_blank_lines()? {
+    None => {}
+    Some(0) if parser.at_marker("---") => {
+      return Err(parser.error("multiple documents aren't supported"));
+    }
+    Some(indent) => {
+      parser.pos += indent;
+      return Err(parser.error("expected end of document"));
+    }
+  }
+  if val.is_null() {
+    val = Value::Object(Map::new());
+  }
+  Ok((val, parser.comments))
+}
+
+/// Where a node is, which decides whether a block collection may start on the same line.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Context {
+  Root,
+  /// After a mapping key's `:`.
+  MappingValue,
+  /// After a sequence entry's `-`.
+  SequenceEntry,
+}
+
+enum Entry {
+  Key(String, Value),
+  Merge(Vec<Map>),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Chomping {
+  /// Keep a single trailing line break.
+  Clip,
+  /// `-`, drop the trailing line breaks.
+  Strip,
+  /// `+`, keep all the trailing line breaks.
+  Keep,
+}
+
+struct Parser<'a> {
+  input: &'a str,
+  pos: usize,
+  comments: CommentMap,
+  /// Comment lines waiting for the next key / entry.
+  pending: Vec<String>,
+  anchors: HashMap<String, Value>,
+}
+
+impl<'a> Parser<'a> {
+  /// Parses the node after a mapping key's `:`, a sequence entry's `-` or the document start:
+  /// either on the rest of the line, or on the (more indented) lines below.
+  /// `indent` is the indentation of the collection containing the node.
+  /// Ends at the start of a line.
+  fn parse_node(&mut self, indent: isize, path: &str, context: Context) -> Result<Value> {
+    self.skip_inline_space();
+    let (anchor, tag) = self.parse_properties()?;
+    let val = if self.at_line_end() {
+      self.end_of_line(Some(path).filter(|path| !path.is_empty()))?;
+      self.parse_block_below(indent, path, context)?
+    } else {
+      let val = self.parse_inline(indent, path, context, tag)?;
+      if !self.at_line_start() {
+        self.end_of_line(Some(path).filter(|path| !path.is_empty()))?;
+      }
+      val
+    };
+    if let Some(anchor) = anchor {
+      self.anchors.insert(anchor, val.clone());
+    }
+    Ok(val)
+  }
+
+  /// Parses a node starting on a following line, more indented than its collection.
+  /// Sequences may also be at the same indentation as their mapping key.
+  fn parse_block_below(&mut self, indent: isize, path: &str, context: Context) -> Result<Value> {
+    let Some(column) = self.skip_blank_lines()? else {
+      return Ok(Value::Null);
+    };
+    if column == 0 && (self.at_marker("---") || self.at_marker("...")) {
+      return Ok(Value::Null);
+    }
+    let entry = is_sequence_entry(&self.rest()[column..]);
+    if column as isize > indent {
+      self.pos += column;
+      if entry {
+        self.parse_block_sequence(column, path)
+      } else if self.is_mapping_key()? {
+        self.parse_block_mapping(column, path)
+      } else {
+        self.parse_node(indent, path, Context::MappingValue)
+      }
+    } else if column as isize == indent && entry && context == Context::MappingValue {
+      self.pos += column;
+      self.parse_block_sequence(column, path)
+    } else {
+      Ok(Value::Null)
+    }
+  }
+
+  /// Parses a node starting on the current line.
+  fn parse_inline(
+    &mut self,
+    indent: isize,
+    path: &str,
+    context: Context,
+    tag: Option<&str>,
+  ) -> Result<Value> {
+    let column = self.column();
+    match self.peek() {
+      Some('|' | '>') => return self.parse_block_scalar(indent, path).map(Value::String),
+      Some('[' | '{') => return self.parse_flow_node(path),
+      Some('*') => return self.parse_alias(),
+      Some('-') if context != Context::MappingValue && is_sequence_entry(self.rest()) => {
+        return self.parse_block_sequence(column, path);
+      }
+      _ => {}
+    }
+    if context != Context::MappingValue && self.is_mapping_key()? {
+      return self.parse_block_mapping(column, path);
+    }
+    let val = match self.peek() {
+      Some(quote @ ('"' | '\'')) => Value::String(self.parse_quoted(quote)?),
+      _ => {
+        let text = self.parse_plain(indent)?;
+        if tag == Some("!!str") {
+          Value::String(text)
+        } else {
+          self.resolve(&text)?
+        }
+      }
+    };
+    self.skip_inline_space();
+    if self.peek() == Some(':') {
+      return Err(self.error("mapping values are not allowed here"));
+    }
+    Ok(val)
+  }
+
+  fn parse_block_mapping(&mut self, indent: usize, path: &str) -> Result<Value> {
+    let mut entries = Vec::new();
+    loop {
+      let Some(key) = self.mapping_key()? else {
+        return Err(self.error("expected a mapping key"));
+      };
+      if key == "<<" {
+        let val = self.parse_node(indent as isize, path, Context::MappingValue)?;
+        let maps = match val {
+          Value::Object(map) => vec![map],
+          Value::Array(vals) => vals
+            .into_iter()
+            .map(|val| match val {
+              Value::Object(map) => Ok(map),
+              _ => Err(self.error("<< merges need mappings")),
+            })
+            .collect::<Result<_>>()?,
+          _ => return Err(self.error("<< merges need a mapping or a list of mappings")),
+        };
+        entries.push(Entry::Merge(maps));
+      } else {
+        let key_path = join_path(path, &key);
+        self.take_pending(&key_path);
+        let val = self.parse_node(indent as isize, &key_path, Context::MappingValue)?;
+        entries.push(Entry::Key(key, val));
+      }
+      match self.skip_blank_lines()? {
+        Some(0) if self.at_marker("---") || self.at_marker("...") => break,
+        Some(column) if column == indent => self.pos += column,
+        Some(column) if column > indent => {
+          self.pos += column;
+          return Err(self.error("unexpected indentation"));
+        }
+        _ => break,
+      }
+    }
+    Ok(Value::Object(merge_entries(entries)))
+  }
+
+  fn parse_block_sequence(&mut self, indent: usize, path: &str) -> Result<Value> {
+    let mut vals = Vec::new();
+    loop {
+      // The `-`
+      self.pos += 1;
+      let elem_path = format!("{path}[{}]", vals.len());
+      self.take_pending(&elem_path);
+      vals.push(self.parse_node(indent as isize, &elem_path, Context::SequenceEntry)?);
+      match self.skip_blank_lines()? {
+        Some(0) if self.at_marker("---") || self.at_marker("...") => break,
+        Some(column) if column == indent && is_sequence_entry(&self.rest()[column..]) => {
+          self.pos += column;
+        }
+        Some(column) if column > indent => {
+          self.pos += column;
+          return Err(self.error("unexpected indentation"));
+        }
+        _ => break,
+      }
+    }
+    Ok(Value::Array(vals))
+  }
+
+  /// Whether the current line starts with a block mapping key.
+  fn is_mapping_key(&mut self) -> Result<bool> {
+    let start = self.pos;
+    let res = self.mapping_key()?.is_some();
+    self.pos = start;
+    Ok(res)
+  }
+
+  /// Parses a block mapping key and its `:`, or returns None (without moving) if there isn't one.
+  fn mapping_key(&mut self) -> Result<Option<String>> {
+    let start = self.pos;
+    let key = match self.peek() {
+      Some(quote @ ('"' | '\'')) => {
+        let key = self.parse_quoted(quote)?;
+        self.skip_inline_space();
+        key
+      }
+      None | Some('[' | '{' | '*' | '&' | '!' | '|' | '>' | '#' | '%' | '@' | '`') => {
+        return Ok(None);
+      }
+      Some('?') if is_separator(self.rest()[1..].chars().next()) => {
+        return Err(self.error("complex keys aren't supported"));
+      }
+      Some('-' | ':') if is_separator(self.rest()[1..].chars().next()) => return Ok(None),
+      _ => self.plain_line(false).to_string(),
+    };
+    if self.peek() == Some(':') && is_separator(self.rest()[1..].chars().next()) {
+      self.pos += 1;
+      Ok(Some(key))
+    } else {
+      self.pos = start;
+      Ok(None)
+    }
+  }
+
+  /// Parses the anchor (`&name`) and tag (eg. `!!str`) of a node, if any.
+  fn parse_properties(&mut self) -> Result<(Option<String>, Option<&'a str>)> {
+    let (mut anchor, mut tag) = (None, None);
+    loop {
+      match self.peek() {
+        Some('&') => {
+          self.pos += 1;
+          let name = self.parse_name();
+          if name.is_empty() {
+            return Err(self.error("expected anchor name"));
+          }
+          anchor = Some(name.to_string());
+        }
+        Some('!') => tag = Some(self.parse_name()),
+        _ => return Ok((anchor, tag)),
+      }
+      self.skip_inline_space();
+    }
+  }
+
+  fn parse_alias(&mut self) -> Result<Value> {
+    self.pos += 1;
+    let name = self.parse_name();
+    match self.anchors.get(name) {
+      Some(val) => Ok(val.clone()),
+      None => Err(self.error(format!("unknown alias *{name}"))),
+    }
+  }
+
+  /// An anchor / alias name or tag, up to whitespace or a flow indicator.
+  fn parse_name(&mut self) -> &'a str {
+    let input = self.input;
+    let start = self.pos;
+    let len = input[start..]
+      .find(|c: char| c.is_whitespace() || matches!(c, ',' | '[' | ']' | '{' | '}'))
+      .unwrap_or(input.len() - start);
+    self.pos += len;
+    &input[start..start + len]
+  }
+
+  /// A plain (unquoted) scalar in block context, which may continue on more indented lines.
+  fn parse_plain(&mut self, indent: isize) -> Result<String> {
+    let mut res = self.plain_line(false).to_string();
+    if res.is_empty() {
+      return Err(self.error("expected value"));
+    }
+    loop {
+      let start = self.pos;
+      self.skip_inline_space();
+      if !matches!(self.peek(), Some('\n' | '\r')) {
+        self.pos = start;
+        break;
+      }
+      self.skip_line();
+      let mut blank = 0;
+      let continues = loop {
+        let line = self.rest();
+        let column = line.len() - line.trim_start_matches(' ').len();
+        let content = line.trim_start_matches([' ', '\t']);
+        if content.starts_with(['\n', '\r']) {
+          blank += 1;
+          self.skip_line();
+          continue;
+        }
+        let marker = column == 0 && (self.at_marker("---") || self.at_marker("..."));
+        break !content.is_empty()
+          && !content.starts_with('#')
+          && column as isize > indent
+          && !marker;
+      };
+      if !continues {
+        self.pos = start;
+        break;
+      }
+      self.skip_inline_space();
+      // Line folding: a single line break becomes a space, and each blank line a line break.
+      if blank == 0 {
+        res.push(' ');
+      } else {
+        res.extend(std::iter::repeat_n('\n', blank));
+      }
+      res.push_str(self.plain_line(false));
+    }
+    Ok(res)
+  }
+
+  /// The rest of a plain scalar on the current line, without trailing whitespace.
+  /// In `flow` collections, it also ends at `,` and brackets.
+  fn plain_line(&mut self, flow: bool) -> &'a str {
+    let input = self.input;
+    let start = self.pos;
+    let mut end = start;
+    let mut after_space = false;
+    let mut chars = input[start..].char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+      let next = chars.peek().map(|(_, c)| *c);
+      match c {
+        '\n' | '\r' => break,
+        ':'
+          if is_separator(next) || (flow && matches!(next, Some(',' | '[' | ']' | '{' | '}'))) =>
+        {
+          break
+        }
+        '#' if after_space => break,
+        ',' | '[' | ']' | '{' | '}' if flow => break,
+        _ => {}
+      }
+      after_space = c == ' ' || c == '\t';
+      if !after_space {
+        end = start + i + c.len_utf8();
+      }
+    }
+    self.pos = end;
+    &input[start..end]
+  }
+
+  fn parse_quoted(&mut self, quote: char) -> Result<String> {
+    self.pos += 1;
+    let mut res = String::new();
+    loop {
+      let Some(c) = self.next_char() else {
+        return Err(self.error("unterminated string"));
+      };
+      match c {
+        '\'' if quote == '\'' && self.peek() == Some('\'') => {
+          self.pos += 1;
+          res.push('\'');
+        }
+        c if c == quote => return Ok(res),
+        '\\' if quote == '"' => match self.next_char() {
+          Some('n') => res.push('\n'),
+          Some('t' | '\t') => res.push('\t'),
+          Some('r') => res.push('\r'),
+          Some('0') => res.push('\0'),
+          Some('a') => res.push('\u{7}'),
+          Some('b') => res.push('\u{8}'),
+          Some('e') => res.push('\u{1b}'),
+          Some('f') => res.push('\u{c}'),
+          Some('v') => res.push('\u{b}'),
+          Some('N') => res.push('\u{85}'),
+          Some('_') => res.push('\u{a0}'),
+          Some('L') => res.push('\u{2028}'),
+          Some('P') => res.push('\u{2029}'),
+          Some('x') => res.push(self.parse_hex(2)?),
+          Some('u') => res.push(self.parse_hex(4)?),
+          Some('U') => res.push(self.parse_hex(8)?),
+          Some(c @ (' ' | '/' | '"' | '\\')) => res.push(c),
+          // An escaped line break joins the lines without a space.
+          Some('\n' | '\r') => {
+            self.eat('\n');
+            self.skip_inline_space();
+          }
+          _ => return Err(self.error("invalid escape")),
+        },
+        '\n' | '\r' => {
+          self.eat('\n');
+          // Line folding, as for plain scalars. Whitespace around the line break is dropped.
+          res.truncate(res.trim_end_matches([' ', '\t']).len());
+          let mut blank = 0;
+          loop {
+            self.skip_inline_space();
+            if self.eat('\r') || self.peek() == Some('\n') {
+              self.eat('\n');
+              blank += 1;
+            } else {
+              break;
+            }
+          }
+          if blank == 0 {
+            res.push(' ');
+          } else {
+            res.extend(std::iter::repeat_n('\n', blank));
+          }
+        }
+        c => res.push(c),
+      }
+    }
+  }
+
+  fn parse_hex(&mut self, len: usize) -> Result<char> {
+    let hex = self.rest().get(..len).unwrap_or_default();
+    let code = u32::from_str_radix(hex, 16).map_err(|_| self.error("invalid hex escape"))?;
+    self.pos += len;
+    char::from_u32(code).ok_or_else(|| self.error("invalid hex escape"))
+  }
+
+  /// A literal (`|`) or folded (`>`) block scalar. Ends at the start of a line.
+  fn parse_block_scalar(&mut self, indent: isize, path: &str) -> Result<String> {
+    let literal = self.next_char() == Some('|');
+    let mut chomping = Chomping::Clip;
+    let mut explicit = None;
+    loop {
+      match self.peek() {
+        Some('-') => chomping = Chomping::Strip,
+        Some('+') => chomping = Chomping::Keep,
+        Some(c @ '1'..='9') => explicit = Some(c as usize - '0' as usize),
+        _ => break,
+      }
+      self.pos += 1;
+    }
+    self.end_of_line(Some(path).filter(|path| !path.is_empty()))?;
+    let mut content_indent = explicit.map(|explicit| indent.max(0) as usize + explicit);
+    let mut lines = Vec::new();
+    let input = self.input;
+    while self.pos < input.len() {
+      let rest = &input[self.pos..];
+      let len = rest.find('\n').map_or(rest.len(), |i| i + 1);
+      let line = rest[..len].trim_end_matches('\n').trim_end_matches('\r');
+      let column = line.len() - line.trim_start_matches(' ').len();
+      if column == line.len() {
+        lines.push(
+          line
+            .get(content_indent.unwrap_or(usize::MAX)..)
+            .unwrap_or_default(),
+        );
+        self.pos += len;
+        continue;
+      }
+      let content_indent = match content_indent {
+        Some(content_indent) => content_indent,
+        None if column as isize > indent => *content_indent.insert(column),
+        None => break,
+      };
+      if column < content_indent
+        || (column == 0 && (self.at_marker("---") || self.at_marker("...")))
+      {
+        break;
+      }
+      lines.push(&line[content_indent..]);
+      self.pos += len;
+    }
+    let trailing = lines
+      .iter()
+      .rev()
+      .take_while(|line| line.is_empty())
+      .count();
+    let lines = &lines[..lines.len() - trailing];
+    let mut res = if literal {
+      lines.join("\n")
+    } else {
+      fold_lines(lines)
+    };
+    match chomping {
+      Chomping::Strip => {}
+      Chomping::Clip if lines.is_empty() => {}
+      Chomping::Clip => res.push('\n'),
+      Chomping::Keep => {
+        let breaks = trailing + usize::from(!lines.is_empty());
+        res.extend(std::iter::repeat_n('\n', breaks));
+      }
+    }
+    Ok(res)
+  }
+
+  fn parse_flow_node(&mut self, path: &str) -> Result<Value> {
+    let (anchor, tag) = self.parse_properties()?;
+    let val = match self.peek() {
+      Some('[') => self.parse_flow_sequence(path)?,
+      Some('{') => self.parse_flow_mapping(path)?,
+      Some('*') => self.parse_alias()?,
+      Some(quote @ ('"' | '\'')) => Value::String(self.parse_quoted(quote)?),
+      _ => {
+        let text = self.plain_line(true);
+        if text.is_empty() {
+          return Err(self.error("expected value"));
+        }
+        if tag == Some("!!str") {
+          Value::String(text.to_string())
+        } else {
+          self.resolve(text)?
+        }
+      }
+    };
+    if let Some(anchor) = anchor {
+      self.anchors.insert(anchor, val.clone());
+    }
+    Ok(val)
+  }
+
+  fn parse_flow_sequence(&mut self, path: &str) -> Result<Value> {
+    self.pos += 1;
+    let mut vals = Vec::new();
+    loop {
+      self.skip_flow_blank();
+      if self.eat(']') {
+        self.pending.clear();
+        return Ok(Value::Array(vals));
+      }
+      let elem_path = format!("{path}[{}]", vals.len());
+      self.take_pending(&elem_path);
+      vals.push(self.parse_flow_node(&elem_path)?);
+      self.skip_flow_blank();
+      if self.eat(']') {
+        self.pending.clear();
+        return Ok(Value::Array(vals));
+      }
+      if !self.eat(',') {
+        return Err(self.error("expected ',' or ']'"));
+      }
+    }
+  }
+
+  fn parse_flow_mapping(&mut self, path: &str) -> Result<Value> {
+    self.pos += 1;
+    let mut map = Map::new();
+    loop {
+      self.skip_flow_blank();
+      if self.eat('}') {
+        self.pending.clear();
+        return Ok(Value::Object(map));
+      }
+      let key = match self.peek() {
+        Some(quote @ ('"' | '\'')) => self.parse_quoted(quote)?,
+        _ => self.plain_line(true).to_string(),
+      };
+      if key.is_empty() {
+        return Err(self.error("expected key"));
+      }
+      let key_path = join_path(path, &key);
+      self.take_pending(&key_path);
+      self.skip_flow_blank();
+      let val = if self.eat(':') {
+        self.skip_flow_blank();
+        if matches!(self.peek(), Some(',' | '}')) {
+          Value::Null
+        } else {
+          self.parse_flow_node(&key_path)?
+        }
+      } else {
+        Value::Null
+      };
+      // Duplicate keys keep their first position, like the other inputs.
+      match map.get_mut(&key) {
+        Some(existing) => *existing = val,
+        None => {
+          map.insert(key, val);
+        }
+      }
+      self.skip_flow_blank();
+      if self.eat('}') {
+        self.pending.clear();
+        return Ok(Value::Object(map));
+      }
+      if !self.eat(',') {
+        return Err(self.error("expected ',' or '}'"));
+      }
+    }
+  }
+
+  /// The value of a plain scalar, per the YAML 1.2 core schema.
+  fn resolve(&self, text: &str) -> Result<Value> {
+    let unsigned = text.strip_prefix(['-', '+']).unwrap_or(text);
+    let val = match text {
+      "" | "~" | "null" | "Null" | "NULL" => Value::Null,
+      "true" | "True" | "TRUE" => Value::Bool(true),
+      "false" | "False" | "FALSE" => Value::Bool(false),
+      _ if matches!(unsigned, ".inf" | ".Inf" | ".INF")
+        || matches!(text, ".nan" | ".NaN" | ".NAN") =>
+      {
+        // The toml spelling, eg. `-.Inf` is `-inf`.
+        let special = text.replacen('.', "", 1).to_ascii_lowercase();
+        let number = Number::special(&special);
+        Value::Number(number.ok_or_else(|| self.error(format!("{text} can't be represented")))?)
+      }
+      _ if !unsigned.is_empty() && unsigned.bytes().all(|c| c.is_ascii_digit()) => {
+        let int = if text.starts_with('-') {
+          text.parse::<i64>().ok().map(Number::from)
+        } else {
+          unsigned.parse::<u64>().ok().map(Number::from)
+        };
+        match int {
+          Some(int) => Value::Number(int),
+          // Too big for 64 bits.
+          None => self.float(text)?,
+        }
+      }
+      _ => {
+        let radix = [("0o", 8), ("0x", 16)]
+          .into_iter()
+          .find_map(|(prefix, radix)| Some((text.strip_prefix(prefix)?, radix)));
+        match radix {
+          Some((digits, radix)) if !digits.is_empty() => match i64::from_str_radix(digits, radix) {
+            Ok(int) => Value::from(int),
+            Err(_) => Value::String(text.to_string()),
+          },
+          _ if is_float(unsigned) => self.float(text)?,
+          _ => Value::String(text.to_string()),
+        }
+      }
+    };
+    Ok(val)
+  }
+
+  fn float(&self, text: &str) -> Result<Value> {
+    match text.parse::<f64>() {
+      Ok(float) if float.is_finite() => Ok(Value::from(float)),
+      _ => Err(self.error(format!("{text} can't be represented"))),
+    }
+  }
+
+  /// From the start of a line, skips blank and comment lines, collecting the comments into `pending`.
+  /// Returns the indentation of the next line with content (without moving past it),
+  /// or None at the end of the input.
+  fn skip_blank_lines(&mut self) -> Result<Option<usize>> {
+    loop {
+      let line = self.rest();
+      let column = line.len() - line.trim_start_matches(' ').len();
+      let content = line[column..].trim_start_matches([' ', '\t']);
+      match content.chars().next() {
+        None => return Ok(None),
+        Some('\n' | '\r') => self.skip_line(),
+        Some('#') => {
+          self.pos += line.len() - content.len();
+          self.read_comment();
+          self.skip_line();
+        }
+        Some(_) if line[column..].starts_with('\t') => {
+          self.pos += column;
+          return Err(self.error("tabs can't be used for indentation"));
+        }
+        Some(_) => return Ok(Some(column)),
+      }
+    }
+  }
+
+  /// Skips whitespace, line breaks and comments inside flow collections.
+  fn skip_flow_blank(&mut self) {
+    loop {
+      match self.peek() {
+        Some(' ' | '\t' | '\n' | '\r') => self.pos += 1,
+        Some('#') => self.read_comment(),
+        _ => return,
+      }
+    }
+  }
+
+  /// Skips the rest of the line after a node, which may only be a comment on the node at `path`.
+  fn end_of_line(&mut self, path: Option<&str>) -> Result<()> {
+    self.skip_inline_space();
+    if self.peek() == Some('#') {
+      let before = self.pending.len();
+      self.read_comment();
+      if let Some(path) = path {
+        let comment = self.pending.split_off(before).join("\n");
+        match self.comments.get_mut(path) {
+          Some(existing) => {
+            existing.push('\n');
+            existing.push_str(&comment);
+          }
+          None => {
+            self.comments.insert(path.to_string(), comment);
+          }
+        }
+      }
+    }
+    match self.peek() {
+      None => Ok(()),
+      Some('\n' | '\r') => {
+        self.skip_line();
+        Ok(())
+      }
+      Some(_) => Err(self.error("expected end of line")),
+    }
+  }
+
+  /// Reads the comment at `#` into `pending`, up to the end of the line.
+  fn read_comment(&mut self) {
+    let rest = &self.rest()[1..];
+    let len = rest.find('\n').unwrap_or(rest.len());
+    let line = rest[..len].trim_end_matches('\r');
+    let line = line
+      .strip_prefix(' ')
+      .unwrap_or(line)
+      .trim_end()
+      .to_string();
+    self.pending.push(line);
+    self.pos += 1 + len;
+  }
+
+  fn take_pending(&mut self, path: &str) {
+    if !self.pending.is_empty() {
+      let comment = std::mem::take(&mut self.pending).join("\n");
+      self.comments.insert(path.to_string(), comment);
+    }
+  }
+
+  /// Whether the content at the current position ends the line.
+  fn at_line_end(&self) -> bool {
+    matches!(self.peek(), None | Some('\n' | '\r' | '#'))
+  }
+
+  fn at_line_start(&self) -> bool {
+    self.pos == 0 || self.input.as_bytes()[self.pos - 1] == b'\n'
+  }
+
+  /// Whether a document marker (`---` / `...`) starts at the current position.
+  fn at_marker(&self, marker: &str) -> bool {
+    match self.rest().strip_prefix(marker) {
+      Some(rest) => self.at_line_start() && is_separator(rest.chars().next()),
+      None => false,
+    }
+  }
+
+  fn column(&self) -> usize {
+    self.pos - self.input[..self.pos].rfind('\n').map_or(0, |i| i + 1)
+  }
+
+  fn skip_inline_space(&mut self) {
+    while let Some(' ' | '\t') = self.peek() {
+      self.pos += 1;
+    }
+  }
+
+  /// Moves past the next line break, or to the end of the input.
+  fn skip_line(&mut self) {
+    self.pos += self.rest().find('\n').map_or(self.rest().len(), |i| i + 1);
+  }
+
+  fn eat(&mut self, c: char) -> bool {
+    if self.peek() == Some(c) {
+      self.pos += c.len_utf8();
+      true
+    } else {
+      false
+    }
+  }
+
+  fn rest(&self) -> &'a str {
+    &self.input[self.pos..]
+  }
+
+  fn peek(&self) -> Option<char> {
+    self.rest().chars().next()
+  }
+
+  fn next_char(&mut self) -> Option<char> {
+    let c = self.peek()?;
+    self.pos += c.len_utf8();
+    Some(c)
+  }
+
+  fn error(&self, message: impl Into<String>) -> Error {
+    Error::ParseYaml {
+      line: self.input[..self.pos].matches('\n').count() + 1,
+      message: message.into(),
+    }
+  }
+}
+
+/// Explicit keys take precedence over merged ones, which take precedence in the order they're listed.
+/// Merged keys go where the `<<` was.
+fn merge_entries(entries: Vec<Entry>) -> Map {
+  let explicit = entries
+    .iter()
+    .filter_map(|entry| match entry {
+      Entry::Key(key, _) => Some(key.clone()),
+      Entry::Merge(_) => None,
+    })
+    .collect::<std::collections::HashSet<_>>();
+  let mut map = Map::new();
+  for entry in entries {
+    match entry {
+      // Duplicate keys keep their first position, like the other inputs.
+      Entry::Key(key, val) => match map.get_mut(&key) {
+        Some(existing) => *existing = val,
+        None => {
+          map.insert(key, val);
+        }
+      },
+      Entry::Merge(maps) => {
+        for (key, val) in maps.into_iter().flatten() {
+          if !explicit.contains(&key) && !map.contains_key(&key) {
+            map.insert(key, val);
+          }
+        }
+      }
+    }
+  }
+  map
+}
+
+/// Folds the lines of a `>` block scalar: line breaks between lines become spaces,
+/// apart from around blank and more indented lines.
+fn fold_lines(lines: &[&str]) -> String {
+  let mut res = String::new();
+  // Whether the previous line with content was more indented.
+  let mut prev = None;
+  let mut blank = 0;
+  for line in lines {
+    if line.is_empty() {
+      blank += 1;
+      continue;
+    }
+    let indented = line.starts_with([' ', '\t']);
+    match prev {
+      Some(false) if !indented && blank == 0 => res.push(' '),
+      Some(false) if !indented => {}
+      Some(_) => res.push('\n'),
+      None => {}
+    }
+    res.extend(std::iter::repeat_n('\n', blank));
+    res.push_str(line);
+    prev = Some(indented);
+    blank = 0;
+  }
+  res
+}
+
+fn is_sequence_entry(line: &str) -> bool {
+  match line.strip_prefix('-') {
+    Some(rest) => is_separator(rest.chars().next()),
+    None => false,
+  }
+}
+
+fn is_separator(next: Option<char>) -> bool {
+  matches!(next, None | Some(' ' | '\t' | '\n' | '\r'))
+}
+
+/// Whether the text is a float per the YAML 1.2 core schema (without the sign),
+/// eg. `1.5`, `.5`, `1e3`.
+fn is_float(text: &str) -> bool {
+  let digits = |text: &str| text.bytes().all(|c| c.is_ascii_digit());
+  let (mantissa, exponent) = match text.split_once(['e', 'E']) {
+    Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+    None => (text, None),
+  };
+  let (int, fraction) = match mantissa.split_once('.') {
+    Some((int, fraction)) => (int, Some(fraction)),
+    None => (mantissa, None),
+  };
+  let mantissa = digits(int)
+    && fraction.is_none_or(digits)
+    && (!int.is_empty() || fraction.is_some_and(|fraction| !fraction.is_empty()));
+  let exponent = exponent.is_none_or(|exponent| {
+    let exponent = exponent.strip_prefix(['-', '+']).unwrap_or(exponent);
+    !exponent.is_empty() && digits(exponent)
+  });
+  mantissa && exponent
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn scalar(text: &str) -> Result<Value> {
+    parse(&format!("a: {text}\n")).map(|(val, _)| val["a"].clone())
+  }
+
+  #[test]
+  fn scalars() {
+    for (text, expected) in [
+      ("~", Value::Null),
+      ("", Value::Null),
+      ("True", Value::Bool(true)),
+      ("12", Value::from(12)),
+      ("-3", Value::from(-3)),
+      ("0x1f", Value::from(31)),
+      ("0o17", Value::from(15)),
+      ("1.5", Value::from(1.5)),
+      ("1e3", Value::from(1000.0)),
+      ("18446744073709551615", Value::from(u64::MAX)),
+      ("'12'", Value::from("12")),
+      ("\"a\\tb\"", Value::from("a\tb")),
+      ("hello world", Value::from("hello world")),
+      ("yes", Value::from("yes")),
+    ] {
+      assert_eq!(scalar(text).unwrap(), expected, "{text}");
+    }
+  }
+
+  #[test]
+  fn special_floats() {
+    for (text, expected) in [
+      (".inf", "inf"),
+      ("+.Inf", "inf"),
+      ("-.INF", "-inf"),
+      (".nan", "nan"),
+      (".NaN", "nan"),
+    ] {
+      let Value::Number(number) = scalar(text).unwrap() else {
+        panic!("{text} isn't a number");
+      };
+      assert!(!number.as_f64().unwrap().is_finite(), "{text}");
+      assert_eq!(number.to_string(), expected);
+    }
+    // Not special floats in the core schema.
+    assert_eq!(scalar("inf").unwrap(), Value::from("inf"));
+    assert_eq!(scalar("-.nan").unwrap(), Value::from("-.nan"));
+  }
+
+  #[test]
+  fn collections() {
+    let (val, comments) = parse(
+      "# the services\nservices:\n  web:\n    ports: [80, 443]\n    env:\n      - A=1\n      - B=2\n  db: {}\n",
+    )
+    .unwrap();
+    let web = &val["services"]["web"];
+    assert_eq!(web["ports"][1].as_u64(), Some(443));
+    assert_eq!(web["env"][0].as_str(), Some("A=1"));
+    assert_eq!(val["services"]["db"], Value::Object(Map::new()));
+    assert!(comments.contains_key("services"));
+  }
+
+  #[test]
+  fn block_scalars() {
+    let (val, _) = parse("a: |\n  one\n  two\nb: >-\n  one\n  two\n").unwrap();
+    assert_eq!(val["a"].as_str(), Some("one\ntwo\n"));
+    assert_eq!(val["b"].as_str(), Some("one two"));
+  }
+
+  #[test]
+  fn anchors_and_merge_keys() {
+    let (val, _) = parse("base: &base\n  a: 1\n  b: 2\nderived:\n  <<: *base\n  b: 3\n").unwrap();
+    assert_eq!(val["derived"]["a"].as_u64(), Some(1));
+    assert_eq!(val["derived"]["b"].as_u64(), Some(3));
+    assert!(parse("a: *missing\n").is_err());
+  }
+
+  #[test]
+  fn documents() {
+    assert_eq!(parse("").unwrap().0, Value::Object(Map::new()));
+    assert!(parse("---\na: 1\n...\n").is_ok());
+    assert!(parse("a: 1\n---\nb: 2\n").is_err());
+    assert!(parse("a: 1\n  b: 2\n").is_err());
+  }
+}
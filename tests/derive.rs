@@ -0,0 +1,85 @@
+#![cfg(feature = "derive")]
+
+use serde::Serialize;
+use toml_pretty::{Deprecated, Examples, Options, Secrets};
+
+#[derive(Serialize, Secrets, Examples)]
+#[serde(rename_all = "camelCase")]
+struct Config {
+  #[toml_pretty(example = "\"my-app\"")]
+  name: String,
+  #[toml_pretty(secret)]
+  api_key: String,
+  #[toml_pretty(nested)]
+  databases: Vec<Database>,
+}
+
+#[derive(Serialize, Default, Examples)]
+struct Server {
+  #[toml_pretty(example = "\"localhost\"")]
+  host: String,
+  #[toml_pretty(example = "8080")]
+  port: u16,
+  #[toml_pretty(nested)]
+  tls: Option<Tls>,
+}
+
+#[derive(Serialize, Default, Examples)]
+struct Tls {
+  #[toml_pretty(example = "\"cert.pem\"")]
+  cert: String,
+}
+
+#[derive(Serialize, Deprecated, Secrets)]
+struct Database {
+  host: String,
+  #[toml_pretty(secret, deprecated = "use a password file")]
+  password: String,
+}
+
+#[test]
+fn secrets() {
+  assert_eq!(Config::secrets(), ["apiKey", "databases.password"]);
+  assert_eq!(
+    Database::deprecated().get("password").map(String::as_str),
+    Some("use a password file")
+  );
+  let config = Config {
+    name: String::from("app"),
+    api_key: String::from("key"),
+    databases: vec![Database {
+      host: String::from("db"),
+      password: String::from("hunter2"),
+    }],
+  };
+  let secrets = Config::secrets();
+  let secrets = secrets.iter().map(String::as_str).collect::<Vec<_>>();
+  let options = Options::default().secrets(&secrets).redact_secrets(true);
+  let res = toml_pretty::to_string(&config, options).unwrap();
+  assert!(!res.contains("key\"") && !res.contains("hunter2"), "{res}");
+  assert!(res.contains("host = \"db\""), "{res}");
+}
+
+#[test]
+fn examples() {
+  assert_eq!(
+    Server::examples(),
+    [
+      (String::from("host"), String::from("\"localhost\"")),
+      (String::from("port"), String::from("8080")),
+      (String::from("tls.cert"), String::from("\"cert.pem\"")),
+    ]
+  );
+  let examples = Server::examples();
+  let examples = examples
+    .iter()
+    .map(|(path, example)| (path.as_str(), example.as_str()))
+    .collect::<Vec<_>>();
+  let options = Options::default().scaffold(true).examples(&examples);
+  let res = toml_pretty::to_string(&Server::default(), options).unwrap();
+  assert_eq!(
+    res,
+    "host = \"localhost\"\nport = 8080\ntls.cert = \"cert.pem\""
+  );
+  assert_eq!(Config::examples()[0].0, "name");
+}